@@ -1,29 +1,33 @@
-use ae_rs::{Decoder, Encoder};
-use criterion::{black_box, criterion_group, criterion_main, Bencher, Criterion, Throughput};
-use rand::Rng;
+use ae_rs::{
+    AdaptiveModel, BinaryDecoder, BinaryEncoder, Decoder, Encoder, RangeDecoder, RangeEncoder,
+    RunModel, SymbolTable,
+};
+use criterion::{criterion_group, criterion_main, Bencher, Criterion, Throughput};
+use rand::RngExt;
+use std::hint::black_box;
 use std::io::Cursor;
 
 fn bench_encode<const SIZE: usize>(b: &mut Bencher, src: &[usize; SIZE]) {
-    let mut output = Vec::new();
-    output.reserve(SIZE * 8);
+    let mut output = Vec::with_capacity(SIZE * 8);
     let mut src2 = [0usize; SIZE];
     for (i, &x) in src.iter().enumerate() {
         src2[i] = black_box(x);
     }
 
     b.iter(move || {
-        let mut encoder = Encoder::new(&mut output);
-        for &x in src {
-            encoder.encode_next(x).unwrap();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            for &x in src {
+                encoder.encode_next(x).unwrap();
+            }
+            encoder.encode_end().unwrap();
         }
-        encoder.encode_end().unwrap();
         black_box(output[output.len() - 1]);
     });
 }
 
 fn bench_decode<const SIZE: usize>(b: &mut Bencher, src: &[usize; SIZE]) {
-    let mut output = Vec::new();
-    output.reserve(SIZE * 8);
+    let mut output = Vec::with_capacity(SIZE * 8);
     let mut src2 = [0usize; SIZE];
     for (i, &x) in src.iter().enumerate() {
         src2[i] = black_box(x);
@@ -34,6 +38,7 @@ fn bench_decode<const SIZE: usize>(b: &mut Bencher, src: &[usize; SIZE]) {
         for x in src2 {
             encoder.encode_next(x).unwrap();
         }
+        encoder.encode_end().unwrap();
     }
     b.iter(move || {
         let mut cursor = Cursor::new(&output);
@@ -45,28 +50,388 @@ fn bench_decode<const SIZE: usize>(b: &mut Bencher, src: &[usize; SIZE]) {
     });
 }
 
+fn bench_range_encode<const SIZE: usize>(b: &mut Bencher, src: &[usize; SIZE]) {
+    let mut output = Vec::with_capacity(SIZE * 8);
+
+    b.iter(move || {
+        let mut encoder = RangeEncoder::new(&mut output);
+        for &x in src {
+            encoder.encode_next(black_box(x)).unwrap();
+        }
+        encoder.encode_end().unwrap();
+        black_box(output[output.len() - 1]);
+    });
+}
+
+fn bench_range_decode<const SIZE: usize>(b: &mut Bencher, src: &[usize; SIZE]) {
+    let mut output = Vec::with_capacity(SIZE * 8);
+
+    {
+        let mut encoder = RangeEncoder::new(&mut output);
+        for &x in src {
+            encoder.encode_next(x).unwrap();
+        }
+        encoder.encode_end().unwrap();
+    }
+    b.iter(move || {
+        let mut cursor = Cursor::new(&output);
+        let mut decoder = RangeDecoder::new(&mut cursor).unwrap();
+        for _ in 0..SIZE {
+            decoder.decode_next().unwrap();
+        }
+        black_box(decoder.decode_next().unwrap());
+    });
+}
+
+/// Deterministic pseudo-random bytes, so the corpora and the ratios in the benchmark names stay
+/// the same from run to run.
+fn xorshift(state: &mut u64) -> u8 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    (*state >> 32) as u8
+}
+
+/// Pseudo-English: words drawn with a skewed distribution, separated by spaces and the odd newline.
+fn text_corpus(size: usize) -> Vec<u8> {
+    let words = "the of and to in a is that for it as was with be by compression"
+        .split(' ')
+        .collect::<Vec<_>>();
+
+    let mut state = 0x9E3779B97F4A7C15;
+    let mut output = Vec::with_capacity(size + 16);
+    while output.len() < size {
+        // Squaring a uniform pick favours the first words, roughly like natural text does.
+        let pick = xorshift(&mut state) as usize % words.len();
+        output.extend_from_slice(words[pick * pick / words.len()].as_bytes());
+        output.push(if xorshift(&mut state) < 16 {
+            b'\n'
+        } else {
+            b' '
+        });
+    }
+    output.truncate(size);
+
+    output
+}
+
+/// Fixed-size binary records: an increasing little endian id, a small enum byte and a mostly-zero
+/// flags byte, the kind of thing a log or table dump is made of.
+fn structured_corpus(size: usize) -> Vec<u8> {
+    let mut state = 0x2545F4914F6CDD1D;
+    let mut output = Vec::with_capacity(size + 8);
+    let mut id = 0u32;
+    while output.len() < size {
+        output.extend_from_slice(&id.to_le_bytes());
+        output.push(xorshift(&mut state) % 4);
+        output.push(if xorshift(&mut state) < 8 { 1 } else { 0 });
+        id += 1;
+    }
+    output.truncate(size);
+
+    output
+}
+
+type ModelFactory = Box<dyn Fn() -> SymbolTable>;
+
+fn encode_with(input: &[u8], model: SymbolTable, output: &mut Vec<u8>) {
+    let mut encoder = Encoder::with_model(output, model);
+    encoder.encode_slice(input).unwrap();
+    encoder.encode_end().unwrap();
+}
+
+fn bench_model_encode(b: &mut Bencher, input: &[u8], model: &dyn Fn() -> SymbolTable) {
+    let mut output = Vec::with_capacity(input.len() * 2);
+
+    b.iter(|| {
+        output.clear();
+        encode_with(black_box(input), model(), &mut output);
+        black_box(output[output.len() - 1]);
+    });
+}
+
+fn bench_model_decode(b: &mut Bencher, input: &[u8], model: &dyn Fn() -> SymbolTable) {
+    let mut output = Vec::new();
+    encode_with(input, model(), &mut output);
+
+    b.iter(|| {
+        let mut cursor = Cursor::new(&output);
+        let mut decoder = Decoder::with_model(&mut cursor, model()).unwrap();
+        black_box(decoder.decode_to_vec().unwrap());
+    });
+}
+
+/// Compares the model variants on realistic inputs. The compression ratio is part of every benchmark
+/// name, so a change to the model shows up in the report next to the throughput.
+fn model_benchmark(c: &mut Criterion) {
+    const SIZE: usize = 64 * 1024;
+
+    let mut g = c.benchmark_group("models");
+    g.throughput(Throughput::Bytes(SIZE as u64));
+
+    for (corpus, input) in [
+        ("text", text_corpus(SIZE)),
+        ("structured", structured_corpus(SIZE)),
+    ] {
+        let mut counts = [0; 256];
+        for &b in &input {
+            counts[b as usize] += 1;
+        }
+
+        let variants: [(&str, ModelFactory); 4] = [
+            ("adaptive", Box::new(SymbolTable::new)),
+            (
+                "decaying",
+                Box::new(|| AdaptiveModel::with_decay(4096, 1, 2).unwrap().build()),
+            ),
+            (
+                "increment 8",
+                Box::new(|| AdaptiveModel::new().with_increment(8).unwrap().build()),
+            ),
+            (
+                "static",
+                Box::new(move || SymbolTable::from_counts(&counts, true).unwrap()),
+            ),
+        ];
+
+        for (variant, model) in &variants {
+            let mut output = Vec::new();
+            encode_with(&input, model(), &mut output);
+            let ratio = output.len() as f64 / input.len() as f64;
+
+            g.bench_function(
+                format!("Encode {corpus} 64KB, {variant} model (ratio {ratio:.3})"),
+                |b| bench_model_encode(b, &input, model),
+            );
+            g.bench_function(
+                format!("Decode {corpus} 64KB, {variant} model (ratio {ratio:.3})"),
+                |b| bench_model_decode(b, &input, model),
+            );
+        }
+    }
+
+    g.finish();
+}
+
+/// Encoding into and decoding from memory through [`Encoder::new_vec`] and [`Decoder::from_slice`],
+/// next to the same work through a borrowed `Vec` and a `Cursor`. The two decodes stay within a few
+/// percent of each other: fetching bytes is a small part of decoding either way.
+fn in_memory_benchmark(c: &mut Criterion) {
+    const SIZE: usize = 64 * 1024;
+
+    let mut g = c.benchmark_group("in memory");
+    g.throughput(Throughput::Bytes(SIZE as u64));
+
+    let input = text_corpus(SIZE);
+    let mut output = Vec::new();
+    encode_with(&input, SymbolTable::new(), &mut output);
+
+    g.bench_function("Encode text 64KB, new_vec", |b| {
+        b.iter(|| {
+            let mut encoder = Encoder::new_vec();
+            encoder.encode_slice(black_box(&input)).unwrap();
+            black_box(encoder.into_inner().unwrap())
+        })
+    });
+    g.bench_function("Encode text 64KB, borrowed Vec", |b| {
+        b.iter(|| {
+            let mut output = Vec::new();
+            encode_with(black_box(&input), SymbolTable::new(), &mut output);
+            black_box(output)
+        })
+    });
+    g.bench_function("Decode text 64KB, from_slice", |b| {
+        b.iter(|| {
+            let mut decoder = Decoder::from_slice(black_box(&output)).unwrap();
+            black_box(decoder.decode_to_vec().unwrap())
+        })
+    });
+    g.bench_function("Decode text 64KB, Cursor", |b| {
+        b.iter(|| {
+            let mut cursor = Cursor::new(black_box(&output));
+            let mut decoder = Decoder::new(&mut cursor).unwrap();
+            black_box(decoder.decode_to_vec().unwrap())
+        })
+    });
+
+    g.finish();
+}
+
+/// A biased bit stream through [`BinaryEncoder`], next to the same bits coded one byte symbol each
+/// through the 257-symbol table.
+fn binary_benchmark(c: &mut Criterion) {
+    const BITS: usize = 512 * 1024;
+
+    let mut g = c.benchmark_group("binary");
+    g.throughput(Throughput::Elements(BITS as u64));
+
+    // About one bit in eight set.
+    let mut state = 0x9E3779B97F4A7C15;
+    let bits = (0..BITS)
+        .map(|_| xorshift(&mut state) < 32)
+        .collect::<Vec<_>>();
+
+    let mut output = Vec::new();
+    {
+        let mut encoder = BinaryEncoder::new(&mut output);
+        for &bit in &bits {
+            encoder.encode_bit(bit).unwrap();
+        }
+        encoder.encode_end().unwrap();
+    }
+
+    g.bench_function("Encode 512K biased bits, binary", |b| {
+        b.iter(|| {
+            let mut output = Vec::new();
+            {
+                let mut encoder = BinaryEncoder::new(&mut output);
+                for &bit in black_box(&bits) {
+                    encoder.encode_bit(bit).unwrap();
+                }
+                encoder.encode_end().unwrap();
+            }
+            black_box(output)
+        })
+    });
+    g.bench_function("Encode 512K biased bits, byte symbols", |b| {
+        b.iter(|| {
+            let mut output = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut output);
+                encoder
+                    .encode_iter(black_box(&bits).iter().map(|&bit| bit as u8))
+                    .unwrap();
+                encoder.encode_end().unwrap();
+            }
+            black_box(output)
+        })
+    });
+    g.bench_function("Decode 512K biased bits, binary", |b| {
+        b.iter(|| {
+            let mut reader = black_box(&output[..]);
+            let mut decoder = BinaryDecoder::new(&mut reader).unwrap();
+            for _ in 0..BITS {
+                black_box(decoder.decode_bit().unwrap());
+            }
+        })
+    });
+
+    g.finish();
+}
+
+/// Data made of runs of random bytes through the run-length mode, next to the same data coded one
+/// symbol per byte.
+fn runs_benchmark(c: &mut Criterion) {
+    const SIZE: usize = 64 * 1024;
+
+    let mut g = c.benchmark_group("runs");
+    g.throughput(Throughput::Bytes(SIZE as u64));
+
+    let mut state = 0x9E3779B97F4A7C15;
+    let mut input = Vec::with_capacity(SIZE);
+    while input.len() < SIZE {
+        let b = xorshift(&mut state);
+        let run = xorshift(&mut state) as usize % 64 + 1;
+        input.extend(std::iter::repeat_n(b, run.min(SIZE - input.len())));
+    }
+
+    let mut output = Vec::new();
+    {
+        let mut encoder = Encoder::new(&mut output);
+        let mut model = RunModel::new();
+        encoder.encode_runs(&mut model, &input).unwrap();
+        encoder.encode_runs_end(&mut model).unwrap();
+    }
+
+    g.bench_function("Encode runs 64KB, run-length mode", |b| {
+        b.iter(|| {
+            let mut output = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut output);
+                let mut model = RunModel::new();
+                encoder.encode_runs(&mut model, black_box(&input)).unwrap();
+                encoder.encode_runs_end(&mut model).unwrap();
+            }
+            black_box(output)
+        })
+    });
+    g.bench_function("Encode runs 64KB, byte symbols", |b| {
+        b.iter(|| {
+            let mut output = Vec::new();
+            encode_with(black_box(&input), SymbolTable::new(), &mut output);
+            black_box(output)
+        })
+    });
+    g.bench_function("Decode runs 64KB, run-length mode", |b| {
+        b.iter(|| {
+            let mut decoder = Decoder::from_slice(black_box(&output)).unwrap();
+            black_box(decoder.decode_runs_to_vec(&mut RunModel::new()).unwrap())
+        })
+    });
+
+    g.finish();
+}
+
+/// The encoder renormalizing a bit at a time against a byte at a time, on text, which settles a few
+/// bits per symbol, and on random bytes, which settle about a byte per symbol. The output is the same.
+fn renormalization_benchmark(c: &mut Criterion) {
+    const SIZE: usize = 64 * 1024;
+
+    let mut g = c.benchmark_group("renormalization");
+    g.throughput(Throughput::Bytes(SIZE as u64));
+
+    let text = text_corpus(SIZE);
+    let mut state = 0x9E3779B97F4A7C15;
+    let random = (0..SIZE).map(|_| xorshift(&mut state)).collect::<Vec<_>>();
+
+    for (name, input) in [("text", &text), ("random", &random)] {
+        for (granularity, bytewise) in [("bits", false), ("bytes", true)] {
+            g.bench_function(format!("Encode {name} 64KB, {granularity}"), |b| {
+                b.iter(|| {
+                    let mut output = Vec::new();
+                    {
+                        let mut encoder =
+                            Encoder::new(&mut output).with_byte_renormalization(bytewise);
+                        encoder.encode_slice(black_box(input)).unwrap();
+                        encoder.encode_end().unwrap();
+                    }
+                    black_box(output)
+                })
+            });
+        }
+    }
+
+    g.finish();
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     let mut g = c.benchmark_group("bazoopy");
 
-    let mut rng = rand::thread_rng();
+    let mut rng = rand::rng();
 
     {
         const SIZE: usize = 64 * 1024;
         g.throughput(Throughput::Bytes(SIZE as u64));
         let mut src = [0usize; SIZE];
-        for i in 0..src.len() {
-            src[i] = rng.gen::<usize>() % 256;
+        for x in src.iter_mut() {
+            *x = rng.random::<u8>() as usize;
         }
         g.bench_function("Encode random 64KB", |b| bench_encode::<SIZE>(b, &src));
         g.bench_function("Decode random 64KB", |b| bench_decode::<SIZE>(b, &src));
+        g.bench_function("Range encode random 64KB", |b| {
+            bench_range_encode::<SIZE>(b, &src)
+        });
+        g.bench_function("Range decode random 64KB", |b| {
+            bench_range_decode::<SIZE>(b, &src)
+        });
     }
 
     {
         const SIZE: usize = 8 * 1024;
         g.throughput(Throughput::Bytes(SIZE as u64));
         let mut src = [0usize; SIZE];
-        for i in 0..src.len() {
-            src[i] = rng.gen::<usize>() % 256;
+        for x in src.iter_mut() {
+            *x = rng.random::<u8>() as usize;
         }
         g.bench_function("Encode random 8KB", |b| bench_encode::<SIZE>(b, &src));
         g.bench_function("Decode random 8KB", |b| bench_decode::<SIZE>(b, &src));
@@ -78,6 +443,12 @@ fn criterion_benchmark(c: &mut Criterion) {
         let src = [0usize; SIZE];
         g.bench_function("Encode zeroes 64KB", |b| bench_encode::<SIZE>(b, &src));
         g.bench_function("Decode zeroes 64KB", |b| bench_decode::<SIZE>(b, &src));
+        g.bench_function("Range encode zeroes 64KB", |b| {
+            bench_range_encode::<SIZE>(b, &src)
+        });
+        g.bench_function("Range decode zeroes 64KB", |b| {
+            bench_range_decode::<SIZE>(b, &src)
+        });
     }
 
     {
@@ -90,5 +461,13 @@ fn criterion_benchmark(c: &mut Criterion) {
     g.finish();
 }
 
-criterion_group!(benches, criterion_benchmark);
+criterion_group!(
+    benches,
+    criterion_benchmark,
+    model_benchmark,
+    in_memory_benchmark,
+    binary_benchmark,
+    runs_benchmark,
+    renormalization_benchmark
+);
 criterion_main!(benches);
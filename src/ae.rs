@@ -1,291 +1,4525 @@
-use crate::bitio::{BitReader, BitWriter, ReadResult};
+use crate::bitio::{BitReader, BitWriter, Handle, ReadResult};
+use crate::error::AeError;
+use crate::io::{Read, Write};
+use crate::model::{
+    ContextModel, Count, ExtendableModel, RunModel, SymbolTable, EOF_SYMBOL, MAX_DATA_SYMBOL,
+    REPEAT_SYMBOL,
+};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 use anyhow::Result;
 use core::fmt;
-use std::io::{Read, Write};
 
-const MAX_SYMBOLS: usize = 0x101;
-const MAX_PROBABILITY: usize = 0xFFFFFFFF;
-const SYMBOL_EOF: usize = 0x100;
+/// Most raw bits [`Encoder::encode_raw_bits`] codes as a single uniform symbol.
+const RAW_CHUNK_BITS: u32 = 16;
 
-#[derive(Debug)]
-struct SymbolTable {
-    symbol_count: usize,
-    table: [usize; MAX_SYMBOLS + 1],
+/// The fewest repeats [`Encoder::encode_runs`] codes with [`REPEAT_SYMBOL`]. Coding the count costs
+/// more than a repeat or two of the byte itself would.
+const MIN_REPEATS: u64 = 2;
+
+/// Register width used when none is given. Streams from coders of different widths aren't compatible.
+pub const DEFAULT_REGISTER_BITS: u32 = 32;
+
+/// Checks, in debug builds only, that narrowing an interval of `range` values to
+/// `symbol_low..symbol_high` out of `total` leaves a non-empty interval. The narrowing casts between
+/// `usize` and the register type and adjusts by one, so a bad model would otherwise wrap silently.
+fn debug_check_symbol(symbol_low: usize, symbol_high: usize, total: usize, range: u128) {
+    debug_assert!(
+        symbol_low < symbol_high && symbol_high <= total,
+        "symbol interval {symbol_low}..{symbol_high} out of {total} is empty or out of bounds"
+    );
+    debug_assert!(
+        range >= total as u128,
+        "model total {total} is larger than the coder's range {range}"
+    );
 }
 
-impl SymbolTable {
-    fn new() -> SymbolTable {
-        let mut ret = SymbolTable {
-            symbol_count: 0,
-            table: [0; MAX_SYMBOLS + 1],
-        };
+/// The interval both coders narrow in lockstep, and the renormalization that keeps it wide. The
+/// encoder writes out the bits it shifts away, the decoder reads in the matching bits of the stream.
+///
+/// The registers are `BITS` wide, held in the low bits of a `u64`.
+#[derive(Debug, Clone, Copy)]
+struct CoderCore<const BITS: u32> {
+    high: u64,
+    low: u64,
+}
+
+/// One step of [`CoderCore::shift`].
+enum Shift {
+    /// The top bit of `high` and `low` agreed and has been shifted out. This is its value.
+    Settled(bool),
+    /// The interval straddled the midpoint too narrowly, so the second bit was taken out instead and
+    /// the top bit is still undecided.
+    Underflow,
+    /// The interval is wide enough, nothing was shifted.
+    Done,
+}
+
+impl<const BITS: u32> CoderCore<BITS> {
+    /// Every bit of the register set, the top of a fresh interval.
+    const MAX: u64 = u64::MAX >> (u64::BITS - BITS);
+    /// The most significant bit of a register, which is what gets shifted out to the stream.
+    const MSB_MASK: u64 = 1 << (BITS - 1);
+    /// The second most significant bit of a register, which is what the underflow handling looks at.
+    const SECOND_MSB_MASK: u64 = Self::MSB_MASK >> 1;
+    /// Largest model total the coder can take. After renormalizing the interval always spans more
+    /// than a quarter of the register, so every symbol still gets at least one value of it.
+    const MAX_TOTAL: u64 = 1 << (BITS - 2);
+    /// [`CoderCore::MAX_TOTAL`] as a model count, which an adaptive model is halved to stay within.
+    const MODEL_LIMIT: usize = if Self::MAX_TOTAL > usize::MAX as u64 {
+        usize::MAX
+    } else {
+        Self::MAX_TOTAL as usize
+    };
 
-        for i in 0..MAX_SYMBOLS {
-            ret.increment_symbol(i)
+    // Narrower registers leave too little room for the 257 symbols of a fresh table, wider ones don't
+    // fit the `u64`. Checked when a coder of that width is created.
+    const VALID_WIDTH: () = assert!(
+        BITS >= 16 && BITS <= u64::BITS,
+        "register width has to be between 16 and 64 bits"
+    );
+
+    fn new() -> CoderCore<BITS> {
+        let () = Self::VALID_WIDTH;
+
+        CoderCore {
+            high: Self::MAX,
+            low: 0,
         }
+    }
 
-        ret
+    /// Back to the whole register, as at the start of a stream.
+    fn reset(&mut self) {
+        self.high = Self::MAX;
+        self.low = 0;
     }
 
-    fn increment_symbol(&mut self, symbol: usize) {
-        self.symbol_count += 1;
+    fn range(&self) -> u128 {
+        (self.high - self.low) as u128 + 1
+    }
 
-        for i in symbol..MAX_SYMBOLS {
-            self.table[i + 1] += 1;
-        }
+    /// Fails if a model with `total` would narrow the interval to nothing, or has nothing to narrow
+    /// it to.
+    fn check_total(total: usize) -> Result<()> {
+        anyhow::ensure!(total > 0, "model total is 0, no symbol can be coded");
+        anyhow::ensure!(
+            total as u64 <= Self::MAX_TOTAL,
+            "model total {total} is too large for {BITS} bit registers, the limit is {}",
+            Self::MAX_TOTAL
+        );
+
+        anyhow::Ok(())
     }
 
-    fn get_symbol(&self, symbol: usize) -> (usize, usize) {
-        (self.table[symbol], self.table[symbol + 1])
+    /// Shifts `bit` into the bottom of `register`, dropping its top bit.
+    fn push_bit(register: u64, bit: bool) -> u64 {
+        (register << 1 | bit as u64) & Self::MAX
     }
 
-    fn find_symbol(&self, cumulative_value: usize) -> (usize, usize, usize) {
-        let mut low = 0;
-        let mut high = self.table.len() - 1;
+    /// Narrows the interval to `symbol_low..symbol_high` out of `total`.
+    fn narrow(&mut self, symbol_low: usize, symbol_high: usize, total: usize) {
+        let range = self.range();
 
-        while high != low {
-            let mid = (low + high) / 2;
-            if self.table[mid] <= cumulative_value {
-                low = mid + 1;
-            } else {
-                high = mid;
+        // rescale low and high so that the new low and high are proportional to the cumulative frequency in the model.
+        // for example if low = 0, high = 1, there's 2 symbols (A, B) with probability 1/3 and 2/3, then if we encode an A the
+        // next [low, high) should be [0, 1/3). If we encode a B then [low, high] should be [1/3rd, 1),
+        // except all of this is with integers, so there's +1 and -1 in various places to prevent truncation issues.
+        debug_check_symbol(symbol_low, symbol_high, total, range);
+        let (high, low) = if BITS <= u32::BITS {
+            // The products fit 64 bits, since the total is at most a quarter of the register.
+            let range = range as u64;
+            let total = total as u64;
+            (
+                symbol_high as u64 * range / total - 1,
+                symbol_low as u64 * range / total,
+            )
+        } else {
+            // With 64 bit registers the whole range is 2^64, so the top of the interval only fits
+            // once one is taken off.
+            let total = total as u128;
+            (
+                (symbol_high as u128 * range / total - 1) as u64,
+                (symbol_low as u128 * range / total) as u64,
+            )
+        };
+        self.high = self.low + high;
+        self.low += low;
+        debug_assert!(
+            self.low <= self.high,
+            "interval collapsed: low {:#x} > high {:#x}",
+            self.low,
+            self.high
+        );
+    }
+
+    /// Shifts one bit out of the interval if it has narrowed enough to, see [`Shift`]. Called until it
+    /// returns [`Shift::Done`] after every [`CoderCore::narrow`].
+    fn shift(&mut self) -> Shift {
+        let shift = if (self.high & Self::MSB_MASK) == (self.low & Self::MSB_MASK) {
+            // As high and low converge their MSBs are settled.
+            Shift::Settled(self.low & Self::MSB_MASK == Self::MSB_MASK)
+        } else if (self.high & (Self::MSB_MASK | Self::SECOND_MSB_MASK)) == Self::MSB_MASK
+            && (self.low & Self::SECOND_MSB_MASK) == Self::SECOND_MSB_MASK
+        {
+            // We've run out of precision, begin implementing hacks.
+            // this is probably one of the trickiest parts.
+            // if low is converging on 0x7FFFFFF... and high is converging on 0x80000....
+            // then we don't know what bit to output because the MSB of low and high do not match yet.
+            // but if we keep going then we will run out of integer precision before the msb matches.
+            // so, we basically shift everything left and keep a counter of how many times we have done that.
+            // eventually either low will go above 0x7fff...  or high will go below 0x8000.... at that point we can output
+            // the MSB followed by <underflow> opposite bits
+            self.low &= Self::SECOND_MSB_MASK - 1;
+            self.high |= Self::SECOND_MSB_MASK;
+            Shift::Underflow
+        } else {
+            return Shift::Done;
+        };
+
+        // Now that the MSB is gone, we shift it out of high and low.
+        // conceptually high has an infinite stream of 1 bits following it, and low has an infinite stream of 0 bits following it.
+        self.high = Self::push_bit(self.high, true);
+        self.low = Self::push_bit(self.low, false);
+
+        shift
+    }
+
+    /// Shifts the top 8 bits out of the interval at once if they've all settled, returning them. The
+    /// same as 8 [`Shift::Settled`] in a row.
+    fn shift_byte(&mut self) -> Option<u8> {
+        if (self.high ^ self.low) >> (BITS - 8) != 0 {
+            return None;
+        }
+
+        let byte = (self.low >> (BITS - 8)) as u8;
+        self.high = (self.high << 8 | 0xFF) & Self::MAX;
+        self.low = (self.low << 8) & Self::MAX;
+
+        Some(byte)
+    }
+
+    /// The encoder's side of renormalizing after [`CoderCore::narrow`]: passes every bit the interval
+    /// settles to `emit`, followed by the `underflow` bits it resolves, and counts the bits still
+    /// undecided in `underflow`. Returns how many bits were shifted out either way.
+    fn renormalize_into(
+        &mut self,
+        underflow: &mut usize,
+        mut emit: impl FnMut(bool) -> Result<()>,
+    ) -> Result<u32> {
+        let mut shifts = 0;
+        loop {
+            match self.shift() {
+                Shift::Settled(bit) => {
+                    emit(bit)?;
+
+                    // When we run out of precision, we remember how many bits are obliterated so that we don't run out of precision.
+                    // Once we discover the true MSB then we can output that number of bits correctly.
+                    while *underflow != 0 {
+                        emit(!bit)?;
+                        *underflow -= 1;
+                    }
+                }
+                Shift::Underflow => *underflow += 1, // Must keep track of how many bits we obliterate.
+                Shift::Done => break,
             }
+            shifts += 1;
+        }
+
+        anyhow::Ok(shifts)
+    }
+}
+
+/// [`CoderCore::renormalize_into`] as a pure function of the registers, so the trickiest part of the
+/// coder can be checked on its own: returns the new `high`, `low` and underflow count with the bits
+/// written along the way.
+#[cfg(test)]
+fn renormalize<const BITS: u32>(
+    high: u64,
+    low: u64,
+    mut underflow: usize,
+) -> (u64, u64, usize, Vec<bool>) {
+    let mut core = CoderCore::<BITS> { high, low };
+    let mut bits = Vec::new();
+    core.renormalize_into(&mut underflow, |bit| {
+        bits.push(bit);
+        anyhow::Ok(())
+    })
+    .expect("collecting bits can't fail");
+
+    (core.high, core.low, underflow, bits)
+}
+
+/// How a stream marks its end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Termination {
+    /// The stream ends with the EOF symbol. This is the default.
+    #[default]
+    Eof,
+    /// No EOF symbol is written; the decoder has to be told the length some other way, e.g. from a
+    /// length prefix, through [`Decoder::with_length`].
+    Length,
+}
+
+/// Warns when an [`Encoder`] is dropped before its stream was finished, since that stream lacks its
+/// last few bits and won't decode. Part of the encoder rather than a `Drop` on it, so
+/// [`Encoder::finish`] can still move the writer out.
+#[derive(Debug)]
+struct FinishGuard {
+    /// Set from creation until the interval is flushed, and again once more symbols are coded.
+    unfinished: bool,
+}
+
+impl Drop for FinishGuard {
+    fn drop(&mut self) {
+        if self.unfinished {
+            tracing::warn!("encoder dropped without encode_end, its stream is incomplete");
         }
+    }
+}
+
+/// The state of an [`Encoder`] between two symbols, enough for a [`Decoder`] to start decoding the
+/// stream from that point with [`Decoder::at_checkpoint`] instead of from the beginning.
+#[derive(Debug, Clone)]
+pub struct Checkpoint<const BITS: u32 = DEFAULT_REGISTER_BITS> {
+    symbols: u64,
+    bit_offset: u64,
+    underflow: usize,
+    high: u64,
+    low: u64,
+    model: SymbolTable,
+}
+
+impl<const BITS: u32> Checkpoint<BITS> {
+    /// Number of symbols encoded before the checkpoint.
+    pub fn symbols(&self) -> u64 {
+        self.symbols
+    }
+
+    /// Offset of the byte a reader has to be positioned at for [`Decoder::at_checkpoint`].
+    pub fn byte_offset(&self) -> u64 {
+        self.bit_offset / 8
+    }
+}
+
+/// Everything an [`Encoder`] needs to carry on its stream later, from [`Encoder::save_state`]: the
+/// interval, the whole model and the bits of a byte that isn't complete yet. Unlike a [`Checkpoint`]
+/// it can go to disk with [`EncoderState::to_bytes`], so a long running encode survives a restart.
+#[derive(Debug, Clone)]
+pub struct EncoderState<const BITS: u32 = DEFAULT_REGISTER_BITS> {
+    high: u64,
+    low: u64,
+    underflow: usize,
+    symbols: u64,
+    bits_written: u64,
+    /// The bits of the partial byte, at the top.
+    partial: u8,
+    termination: Termination,
+    model: SymbolTable,
+}
+
+impl<const BITS: u32> EncoderState<BITS> {
+    /// Words before the model's: the register width, the interval, the underflow, the symbol and bit
+    /// counts, the partial byte and the termination.
+    const HEADER_WORDS: usize = 8;
+
+    /// Number of symbols encoded before the state was saved.
+    pub fn symbols(&self) -> u64 {
+        self.symbols
+    }
+
+    /// Serializes the state as a sequence of little endian u64 words.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = [
+            BITS as u64,
+            self.high,
+            self.low,
+            self.underflow as u64,
+            self.symbols,
+            self.bits_written,
+            self.partial as u64,
+            (self.termination == Termination::Length) as u64,
+        ];
+
+        header
+            .into_iter()
+            .chain(self.model.to_words())
+            .flat_map(u64::to_le_bytes)
+            .collect()
+    }
 
-        let symbol = low - 1;
+    /// Reads back [`EncoderState::to_bytes`], rejecting a state no encoder with `BITS` wide registers
+    /// could have saved.
+    pub fn from_bytes(bytes: &[u8]) -> Result<EncoderState<BITS>> {
+        let words = Self::HEADER_WORDS + SymbolTable::WORDS;
+        anyhow::ensure!(
+            bytes.len() == words * 8,
+            "encoder state must be {} bytes, got {}",
+            words * 8,
+            bytes.len()
+        );
 
-        (symbol, self.table[symbol], self.table[symbol + 1])
+        let words = bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        let (header, model) = words.split_at(Self::HEADER_WORDS);
+        let &[bits, high, low, underflow, symbols, bits_written, partial, termination] = header
+        else {
+            unreachable!("the length was checked above");
+        };
+
+        anyhow::ensure!(
+            bits == BITS as u64,
+            "encoder state is for {bits} bit registers, not {BITS}"
+        );
+        anyhow::ensure!(
+            low <= high && high <= CoderCore::<BITS>::MAX,
+            "encoder state has an invalid interval {low:#x}..={high:#x}"
+        );
+        // Only the bits already written to the partial byte can be set.
+        anyhow::ensure!(
+            partial & !(0xFF00 >> (bits_written % 8) & 0xFF) == 0,
+            "encoder state has an invalid partial byte {partial:#x}"
+        );
+        let termination = match termination {
+            0 => Termination::Eof,
+            1 => Termination::Length,
+            _ => anyhow::bail!("encoder state has an invalid termination {termination}"),
+        };
+
+        anyhow::Ok(EncoderState {
+            high,
+            low,
+            underflow: usize::try_from(underflow)
+                .map_err(|_| anyhow::anyhow!("underflow count {underflow} doesn't fit usize"))?,
+            symbols,
+            bits_written,
+            partial: partial as u8,
+            termination,
+            model: SymbolTable::from_words(model)?,
+        })
     }
 }
 
+/// Counters of the work an [`Encoder`] has done, from [`Encoder::metrics`], for finding out why an
+/// input codes slowly. Only counted with the `metrics` feature.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Intervals coded. An escaped symbol counts twice, and raw bits once per chunk.
+    pub symbols_encoded: u64,
+    /// Bits shifted out of the registers, settled or underflowed, to make room for the next symbol.
+    pub renormalizations: u64,
+    /// Bits written to the output, see [`Encoder::bits_written`].
+    pub bits_written: u64,
+    /// Times the counts of a [`SymbolTable`] the encoder coded with were scaled down.
+    pub model_rescales: u64,
+}
+
+/// Encodes symbols into a stream of bits written to `T`.
+///
+/// `BITS` is the width of the coder's registers. Wider registers code improbable symbols a little
+/// more precisely at some cost in speed, narrower ones limit the model total to `2^(BITS - 2)`. The
+/// decoder has to use the same width, see [`Encoder::with_register_bits`].
+///
+/// The stream is only complete once [`Encoder::encode_end`] (or one of the other ways to finish it)
+/// has been called. Dropping the encoder before that logs a warning through `tracing`; use
+/// [`Encoder::abandon`] to throw a stream away on purpose.
+///
+/// The encoder is `Send` whenever `T` is, so each block of a parallel encode can be handed to its
+/// own thread along with the writer it borrows.
 #[derive(Debug)]
-pub struct Encoder<'a, T: Write> {
-    high: u32,
-    low: u32,
+#[must_use = "the stream is incomplete until the encoder is finished with encode_end"]
+pub struct Encoder<'a, T: Write, const BITS: u32 = DEFAULT_REGISTER_BITS> {
+    core: CoderCore<BITS>,
     underflow: usize,
+    /// Interval width and model total of the last symbol given to `encode_next`.
+    last_symbol: (usize, usize),
+    termination: Termination,
+    /// Renormalize a byte at a time where possible, see [`Encoder::with_byte_renormalization`].
+    byte_renormalization: bool,
+    symbols_encoded: u64,
+    /// Record a checkpoint every this many symbols, 0 for never.
+    checkpoint_interval: u64,
+    checkpoints: Vec<Checkpoint<BITS>>,
+    #[cfg(feature = "sync-check")]
+    model_trace: Vec<usize>,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+
+    guard: FinishGuard,
+    /// Set once the stream has been ended, after which ending it again does nothing.
+    finished: bool,
 
     symbols: SymbolTable,
     bit_writer: BitWriter<'a, T>,
 }
 
+/// Decodes symbols from a stream written by an [`Encoder`] with the same register width `BITS`.
+///
+/// Like the [`Encoder`] it's `Send` whenever `T` is.
 #[derive(Debug)]
-pub struct Decoder<'a, T: Read> {
-    high: u32,
-    low: u32,
-    code: u32,
+pub struct Decoder<'a, T: Read, const BITS: u32 = DEFAULT_REGISTER_BITS> {
+    core: CoderCore<BITS>,
+    code: u64,
+    /// Bits made up because the stream had already ended.
+    eof_bits: u32,
+    termination: Termination,
+    /// Bytes left to decode in [`Termination::Length`] mode, when the length is known.
+    remaining: Option<u64>,
+    /// The most bytes [`Decoder::decode_to_vec`] will return, see [`Decoder::with_max_output_bytes`].
+    max_output: u64,
+    #[cfg(feature = "sync-check")]
+    model_trace: Option<Vec<usize>>,
+    #[cfg(feature = "sync-check")]
+    symbols_decoded: usize,
+
+    /// Re-encodes what's decoded, see [`Decoder::new_verified`].
+    verifier: Option<Box<Verifier<BITS>>>,
 
     symbols: SymbolTable,
     bit_reader: BitReader<'a, T>,
 }
 
-impl<T: Write + fmt::Debug> Encoder<'_, T> {
+/// The shadow encoder of a [`Decoder::new_verified`]. It codes every interval the decoder narrows to
+/// again, the way the encoder did, and checks the bits it would write against the ones read.
+#[derive(Debug)]
+struct Verifier<const BITS: u32> {
+    core: CoderCore<BITS>,
+    underflow: usize,
+    /// The encoder's model, updated with every symbol the decoder returns from its own model.
+    model: SymbolTable,
+    /// Bits read from the stream that re-encoding hasn't caught up with yet.
+    stream: VecDeque<bool>,
+    /// Position in the stream of the first bit of `stream`.
+    position: u64,
+    /// Intervals coded so far, the index of the next one.
+    symbols: u64,
+}
+
+impl<const BITS: u32> Verifier<BITS> {
+    fn new(model: SymbolTable) -> Verifier<BITS> {
+        Verifier {
+            core: CoderCore::new(),
+            underflow: 0,
+            model,
+            stream: VecDeque::new(),
+            position: 0,
+            symbols: 0,
+        }
+    }
+
+    /// Checks the encoder's model gives `symbol` the interval the decoder found it in, then counts it.
+    fn check_model(&mut self, symbol: usize, low: usize, high: usize, total: usize) -> Result<()> {
+        if self.model.get_symbol(symbol) != (low, high) || self.model.symbol_count != total {
+            return Err(self.diverged(
+                self.symbols,
+                "the encoder's model gives it a different interval",
+            ));
+        }
+        self.model
+            .update_within(symbol, CoderCore::<BITS>::MODEL_LIMIT);
+
+        anyhow::Ok(())
+    }
+
+    /// Codes an interval the decoder narrowed to, checking every bit written against the stream.
+    fn encode(&mut self, symbol_low: usize, symbol_high: usize, total: usize) -> Result<()> {
+        self.core.narrow(symbol_low, symbol_high, total);
+        let mut core = self.core;
+        let mut underflow = self.underflow;
+        let symbol = self.symbols;
+        let shifted = core.renormalize_into(&mut underflow, |bit| self.check_bit(symbol, bit));
+        self.core = core;
+        self.underflow = underflow;
+        shifted?;
+        self.symbols += 1;
+
+        anyhow::Ok(())
+    }
+
+    /// Writes what the encoder's flush would, padding included, then starts a fresh interval.
+    fn finish(&mut self) -> Result<()> {
+        // The flush belongs to the last symbol coded.
+        let symbol = self.symbols.saturating_sub(1);
+        let second_bit = self.core.low & CoderCore::<BITS>::SECOND_MSB_MASK != 0;
+        self.check_bit(symbol, second_bit)?;
+        for _ in 0..=self.underflow {
+            self.check_bit(symbol, !second_bit)?;
+        }
+        while !self.position.is_multiple_of(8) {
+            self.check_bit(symbol, false)?;
+        }
+
+        self.core.reset();
+        self.underflow = 0;
+
+        anyhow::Ok(())
+    }
+
+    fn check_bit(&mut self, symbol: u64, bit: bool) -> Result<()> {
+        if self.stream.pop_front() != Some(bit) {
+            return Err(self.diverged(symbol, "re-encoding it doesn't give the bits read"));
+        }
+        self.position += 1;
+
+        anyhow::Ok(())
+    }
+
+    #[cold]
+    fn diverged(&self, symbol: u64, why: &str) -> anyhow::Error {
+        anyhow::Error::from(AeError::CorruptStream).context(alloc::format!(
+            "decoded symbol {symbol} doesn't check out at bit {}: {why}",
+            self.position
+        ))
+    }
+}
+
+impl<'a, T: Write + fmt::Debug> Encoder<'a, T> {
     pub fn new(writer: &mut T) -> Encoder<'_, T> {
+        Self::with_model(writer, SymbolTable::new())
+    }
+
+    /// Starts encoding from an already trained model, e.g. one restored with [`SymbolTable::import`].
+    /// The stream can only be decoded by a [`Decoder::with_model`] given an identical model.
+    pub fn with_model(writer: &mut T, model: SymbolTable) -> Encoder<'_, T> {
+        Encoder::with_bit_writer(BitWriter::new(writer), model)
+    }
+
+    /// Starts encoding from an adaptive model primed with `counts`, see
+    /// [`SymbolTable::with_initial_counts`]. Decode with [`Decoder::with_initial_counts`] and the same
+    /// counts.
+    pub fn with_initial_counts(writer: &'a mut T, counts: &[usize; 257]) -> Result<Encoder<'a, T>> {
+        anyhow::Ok(Self::with_model(
+            writer,
+            SymbolTable::with_initial_counts(counts)?,
+        ))
+    }
+
+    /// Creates an encoder that owns its writer, so it has no borrow to outlive and can be stored
+    /// anywhere. Get the writer back with [`Encoder::into_inner`].
+    pub fn new_owned(writer: T) -> Encoder<'static, T> {
+        Encoder::with_bit_writer(
+            BitWriter::with_handle(Handle::Owned(writer)),
+            SymbolTable::new(),
+        )
+    }
+}
+
+impl<'a, T: Write + fmt::Debug, const BITS: u32> Encoder<'a, T, BITS> {
+    /// Starts encoding with `BITS` wide registers instead of the default 32, e.g.
+    /// `Encoder::<_, 64>::with_register_bits(&mut output, SymbolTable::new())`. Decode with
+    /// [`Decoder::with_register_bits`] and the same width.
+    pub fn with_register_bits(writer: &'a mut T, model: SymbolTable) -> Encoder<'a, T, BITS> {
+        Encoder::with_bit_writer(BitWriter::new(writer), model)
+    }
+
+    fn with_bit_writer(bit_writer: BitWriter<'a, T>, model: SymbolTable) -> Encoder<'a, T, BITS> {
         Encoder {
-            high: MAX_PROBABILITY as u32,
-            low: 0,
+            core: CoderCore::new(),
             underflow: 0,
-            symbols: SymbolTable::new(),
-            bit_writer: BitWriter::new(writer),
+            last_symbol: (1, 1),
+            termination: Termination::Eof,
+            byte_renormalization: false,
+            symbols_encoded: 0,
+            checkpoint_interval: 0,
+            checkpoints: Vec::new(),
+            #[cfg(feature = "sync-check")]
+            model_trace: Vec::new(),
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::default(),
+            guard: FinishGuard { unfinished: true },
+            finished: false,
+            symbols: model,
+            bit_writer,
         }
     }
 
-    pub fn encode_next(&mut self, symbol: usize) -> Result<()> {
-        let range = (self.high - self.low) as usize + 1;
+    /// Renormalizes a whole byte at a time whenever the top 8 bits of the interval have settled, rather
+    /// than always a bit at a time. The bits written are exactly the same, so the stream and its decoder
+    /// don't change, only how it's written. With the adaptive model that's no faster in the
+    /// `renormalization` benches, since updating the model costs far more than the bits do, which is
+    /// why it's off by default.
+    pub fn with_byte_renormalization(mut self, enabled: bool) -> Self {
+        self.byte_renormalization = enabled;
+        self
+    }
 
-        // should probably make this a part of the model.
-        let (symbol_low, symbol_high) = self.symbols.get_symbol(symbol);
+    /// Records a [`Checkpoint`] after every `interval` symbols given to [`Encoder::encode_next`],
+    /// collected with [`Encoder::take_checkpoints`]. Storing them next to the stream lets a decoder
+    /// seek into it. An interval of 0 turns checkpoints off.
+    pub fn with_checkpoints(mut self, interval: u64) -> Self {
+        self.checkpoint_interval = interval;
+        self
+    }
 
-        // rescale low and high so that the new low and high are proportional to the cumulative frequency in the model.
-        // for example if low = 0, high = 1, there's 2 symbols (A, B) with probability 1/3 and 2/3, then if we encode an A the
-        // next [low, high) should be [0, 1/3). If we encode a B then [low, high] should be [1/3rd, 1),
-        // except all of this is with integers, so there's +1 and -1 in various places to prevent truncation issues.
-        self.high =
-            (self.low as usize + ((symbol_high * range) / self.symbols.symbol_count) - 1) as u32;
-        self.low = (self.low as usize + ((symbol_low * range) / self.symbols.symbol_count)) as u32;
+    /// Captures the encoder's state at this point of the stream.
+    pub fn checkpoint(&self) -> Checkpoint<BITS> {
+        Checkpoint {
+            symbols: self.symbols_encoded,
+            bit_offset: self.bit_writer.bits_written(),
+            underflow: self.underflow,
+            high: self.core.high,
+            low: self.core.low,
+            model: self.symbols.clone(),
+        }
+    }
 
-        // As high and low converge we want to write out their MSBs.
-        loop {
-            if (self.high & 0x80000000) == (self.low & 0x80000000) {
-                self.bit_writer.write(self.low & 0x80000000 == 0x80000000)?;
-
-                // When we run out of precision, we remember how many bits are obliterated so that we don't run out of precision.
-                // Once we discover the true MSB then we can output that number of bits correctly.
-                while self.underflow != 0 {
-                    self.bit_writer
-                        .write((self.low & 0x80000000) != 0x80000000)?;
-                    self.underflow -= 1;
-                }
-            } else if (self.high & 0xC0000000) == 0x80000000
-                && (self.low & 0x40000000) == 0x40000000
-            {
-                // We've run out of precision, begin implementing hacks.
-                // this is probably one of the trickiest parts.
-                // if low is converging on 0x7FFFFFF... and high is converging on 0x80000....
-                // then we don't know what bit to output because the MSB of low and high do not match yet.
-                // but if we keep going then we will run out of integer precision before the msb matches.
-                // so, we basically shift everything left and keep a counter of how many times we have done that.
-                // eventually either low will go above 0x7fff...  or high will go below 0x8000.... at that point we can output
-                // the MSB followed by <underflow> opposite bits
-                self.underflow += 1; // Must keep track of how many bits we obliterate.
-                self.low &= 0x3FFFFFFF;
-                self.high |= 0x40000000;
-            } else {
-                break;
-            }
+    /// Hands over the checkpoints recorded so far.
+    pub fn take_checkpoints(&mut self) -> Vec<Checkpoint<BITS>> {
+        core::mem::take(&mut self.checkpoints)
+    }
 
-            // Now that the MSB is gone, we shift it out of high and low.
-            self.high = self.high << 1;
-            self.low = self.low << 1;
+    /// Stops encoding part way through the stream, to carry on later with [`Encoder::restore_state`].
+    /// Every completed byte is written out and the writer flushed; the bits of the last, partial byte
+    /// are kept in the state with everything else the encoder needs. Recorded checkpoints, the
+    /// metrics and the sync-check trace aren't part of it.
+    pub fn save_state(mut self) -> Result<EncoderState<BITS>> {
+        self.ensure_unfinished()?;
+        self.guard.unfinished = false;
 
-            // conceptually high has an infinite stream of 1 bits following it, and low has an infinite stream of 0 bits following it.
-            self.high = self.high | 1;
+        anyhow::Ok(EncoderState {
+            high: self.core.high,
+            low: self.core.low,
+            underflow: self.underflow,
+            symbols: self.symbols_encoded,
+            bits_written: self.bit_writer.bits_written(),
+            partial: self.bit_writer.suspend()?,
+            termination: self.termination,
+            model: self.symbols,
+        })
+    }
 
-            // The next shifted in MSBs might also match, so we loop.
+    /// Carries on the stream of an encoder stopped with [`Encoder::save_state`]. `writer` has to be
+    /// positioned right after what that encoder wrote, so the two parts together form the same stream
+    /// as encoding everything in one go.
+    pub fn restore_state(writer: &'a mut T, state: EncoderState<BITS>) -> Encoder<'a, T, BITS> {
+        let bit_writer = BitWriter::new(writer).resume(state.bits_written, state.partial);
+        let mut encoder = Encoder::with_bit_writer(bit_writer, state.model);
+        encoder.core.high = state.high;
+        encoder.core.low = state.low;
+        encoder.underflow = state.underflow;
+        encoder.symbols_encoded = state.symbols;
+        encoder.termination = state.termination;
+
+        encoder
+    }
+
+    /// The model's total after each symbol given to [`Encoder::encode_next`], for
+    /// [`Decoder::with_model_trace`].
+    #[cfg(feature = "sync-check")]
+    pub fn model_trace(&self) -> &[usize] {
+        &self.model_trace
+    }
+
+    /// What the encoder has done so far.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            bits_written: self.bit_writer.bits_written(),
+            ..self.metrics
         }
+    }
 
-        self.symbols.increment_symbol(symbol);
+    /// Adds to [`Metrics::model_rescales`], without the `metrics` feature it does nothing.
+    #[inline(always)]
+    fn count_rescales(&mut self, _rescales: u32) {
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.model_rescales += u64::from(_rescales);
+        }
+    }
 
-        anyhow::Ok(())
+    /// The model as it stands after the symbols encoded so far, e.g. for
+    /// [`SymbolTable::frequencies`].
+    pub fn model(&self) -> &SymbolTable {
+        &self.symbols
     }
 
-    pub fn encode_end(&mut self) -> Result<()> {
-        self.encode_next(SYMBOL_EOF)?;
+    /// The model, for changing it between symbols, e.g. boosting a symbol some outside signal says is
+    /// coming. The decoder's model has to be changed the same way after the same symbol through
+    /// [`Decoder::model_mut`], otherwise the rest of the stream decodes to garbage.
+    pub fn model_mut(&mut self) -> &mut SymbolTable {
+        &mut self.symbols
+    }
 
-        self.underflow += 1;
-        self.bit_writer.write(self.low & 0x40000000 == 0x40000000)?;
+    /// A copy of the model as it stands, e.g. to try a change on it and throw it away again.
+    pub fn clone_model(&self) -> SymbolTable {
+        self.symbols.clone()
+    }
 
-        while self.underflow > 0 {
-            self.underflow -= 1;
-            self.bit_writer.write(self.low & 0x40000000 != 0x40000000)?;
+    /// Forgets everything the model has learned, see [`SymbolTable::reset`], e.g. where the data is
+    /// known to change character. The decoder has to call [`Decoder::reset_model`] after the same
+    /// symbol. To reset every so many symbols instead use [`crate::AdaptiveModel::with_reset`].
+    pub fn reset_model(&mut self) {
+        self.symbols.reset();
+    }
+
+    /// Sets how [`Encoder::encode_end`] terminates the stream.
+    pub fn with_termination(mut self, termination: Termination) -> Self {
+        self.termination = termination;
+        self
+    }
+
+    pub fn encode_next(&mut self, symbol: usize) -> Result<()> {
+        // should probably make this a part of the model.
+        self.check_symbol(symbol)?;
+        let (symbol_low, symbol_high) = self.symbols.get_symbol(symbol);
+        anyhow::ensure!(
+            symbol_high > symbol_low,
+            "symbol {symbol} has a zero count in the model and can't be encoded"
+        );
+
+        self.encode_range(symbol_low, symbol_high, self.symbols.symbol_count)?;
+        self.last_symbol = (symbol_high - symbol_low, self.symbols.symbol_count);
+
+        let rescales = self
+            .symbols
+            .update_within(symbol, CoderCore::<BITS>::MODEL_LIMIT);
+        self.count_rescales(rescales);
+        #[cfg(feature = "sync-check")]
+        self.model_trace.push(self.symbols.symbol_count);
+
+        self.symbols_encoded += 1;
+        if self.checkpoint_interval > 0
+            && self
+                .symbols_encoded
+                .is_multiple_of(self.checkpoint_interval)
+        {
+            self.checkpoints.push(self.checkpoint());
         }
 
-        self.bit_writer.flush()?;
+        anyhow::Ok(())
+    }
+
+    /// Encodes every byte of `bytes`, leaving finalization to the caller.
+    pub fn encode_slice(&mut self, bytes: &[u8]) -> Result<()> {
+        for &b in bytes {
+            self.encode_next(b as usize)?;
+        }
 
         anyhow::Ok(())
     }
-}
 
-impl<T: Read + fmt::Debug> Decoder<'_, T> {
-    pub fn new(reader: &mut T) -> Result<Decoder<'_, T>> {
-        let mut decoder = Decoder {
-            high: 0xFFFFFFFF,
-            low: 0,
-            symbols: SymbolTable::new(),
-            bit_reader: BitReader::new(reader),
-            code: 0,
-        };
+    /// Encodes one piece of a stream that arrives in parts, e.g. off the network, so the whole input
+    /// never has to be buffered. Feeding the input in any split produces exactly the same stream as
+    /// encoding it in one go.
+    ///
+    /// Bits are emitted as the interval narrows, except for a few the coder holds back while it's
+    /// undecided (pending underflow), and only [`Encoder::encode_end`] settles those. Unlike
+    /// [`Encoder::encode_slice`], which leaves completed bytes batched up, this hands every completed
+    /// byte to the writer before returning, so the receiving end is never more than a partial byte
+    /// plus the held back bits behind.
+    pub fn encode_chunk(&mut self, chunk: &[u8]) -> Result<()> {
+        self.encode_slice(chunk)?;
+        self.bit_writer.write_batch()
+    }
 
-        for _ in 0..32 {
-            decoder.code = decoder.code << 1;
-            match decoder.bit_reader.read()? {
-                ReadResult::EOF => decoder.code = decoder.code | 1,
-                ReadResult::Bit(r) => decoder.code = decoder.code | if r { 1 } else { 0 },
-            }
+    /// Encodes every byte `bytes` yields, leaving finalization to the caller. Saves collecting an
+    /// iterator into a buffer just to call [`Encoder::encode_slice`].
+    pub fn encode_iter<I: IntoIterator<Item = u8>>(&mut self, bytes: I) -> Result<()> {
+        for b in bytes {
+            self.encode_next(b as usize)?;
         }
 
-        anyhow::Ok(decoder)
+        anyhow::Ok(())
     }
 
-    pub fn decode_next(&mut self) -> Result<usize> {
-        // Decoding is almost identical to encoding except that we have a stream of already encoded bits that we have to deal with.
-        let range = (self.high - self.low) as usize + 1;
+    /// Trains the model on `sample` as if it had been encoded, without writing anything, so data like
+    /// it that follows codes cheaper. The decoder has to be primed with the same sample at the same
+    /// point, with [`Decoder::prime`].
+    pub fn prime(&mut self, sample: &[u8]) {
+        for &b in sample {
+            self.symbols
+                .update_within(b as usize, CoderCore::<BITS>::MODEL_LIMIT);
+        }
+    }
 
-        // This is essentially the major difference between encoding and decoding.
-        // In decoding we determine the symbol from the already encoded stream by where it lies in the range between high and low.
-        // in encoding we calculate the range directly as we are given the symbol.
-        let cumulative_value =
-            ((self.code as usize - self.low as usize + 1) as usize * self.symbols.symbol_count - 1)
-                / range as usize;
+    /// Number of bits emitted so far. Bits the coder is still holding back (pending underflow and the
+    /// final flush) aren't counted, so the finished stream is a little longer than this.
+    pub fn bits_written(&self) -> u64 {
+        self.bit_writer.bits_written()
+    }
 
-        let (symbol, symbol_low, symbol_high) = self.symbols.find_symbol(cumulative_value);
+    /// The information content, in bits, of the last symbol passed to [`Encoder::encode_next`]: `-log2(p)`
+    /// where `p` is the probability the model gave it at the time. This is a diagnostic estimate of what
+    /// the symbol cost, the bits actually written trail it a little. It's 0 before the first symbol.
+    #[cfg(feature = "std")]
+    pub fn last_symbol_cost(&self) -> f64 {
+        let (width, total) = self.last_symbol;
 
-        // The following is identical to encoding.
-        self.high =
-            (self.low as usize + ((symbol_high * range) / self.symbols.symbol_count) - 1) as u32;
-        self.low = (self.low as usize + ((symbol_low * range) / self.symbols.symbol_count)) as u32;
+        (total as f64).log2() - (width as f64).log2()
+    }
 
-        loop {
-            if (self.high & 0x80000000) == (self.low & 0x80000000) {
-                // Since we are decoding then there's nothing to do here.
-                // We need to preserve the condition because the second branch in this if statement has the assumption that the above is not true.
-            } else if (self.high & 0xC0000000) == 0x80000000
-                && (self.low & 0x40000000) == 0x40000000
-            {
-                // More precision hacks.
-                self.high = self.high | 0x40000000;
-                self.low = self.low & 0x3FFFFFFF;
+    /// How many bits wide the current interval is: `log2(high - low + 1)`, less the pending underflow
+    /// bits, which narrow the real interval by half each. A wide interval is cheap to terminate, since
+    /// [`Encoder::encode_end`] has to write the pending underflow bits plus two, so this is a way to
+    /// pick good places to end a stream and start a new one. Purely informational.
+    pub fn interval_bits(&self) -> u32 {
+        let width = self.core.range();
+        let underflow = u32::try_from(self.underflow).unwrap_or(u32::MAX);
 
-                self.code -= 0x40000000;
-            } else {
-                // Can't do anything.
-                break;
-            }
+        width.ilog2().saturating_sub(underflow)
+    }
 
-            // Now that the MSB is gone, we shift it out of high and low.
-            self.high = self.high << 1;
-            self.low = self.low << 1;
-            self.code = self.code << 1;
+    /// Encodes `symbol` with an [`ExtendableModel`]. Symbols that aren't in the model's alphabet yet are
+    /// coded as the escape followed by their identity, after which they join the alphabet.
+    /// The decoder has to be driven with the same model through [`Decoder::decode_extendable`].
+    pub fn encode_extendable(&mut self, model: &mut ExtendableModel, symbol: usize) -> Result<()> {
+        match model.slot_of(symbol) {
+            Some(slot) => {
+                let (symbol_low, symbol_high) = model.get_slot(slot);
+                self.encode_range(symbol_low, symbol_high, model.total())?;
+                model.increment_slot(slot, CoderCore::<BITS>::MODEL_LIMIT);
+            }
+            None => {
+                anyhow::ensure!(
+                    symbol < model.capacity(),
+                    "symbol {symbol} is outside the capacity {}",
+                    model.capacity()
+                );
 
-            self.high = self.high | 1; // There it is.
+                let (escape_low, escape_high) = model.get_slot(model.escape_slot());
+                self.encode_range(escape_low, escape_high, model.total())?;
 
-            // This is the other major difference from encoding.
-            // This is just reading the stream of bits from the encoded value, we don't have this while encoding.
-            match self.bit_reader.read()? {
-                crate::bitio::ReadResult::Bit(r) => {
-                    self.code |= if r { 1 } else { 0 };
-                }
-                crate::bitio::ReadResult::EOF => {
-                    // I think that when decoding a well-formed stream, the actual decoder never processes these bits
-                    // but for extremely small messages the decoder starts with 4 bytes of read data so this can actually be invoked.
-                    self.code |= 1;
-                }
+                // The identity of the new symbol is coded uniformly, there's nothing better to go on.
+                self.encode_range(symbol, symbol + 1, model.capacity())?;
+                model.extend(symbol, CoderCore::<BITS>::MODEL_LIMIT)?;
             }
-
-            // The next shifted in MSBs might also match, so we loop.
         }
 
-        // We want to update our probability model now.
-        self.symbols.increment_symbol(symbol);
-
-        anyhow::Ok(symbol)
+        anyhow::Ok(())
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::Decoder;
-    use super::Encoder;
-    use super::SYMBOL_EOF;
-    use quickcheck_macros::quickcheck;
+    /// Encodes `symbol` (a byte value or [`EOF_SYMBOL`]) with a [`ContextModel`], escaping to its
+    /// order-0 fallback when the current context hasn't seen the symbol yet. The decoder has to be
+    /// driven with the same model through [`Decoder::decode_context`]. End a stream coded this way
+    /// by encoding [`EOF_SYMBOL`] here and finishing with [`Encoder::encode_end_without_eof`].
+    pub fn encode_context(&mut self, model: &mut ContextModel, symbol: usize) -> Result<()> {
+        self.check_symbol(symbol)?;
 
-    #[quickcheck]
-    fn can_read_and_write_same_bytes(input: Vec<u8>) {
-        let mut output = Vec::new();
-        let mut output2 = Vec::new();
+        let total = model.context_total();
+        match model.context_interval(symbol) {
+            Some((symbol_low, symbol_high)) => self.encode_range(symbol_low, symbol_high, total)?,
+            None => {
+                let (escape_low, escape_high) = model.escape_interval();
+                self.encode_range(escape_low, escape_high, total)?;
 
-        {
-            let mut encoder = Encoder::new(&mut output);
-            for s in &input {
-                encoder.encode_next(*s as usize).unwrap();
+                let (symbol_low, symbol_high) = model.fallback_interval(symbol);
+                self.encode_range(symbol_low, symbol_high, model.fallback_total())?;
             }
-            encoder.encode_end().unwrap();
         }
+        model.update(symbol, CoderCore::<BITS>::MODEL_LIMIT);
 
-        {
-            let mut cursor = std::io::Cursor::new(&output);
-            let mut decoder = Decoder::new(&mut cursor).unwrap();
+        anyhow::Ok(())
+    }
 
-            loop {
-                let s = decoder.decode_next().unwrap();
+    /// Encodes `bytes` in the run-length mode: every run of the same byte as the byte followed by
+    /// [`REPEAT_SYMBOL`] and the number of repeats, all with `model`. Decode with
+    /// [`Decoder::decode_run`] and the same model, and end the stream with [`Encoder::encode_runs_end`].
+    /// A run that carries on into the next call is coded as two.
+    pub fn encode_runs(&mut self, model: &mut RunModel, bytes: &[u8]) -> Result<()> {
+        let mut rest = bytes;
+        while let Some(&b) = rest.first() {
+            let run = rest.iter().take_while(|&&x| x == b).count();
+            rest = &rest[run..];
 
-                if s == SYMBOL_EOF {
-                    break;
+            self.encode_extendable(&mut model.symbols, b as usize)?;
+            let repeats = run as u64 - 1;
+            if repeats >= MIN_REPEATS {
+                self.encode_extendable(&mut model.symbols, REPEAT_SYMBOL)?;
+                self.encode_repeats(repeats)?;
+            } else {
+                for _ in 0..repeats {
+                    self.encode_extendable(&mut model.symbols, b as usize)?;
                 }
-
-                output2.push(s as u8);
             }
         }
 
-        assert_eq!(input, output2);
+        anyhow::Ok(())
+    }
+
+    /// Ends a stream written by [`Encoder::encode_runs`], with the EOF symbol from `model`.
+    pub fn encode_runs_end(&mut self, model: &mut RunModel) -> Result<()> {
+        self.encode_extendable(&mut model.symbols, EOF_SYMBOL)?;
+
+        self.flush_interval()
+    }
+
+    /// Codes a repeat count as the number of bits it takes, then the bits below its leading one.
+    fn encode_repeats(&mut self, repeats: u64) -> Result<()> {
+        let value = repeats - MIN_REPEATS + 1;
+        let length = u64::BITS - value.leading_zeros();
+        self.encode_raw_bits(u64::from(length - 1), 6)?;
+
+        self.encode_raw_bits(value ^ 1 << (length - 1), length - 1)
+    }
+
+    /// Encodes `symbol` with `model` instead of the encoder's own, for formats whose fields have
+    /// different statistics: keep a model per kind of field and pass the one for each symbol. The
+    /// decoder has to pick the same model for every symbol in [`Decoder::decode_with`]. Only `model`
+    /// adapts, and [`Encoder::encode_end`] still ends the stream with the encoder's own model.
+    pub fn encode_with<C: Count>(
+        &mut self,
+        model: &mut SymbolTable<C>,
+        symbol: usize,
+    ) -> Result<()> {
+        self.check_symbol(symbol)?;
+        let (symbol_low, symbol_high) = model.get_symbol(symbol);
+        anyhow::ensure!(
+            symbol_high > symbol_low,
+            "symbol {symbol} has a zero count in the model and can't be encoded"
+        );
+
+        self.encode_range(symbol_low, symbol_high, model.symbol_count)?;
+        self.last_symbol = (symbol_high - symbol_low, model.symbol_count);
+        let rescales = model.update_within(symbol, CoderCore::<BITS>::MODEL_LIMIT);
+        self.count_rescales(rescales);
+
+        anyhow::Ok(())
+    }
+
+    /// Encodes the low `count` bits of `value` (at most 64) as they are, without a model, for flags
+    /// and lengths mixed in with modeled symbols. Decode them with [`Decoder::decode_raw_bits`].
+    ///
+    /// The bits are coded as symbols of a uniform distribution, up to 16 bits at a time, rather than
+    /// written out directly, so the interval carries on undisturbed and nothing has to be flushed.
+    /// That makes them cost their width in the stream, give or take the rounding of the interval,
+    /// which is a small fraction of a bit.
+    pub fn encode_raw_bits(&mut self, value: u64, count: u32) -> Result<()> {
+        anyhow::ensure!(
+            count <= u64::BITS,
+            "can't encode {count} raw bits, at most 64"
+        );
+        anyhow::ensure!(
+            count == u64::BITS || value >> count == 0,
+            "{value:#x} doesn't fit in {count} bits"
+        );
+
+        let mut remaining = count;
+        while remaining > 0 {
+            let chunk = remaining.min(RAW_CHUNK_BITS).min(BITS - 2);
+            remaining -= chunk;
+            let bits = (value >> remaining) as usize & ((1 << chunk) - 1);
+            self.encode_range(bits, bits + 1, 1 << chunk)?;
+        }
+
+        anyhow::Ok(())
+    }
+
+    /// Fails unless `symbol` is in the alphabet: the byte values, plus [`EOF_SYMBOL`] unless the
+    /// stream is in [`Termination::Length`] mode, where the length marks the end and there's no EOF.
+    fn check_symbol(&self, symbol: usize) -> Result<()> {
+        if self.termination == Termination::Length {
+            anyhow::ensure!(
+                symbol <= MAX_DATA_SYMBOL,
+                "symbol {symbol} is outside the alphabet of a length terminated stream, which ends \
+                 with {MAX_DATA_SYMBOL}"
+            );
+        }
+        anyhow::ensure!(
+            symbol <= EOF_SYMBOL,
+            "symbol {symbol} is outside the alphabet, which ends with EOF at {EOF_SYMBOL}"
+        );
+
+        anyhow::Ok(())
+    }
+
+    pub(crate) fn encode_range(
+        &mut self,
+        symbol_low: usize,
+        symbol_high: usize,
+        total: usize,
+    ) -> Result<()> {
+        // After a failed write the stream is missing bytes, so narrowing the interval any further
+        // would only pretend to carry on.
+        self.bit_writer.ensure_usable()?;
+        self.ensure_unfinished()?;
+        CoderCore::<BITS>::check_total(total)?;
+        self.guard.unfinished = true;
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.symbols_encoded += 1;
+        }
+
+        self.core.narrow(symbol_low, symbol_high, total);
+
+        // A byte can only go out whole while no underflow bits are waiting to go after its first bit.
+        while self.byte_renormalization && self.underflow == 0 {
+            let Some(byte) = self.core.shift_byte() else {
+                break;
+            };
+            self.bit_writer.write_byte(byte)?;
+            #[cfg(feature = "metrics")]
+            {
+                self.metrics.renormalizations += 8;
+            }
+        }
+
+        let bit_writer = &mut self.bit_writer;
+        let _shifts = self
+            .core
+            .renormalize_into(&mut self.underflow, |bit| bit_writer.write(bit))?;
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.renormalizations += u64::from(_shifts);
+        }
+
+        anyhow::Ok(())
+    }
+
+    /// Encodes the EOF symbol, unless the encoder is in [`Termination::Length`] mode, and flushes the
+    /// interval. Even with no symbols before it this writes a complete stream, two bytes long with the
+    /// EOF symbol, which decodes to nothing.
+    ///
+    /// Ending a stream that has already been ended does nothing, rather than tacking another EOF and
+    /// flush onto it. Encoding more symbols, flushing a boundary or saving the state after it is an
+    /// error.
+    pub fn encode_end(&mut self) -> Result<()> {
+        if self.finished {
+            return anyhow::Ok(());
+        }
+
+        if self.termination == Termination::Eof {
+            self.encode_next(EOF_SYMBOL)?;
+        }
+
+        self.end()
+    }
+
+    /// Finalizes the stream without encoding the EOF symbol, which saves its cost when the decoder is
+    /// told the length some other way, e.g. with [`Decoder::decode_exact`]. Like
+    /// [`Encoder::encode_end`] it does nothing once the stream has been ended.
+    pub fn encode_end_without_eof(&mut self) -> Result<()> {
+        if self.finished {
+            return anyhow::Ok(());
+        }
+
+        self.end()
+    }
+
+    fn end(&mut self) -> Result<()> {
+        self.flush_interval()?;
+        self.finished = true;
+
+        anyhow::Ok(())
+    }
+
+    /// Fails once the stream has been ended, for everything that would add to it.
+    fn ensure_unfinished(&self) -> Result<()> {
+        anyhow::ensure!(!self.finished, "the stream has already been ended");
+
+        anyhow::Ok(())
+    }
+
+    /// Ends the current interval at a byte boundary, like [`Encoder::encode_end_without_eof`], then
+    /// starts a fresh one right after it. The model carries on, so this costs the two or so bytes of the
+    /// flush, not a fresh start for the statistics.
+    ///
+    /// The decoder has to call [`Decoder::resync_at_boundary`] after the same symbol. A
+    /// [`Encoder::checkpoint`] taken here starts at a whole byte and depends on nothing before it, so
+    /// after corruption [`Decoder::at_checkpoint`] can pick up again from the next boundary.
+    pub fn flush_boundary(&mut self) -> Result<()> {
+        self.ensure_unfinished()?;
+        self.flush_interval()?;
+
+        self.core.reset();
+        self.underflow = 0;
+
+        anyhow::Ok(())
+    }
+
+    /// Writes out enough bits to pin down a value inside the current interval, then pads to a byte.
+    fn flush_interval(&mut self) -> Result<()> {
+        self.bit_writer.ensure_usable()?;
+
+        let second_bit = self.core.low & CoderCore::<BITS>::SECOND_MSB_MASK != 0;
+        self.underflow += 1;
+        self.bit_writer.write(second_bit)?;
+
+        while self.underflow > 0 {
+            self.underflow -= 1;
+            self.bit_writer.write(!second_bit)?;
+        }
+
+        self.bit_writer.flush()?;
+        self.guard.unfinished = false;
+
+        anyhow::Ok(())
+    }
+
+    /// Drops the encoder without finishing its stream and without the warning, for output that's
+    /// being thrown away anyway.
+    pub fn abandon(mut self) {
+        self.guard.unfinished = false;
+    }
+
+    /// Finalizes the stream like [`Encoder::encode_end`] and hands back the writer, e.g. to append a
+    /// trailer after the compressed data.
+    pub fn finish(mut self) -> Result<&'a mut T> {
+        // Checked before ending the stream, so the wrong call leaves the encoder's output untouched.
+        anyhow::ensure!(
+            !self.bit_writer.owns_writer(),
+            "encoder owns its writer, use into_inner"
+        );
+        self.encode_end()?;
+
+        match self.bit_writer.into_inner()? {
+            Handle::Borrowed(writer) => anyhow::Ok(writer),
+            Handle::Owned(_) => unreachable!("checked above"),
+        }
+    }
+
+    /// Finalizes the stream like [`Encoder::encode_end`] and returns the writer of an encoder created
+    /// with [`Encoder::new_owned`].
+    pub fn into_inner(mut self) -> Result<T> {
+        anyhow::ensure!(
+            self.bit_writer.owns_writer(),
+            "encoder borrows its writer, use finish"
+        );
+        self.encode_end()?;
+
+        match self.bit_writer.into_inner()? {
+            Handle::Owned(writer) => anyhow::Ok(writer),
+            Handle::Borrowed(_) => unreachable!("checked above"),
+        }
+    }
+}
+
+/// The part of [`Encoder`]'s interface that doesn't depend on the writer, as an object safe trait, so
+/// encoders over different writers and register widths can be kept together as `Box<dyn DynEncoder>`.
+/// The writer still has to implement `Debug`; put a `Box<dyn Write>` in a newtype that does.
+pub trait DynEncoder {
+    /// See [`Encoder::encode_next`].
+    fn encode_next(&mut self, symbol: usize) -> Result<()>;
+
+    /// See [`Encoder::encode_slice`].
+    fn encode_slice(&mut self, bytes: &[u8]) -> Result<()>;
+
+    /// See [`Encoder::bits_written`].
+    fn bits_written(&self) -> u64;
+
+    /// Finalizes the stream like [`Encoder::encode_end`] and drops the encoder, releasing the writer.
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+impl<T: Write + fmt::Debug, const BITS: u32> DynEncoder for Encoder<'_, T, BITS> {
+    fn encode_next(&mut self, symbol: usize) -> Result<()> {
+        Encoder::encode_next(self, symbol)
+    }
+
+    fn encode_slice(&mut self, bytes: &[u8]) -> Result<()> {
+        Encoder::encode_slice(self, bytes)
+    }
+
+    fn bits_written(&self) -> u64 {
+        Encoder::bits_written(self)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.encode_end()
+    }
+}
+
+impl Encoder<'static, Vec<u8>> {
+    /// Creates an encoder that writes into a `Vec` it owns, for the common in-memory case. Get the
+    /// bytes with [`Encoder::into_inner`]. Output already reaches the `Vec` in batches, so this is
+    /// about convenience rather than speed.
+    pub fn new_vec() -> Encoder<'static, Vec<u8>> {
+        Encoder::new_owned(Vec::new())
+    }
+}
+
+/// How many input bytes an [`EncodedBytes`] encodes at a time while no output is pending.
+const LAZY_CHUNK: usize = 64;
+
+/// Encodes the bytes of an iterator lazily, yielding the stream a byte at a time as it's produced
+/// instead of collecting it, e.g. to forward it over a socket. Each byte pulled encodes just enough
+/// of the input to complete it, and once the input runs out the stream is finished with
+/// [`Encoder::encode_end`]. The stream is the same as encoding all of the input in one go.
+///
+/// An error from the encoder is yielded once, and ends the stream.
+#[derive(Debug)]
+pub struct EncodedBytes<I> {
+    input: I,
+    /// `None` once the stream is finished, or has failed.
+    encoder: Option<Encoder<'static, Vec<u8>>>,
+    /// What the encoder has written that hasn't been yielded yet, from `position` on.
+    pending: Vec<u8>,
+    position: usize,
+}
+
+impl<I: Iterator<Item = u8>> EncodedBytes<I> {
+    pub fn new<T: IntoIterator<IntoIter = I>>(input: T) -> EncodedBytes<I> {
+        EncodedBytes {
+            input: input.into_iter(),
+            encoder: Some(Encoder::new_vec()),
+            pending: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Encodes whatever input is left and returns the rest of the stream, the tail included, for when
+    /// there's no point in pulling it a byte at a time any more.
+    pub fn finish(mut self) -> Result<Vec<u8>> {
+        let mut rest = self.pending.split_off(self.position);
+        if let Some(mut encoder) = self.encoder.take() {
+            encoder.encode_iter(&mut self.input)?;
+            rest.extend(encoder.into_inner()?);
+        }
+
+        anyhow::Ok(rest)
+    }
+
+    /// Encodes more input until the encoder writes something, finishing the stream once the input is
+    /// used up. Leaves `pending` empty only when the stream is complete.
+    fn refill(&mut self) -> Result<()> {
+        self.pending.clear();
+        self.position = 0;
+
+        while let Some(encoder) = &mut self.encoder {
+            let mut chunk = [0; LAZY_CHUNK];
+            let mut n = 0;
+            for (slot, b) in chunk.iter_mut().zip(&mut self.input) {
+                *slot = b;
+                n += 1;
+            }
+
+            if n > 0 {
+                encoder.encode_chunk(&chunk[..n])?;
+            } else {
+                self.pending = self.encoder.take().unwrap().into_inner()?;
+            }
+
+            if let Some(written) = self
+                .encoder
+                .as_mut()
+                .and_then(|e| e.bit_writer.writer_mut())
+            {
+                core::mem::swap(&mut self.pending, written);
+            }
+            if !self.pending.is_empty() {
+                break;
+            }
+        }
+
+        anyhow::Ok(())
+    }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for EncodedBytes<I> {
+    type Item = Result<u8>;
+
+    fn next(&mut self) -> Option<Result<u8>> {
+        if self.position == self.pending.len() {
+            if let Err(e) = self.refill() {
+                if let Some(encoder) = self.encoder.take() {
+                    encoder.abandon();
+                }
+                return Some(Err(e));
+            }
+        }
+
+        let b = *self.pending.get(self.position)?;
+        self.position += 1;
+
+        Some(Ok(b))
+    }
+}
+
+impl<'s> Decoder<'s, &'s [u8]> {
+    /// Creates a decoder reading straight from an in-memory stream, e.g. one from
+    /// [`Encoder::new_vec`], without wrapping it in a `Cursor`. The slice is read through the same
+    /// statically dispatched [`BitReader`] as any other reader; decoding is bound by the model rather
+    /// than by fetching bytes, so a reader specialized for slices wouldn't be any faster.
+    pub fn from_slice(input: &'s [u8]) -> Result<Decoder<'s, &'s [u8]>> {
+        Decoder::with_bit_reader(
+            BitReader::with_handle(Handle::Owned(input)),
+            SymbolTable::new(),
+        )
+    }
+}
+
+impl<'a, T: Read + fmt::Debug> Decoder<'a, T> {
+    pub fn new(reader: &mut T) -> Result<Decoder<'_, T>> {
+        Self::with_model(reader, SymbolTable::new())
+    }
+
+    /// Starts decoding from the same model the stream was encoded with via [`Encoder::with_model`].
+    pub fn with_model(reader: &mut T, model: SymbolTable) -> Result<Decoder<'_, T>> {
+        Decoder::with_bit_reader(BitReader::new(reader), model)
+    }
+
+    /// Starts decoding a stream written by [`Encoder::with_initial_counts`] with the same counts.
+    pub fn with_initial_counts(reader: &'a mut T, counts: &[usize; 257]) -> Result<Decoder<'a, T>> {
+        Self::with_model(reader, SymbolTable::with_initial_counts(counts)?)
+    }
+
+    /// Like [`Decoder::new`], but every symbol decoded is also fed to a shadow encoder, which checks it
+    /// takes the same interval in the encoder's model and writes the bits that were read, failing with
+    /// [`AeError::CorruptStream`] at the first one that doesn't. After the EOF symbol the encoder's
+    /// flush is checked the same way. This about doubles the work, for data where a decode has to be
+    /// consistent with its encode.
+    ///
+    /// The check catches the coder or the model going wrong at the symbol where it does. A stream
+    /// corrupted in transit still decodes consistently, only to garbage, so that's caught where the
+    /// garbage ends and the stream doesn't end the way an encoder would have ended it, and not every
+    /// time; pair it with a checksum for that.
+    pub fn new_verified(reader: &mut T) -> Result<Decoder<'_, T>> {
+        let mut decoder = Decoder::unprimed(BitReader::new(reader), SymbolTable::new());
+        decoder.verifier = Some(Box::new(Verifier::new(SymbolTable::new())));
+
+        decoder.fill_code()
+    }
+
+    /// Starts decoding where `reader` is, e.g. after a header parsed with it, bits it has already
+    /// buffered included. [`Decoder::bytes_consumed`] still counts from where `reader` started, so it
+    /// takes in the header too.
+    pub fn from_bit_reader(reader: BitReader<'a, T>) -> Result<Decoder<'a, T>> {
+        Decoder::with_bit_reader(reader, SymbolTable::new())
+    }
+
+    /// Creates a decoder that owns its reader. Get the reader back with [`Decoder::into_inner`].
+    pub fn new_owned(reader: T) -> Result<Decoder<'static, T>> {
+        Decoder::with_bit_reader(
+            BitReader::with_handle(Handle::Owned(reader)),
+            SymbolTable::new(),
+        )
+    }
+}
+
+impl<'a, T: Read + fmt::Debug, const BITS: u32> Decoder<'a, T, BITS> {
+    /// Starts decoding a stream written by [`Encoder::with_register_bits`] with the same `BITS` and
+    /// model.
+    pub fn with_register_bits(
+        reader: &'a mut T,
+        model: SymbolTable,
+    ) -> Result<Decoder<'a, T, BITS>> {
+        Decoder::with_bit_reader(BitReader::new(reader), model)
+    }
+
+    /// Returns the reader of a decoder created with [`Decoder::new_owned`]. The decoder reads ahead of
+    /// the symbols it has returned by its register width, so the reader is positioned up to `BITS / 8`
+    /// bytes past them; see [`Decoder::bytes_consumed`] for where the stream actually ended.
+    pub fn into_inner(self) -> Result<T> {
+        match self.bit_reader.into_inner() {
+            Handle::Owned(reader) => anyhow::Ok(reader),
+            Handle::Borrowed(_) => anyhow::bail!("decoder borrows its reader"),
+        }
+    }
+
+    /// Length in bytes of the stream decoded so far, once its last symbol (usually EOF) has been
+    /// decoded. The decoder reads a register's worth of bytes further ahead than that, four with the
+    /// default 32 bit registers, so when several streams are concatenated this is where the next one
+    /// starts, e.g. for seeking the reader back to it.
+    pub fn bytes_consumed(&self) -> u64 {
+        // The encoder writes one bit for every shift of the interval plus two to finish it off, while
+        // the decoder reads a whole register up front and then one bit per shift.
+        let stream_bits = self.bit_reader.bits_read() - BITS as u64 + 2;
+
+        stream_bits.div_ceil(8)
+    }
+
+    /// Starts decoding in the middle of a stream, at a [`Checkpoint`] its encoder recorded. `reader`
+    /// has to be positioned at [`Checkpoint::byte_offset`] into the stream, and the next symbol decoded
+    /// is the one encoded right after the checkpoint.
+    pub fn at_checkpoint(
+        reader: &'a mut T,
+        checkpoint: &Checkpoint<BITS>,
+    ) -> Result<Decoder<'a, T, BITS>> {
+        let mut decoder = Decoder::unprimed(BitReader::new(reader), checkpoint.model.clone());
+        decoder.core.high = checkpoint.high;
+        decoder.core.low = checkpoint.low;
+
+        let skipped = checkpoint.byte_offset() * 8;
+        decoder.bit_reader.skipped(skipped);
+        for _ in skipped..checkpoint.bit_offset {
+            decoder.read_bit()?;
+        }
+
+        // With underflow pending the encoder hasn't written the interval's top bit yet. It will be
+        // followed by one opposite bit per underflow, which the decoder takes out of `code` as it
+        // goes, so they're skipped here too.
+        decoder.code = decoder.read_bit()? as u64;
+        for _ in 0..checkpoint.underflow {
+            decoder.read_bit()?;
+        }
+        for _ in 1..BITS {
+            decoder.code = CoderCore::<BITS>::push_bit(decoder.code, decoder.read_bit()?);
+        }
+
+        anyhow::Ok(decoder)
+    }
+
+    fn with_bit_reader(
+        bit_reader: BitReader<'a, T>,
+        model: SymbolTable,
+    ) -> Result<Decoder<'a, T, BITS>> {
+        Decoder::unprimed(bit_reader, model).fill_code()
+    }
+
+    /// Reads the first `BITS` bits of the stream into the code register.
+    fn fill_code(mut self) -> Result<Decoder<'a, T, BITS>> {
+        for _ in 0..BITS {
+            self.code = CoderCore::<BITS>::push_bit(self.code, self.read_bit()?);
+        }
+
+        anyhow::Ok(self)
+    }
+
+    fn unprimed(bit_reader: BitReader<'a, T>, model: SymbolTable) -> Decoder<'a, T, BITS> {
+        Decoder {
+            core: CoderCore::new(),
+            eof_bits: 0,
+            termination: Termination::Eof,
+            remaining: None,
+            max_output: u64::MAX,
+            #[cfg(feature = "sync-check")]
+            model_trace: None,
+            #[cfg(feature = "sync-check")]
+            symbols_decoded: 0,
+            verifier: None,
+            symbols: model,
+            bit_reader,
+            code: 0,
+        }
+    }
+
+    /// Reads the next bit of the stream, or a 1 once it has ended.
+    ///
+    /// The value of the made up bits doesn't matter. The encoder's flush picks the quarter of the
+    /// register that sits inside the final interval, `01` or `10`, and writes those two bits plus any
+    /// pending underflow. Whatever follows them, the zero padding of the last byte or these ones, the
+    /// code stays inside that quarter and so inside the interval.
+    ///
+    /// A well formed stream ends with two bits more than the decoder has shifted out, so decoding all
+    /// of it never takes more than `BITS - 2` made up bits. Needing more means the stream was
+    /// cut short, and going on would only produce garbage symbols.
+    fn read_bit(&mut self) -> Result<bool> {
+        let bit = match self.bit_reader.read()? {
+            ReadResult::Bit(r) => r,
+            ReadResult::EOF => {
+                self.eof_bits += 1;
+                if self.eof_bits > BITS - 2 {
+                    return Err(AeError::UnexpectedEof.into());
+                }
+
+                true
+            }
+        };
+        if let Some(verifier) = &mut self.verifier {
+            verifier.stream.push_back(bit);
+        }
+
+        anyhow::Ok(bit)
+    }
+
+    /// Sets how the stream is terminated, which has to match the encoder. In [`Termination::Length`]
+    /// mode [`Decoder::decode_to_vec`] needs the length from [`Decoder::with_length`].
+    pub fn with_termination(mut self, termination: Termination) -> Self {
+        self.termination = termination;
+        self
+    }
+
+    /// Switches to [`Termination::Length`] mode with `length` bytes in the stream. After that many
+    /// [`Decoder::decode_next`] returns the EOF symbol without reading further, and finding an EOF
+    /// symbol before then is an error.
+    pub fn with_length(mut self, length: u64) -> Self {
+        self.termination = Termination::Length;
+        self.remaining = Some(length);
+        self
+    }
+
+    /// Makes [`Decoder::decode_to_vec`] fail with [`AeError::OutputTooLarge`] rather than return more
+    /// than `bytes`. A few bytes of stream can decode to an enormous output, so set this when the
+    /// stream isn't trusted.
+    pub fn with_max_output_bytes(mut self, bytes: u64) -> Self {
+        self.max_output = bytes;
+        self
+    }
+
+    /// Checks the model against the trace an encoder recorded with [`Encoder::model_trace`] after
+    /// every symbol, so a change that updates the two models differently fails at the first symbol
+    /// where they part ways instead of as garbage output further on.
+    #[cfg(feature = "sync-check")]
+    pub fn with_model_trace(mut self, trace: Vec<usize>) -> Self {
+        self.model_trace = Some(trace);
+        self
+    }
+
+    #[cfg(feature = "sync-check")]
+    fn check_model_trace(&mut self) -> Result<()> {
+        let index = self.symbols_decoded;
+        self.symbols_decoded += 1;
+
+        let Some(trace) = &self.model_trace else {
+            return anyhow::Ok(());
+        };
+        let expected = trace.get(index).copied();
+        anyhow::ensure!(
+            expected == Some(self.symbols.symbol_count),
+            "model out of sync at symbol {index}: decoder total {}, encoder total {expected:?}",
+            self.symbols.symbol_count
+        );
+
+        anyhow::Ok(())
+    }
+
+    pub fn decode_next(&mut self) -> Result<usize> {
+        if self.remaining == Some(0) {
+            return anyhow::Ok(EOF_SYMBOL);
+        }
+
+        let cumulative_value = self.decode_target(self.symbols.symbol_count)?;
+
+        let (symbol, symbol_low, symbol_high) = self.symbols.find_symbol(cumulative_value)?;
+        if let Some(verifier) = &mut self.verifier {
+            verifier.check_model(symbol, symbol_low, symbol_high, self.symbols.symbol_count)?;
+        }
+
+        self.decode_range(symbol_low, symbol_high, self.symbols.symbol_count)?;
+        if symbol == EOF_SYMBOL {
+            if let Some(verifier) = &mut self.verifier {
+                verifier.finish()?;
+            }
+        }
+
+        // We want to update our probability model now.
+        self.symbols
+            .update_within(symbol, CoderCore::<BITS>::MODEL_LIMIT);
+        #[cfg(feature = "sync-check")]
+        self.check_model_trace()?;
+
+        if let Some(remaining) = &mut self.remaining {
+            anyhow::ensure!(
+                symbol != EOF_SYMBOL,
+                "stream ended with {remaining} bytes still expected"
+            );
+            *remaining -= 1;
+        }
+
+        anyhow::Ok(symbol)
+    }
+
+    /// Same as [`Decoder::decode_next`], but also returns how many bits of the stream the symbol
+    /// consumed, i.e. how many were shifted out of the code register while renormalizing after it.
+    /// Over a whole stream these add up to its length in bits, less the two that finish it off and
+    /// the padding to a byte.
+    pub fn decode_next_with_bits(&mut self) -> Result<(usize, u32)> {
+        let before = self.bit_reader.bits_read();
+        let symbol = self.decode_next()?;
+
+        anyhow::Ok((symbol, (self.bit_reader.bits_read() - before) as u32))
+    }
+
+    /// The model as it stands after the symbols decoded so far.
+    pub fn model(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// The model, for mirroring a change made through [`Encoder::model_mut`] after the same symbol.
+    pub fn model_mut(&mut self) -> &mut SymbolTable {
+        &mut self.symbols
+    }
+
+    /// A copy of the model as it stands after the symbols decoded so far.
+    pub fn clone_model(&self) -> SymbolTable {
+        self.symbols.clone()
+    }
+
+    /// Mirrors an [`Encoder::reset_model`] after the same symbol.
+    pub fn reset_model(&mut self) {
+        self.symbols.reset();
+    }
+
+    /// Trains the model on `sample` without reading anything, matching an [`Encoder::prime`] with the
+    /// same sample at the same point of the stream.
+    pub fn prime(&mut self, sample: &[u8]) {
+        for &b in sample {
+            self.symbols
+                .update_within(b as usize, CoderCore::<BITS>::MODEL_LIMIT);
+        }
+    }
+
+    /// Follows the encoder across an [`Encoder::flush_boundary`], which has to have come right after
+    /// the symbols decoded so far. Drops what's left of the interval and restarts at the next byte.
+    pub fn resync_at_boundary(&mut self) -> Result<()> {
+        // The encoder's flush ended the interval two bits past the ones the decoder has shifted out,
+        // then padded to a byte. The code register already reads that far ahead and more, so shifting
+        // in the few bits up to the boundary lines it up with the start of the next interval.
+        if let Some(verifier) = &mut self.verifier {
+            verifier.finish()?;
+        }
+        let boundary = self.bytes_consumed() * 8;
+        let shifted_out = self.bit_reader.bits_read() - BITS as u64;
+        for _ in shifted_out..boundary {
+            self.code = CoderCore::<BITS>::push_bit(self.code, self.read_bit()?);
+        }
+
+        self.core.reset();
+
+        anyhow::Ok(())
+    }
+
+    /// Decodes the next byte, or `None` at the end of the stream, so callers never see the EOF symbol.
+    pub fn decode_byte(&mut self) -> Result<Option<u8>> {
+        let symbol = self.decode_next()?;
+
+        anyhow::Ok((symbol != EOF_SYMBOL).then_some(symbol as u8))
+    }
+
+    /// Decodes bytes until the EOF symbol, or until the length given to [`Decoder::with_length`]. Fails
+    /// with [`AeError::OutputTooLarge`] past the limit set by [`Decoder::with_max_output_bytes`].
+    pub fn decode_to_vec(&mut self) -> Result<Vec<u8>> {
+        self.decode_to_vec_with_progress(usize::MAX, |_| ())
+    }
+
+    /// Like [`Decoder::decode_to_vec`], calling `progress` with the number of bytes decoded so far
+    /// after every `every` of them. Only observes the decode, so the output is the same either way.
+    pub fn decode_to_vec_with_progress(
+        &mut self,
+        every: usize,
+        mut progress: impl FnMut(usize),
+    ) -> Result<Vec<u8>> {
+        anyhow::ensure!(
+            every > 0,
+            "progress must be reported every one or more bytes"
+        );
+        anyhow::ensure!(
+            self.termination == Termination::Eof || self.remaining.is_some(),
+            "a stream without an EOF symbol can only be decoded to the end with a known length"
+        );
+
+        let mut output = Vec::new();
+        // Counted down rather than a division per byte, `decode_to_vec` goes through here too.
+        let mut until_progress = every;
+        while let Some(b) = self.decode_byte()? {
+            if output.len() as u64 == self.max_output {
+                return Err(AeError::OutputTooLarge {
+                    limit: self.max_output,
+                }
+                .into());
+            }
+            output.push(b);
+            until_progress -= 1;
+            if until_progress == 0 {
+                progress(output.len());
+                until_progress = every;
+            }
+        }
+
+        anyhow::Ok(output)
+    }
+
+    /// Decodes bytes into `out` until the EOF symbol without allocating, returning how many were
+    /// written. A stream with more bytes than `out` can hold is an error; to decode a prefix of known
+    /// length use [`Decoder::decode_exact`] instead.
+    pub fn decode_into(&mut self, out: &mut [u8]) -> Result<usize> {
+        for (i, byte) in out.iter_mut().enumerate() {
+            match self.decode_byte()? {
+                Some(b) => *byte = b,
+                None => return anyhow::Ok(i),
+            }
+        }
+
+        anyhow::ensure!(
+            self.decode_byte()?.is_none(),
+            "stream holds more than the {} bytes the output has room for",
+            out.len()
+        );
+
+        anyhow::Ok(out.len())
+    }
+
+    /// Decodes exactly `out.len()` bytes. The stream doesn't need an EOF symbol after them, but running
+    /// into one before `out` is full is an error.
+    pub fn decode_exact(&mut self, out: &mut [u8]) -> Result<()> {
+        for (i, byte) in out.iter_mut().enumerate() {
+            let symbol = self.decode_next()?;
+            anyhow::ensure!(
+                symbol != EOF_SYMBOL,
+                "stream ended after {i} of {} bytes",
+                out.len()
+            );
+            *byte = symbol as u8;
+        }
+
+        anyhow::Ok(())
+    }
+
+    /// Decodes `n` symbols and throws them away, e.g. to get past the start of a stream without a
+    /// checkpoint to seek to. The model still learns from them, so decoding carries on as if they had
+    /// been returned. Running into EOF before all `n` is an error.
+    pub fn skip(&mut self, n: usize) -> Result<()> {
+        for i in 0..n {
+            anyhow::ensure!(
+                self.decode_next()? != EOF_SYMBOL,
+                "stream ended after skipping {i} of {n} symbols"
+            );
+        }
+
+        anyhow::Ok(())
+    }
+
+    /// Decodes a symbol written by [`Encoder::encode_extendable`], growing `model` in the same way the
+    /// encoder did when an escape is encountered.
+    pub fn decode_extendable(&mut self, model: &mut ExtendableModel) -> Result<usize> {
+        let cumulative_value = self.decode_target(model.total())?;
+        let (slot, symbol_low, symbol_high) = model.find_slot(cumulative_value);
+        self.decode_range(symbol_low, symbol_high, model.total())?;
+
+        if slot != model.escape_slot() {
+            model.increment_slot(slot, CoderCore::<BITS>::MODEL_LIMIT);
+            return anyhow::Ok(model.symbol_at(slot));
+        }
+
+        let symbol = self.decode_target(model.capacity())?;
+        self.decode_range(symbol, symbol + 1, model.capacity())?;
+        model.extend(symbol, CoderCore::<BITS>::MODEL_LIMIT)?;
+
+        anyhow::Ok(symbol)
+    }
+
+    /// Decodes the next run written by [`Encoder::encode_runs`]: a byte and how many times in a row
+    /// it occurs, or `None` at the end of the stream. A run may come back in pieces, the same byte
+    /// twice in a row.
+    pub fn decode_run(&mut self, model: &mut RunModel) -> Result<Option<(u8, u64)>> {
+        let symbol = self.decode_extendable(&mut model.symbols)?;
+        match symbol {
+            EOF_SYMBOL => anyhow::Ok(None),
+            REPEAT_SYMBOL => {
+                let Some(b) = model.previous.take() else {
+                    return Err(AeError::CorruptStream.into());
+                };
+
+                anyhow::Ok(Some((b, self.decode_repeats()?)))
+            }
+            b => {
+                model.previous = Some(b as u8);
+
+                anyhow::Ok(Some((b as u8, 1)))
+            }
+        }
+    }
+
+    /// Decodes a whole stream written by [`Encoder::encode_runs`]. A few bytes of stream can claim a
+    /// run of any length, so this checks the total against [`Decoder::with_max_output_bytes`] before
+    /// it allocates.
+    pub fn decode_runs_to_vec(&mut self, model: &mut RunModel) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        while let Some((b, count)) = self.decode_run(model)? {
+            if count > self.max_output - output.len() as u64 {
+                return Err(AeError::OutputTooLarge {
+                    limit: self.max_output,
+                }
+                .into());
+            }
+            let count = usize::try_from(count)
+                .map_err(|_| anyhow::anyhow!("run of {count} bytes doesn't fit in memory"))?;
+            output.resize(output.len() + count, b);
+        }
+
+        anyhow::Ok(output)
+    }
+
+    fn decode_repeats(&mut self) -> Result<u64> {
+        let length = self.decode_raw_bits(6)? as u32 + 1;
+        let value = 1 << (length - 1) | self.decode_raw_bits(length - 1)?;
+
+        value
+            .checked_add(MIN_REPEATS - 1)
+            .ok_or_else(|| AeError::CorruptStream.into())
+    }
+
+    /// Decodes a symbol written by [`Encoder::encode_context`], updating `model` the same way the
+    /// encoder did.
+    pub fn decode_context(&mut self, model: &mut ContextModel) -> Result<usize> {
+        let total = model.context_total();
+        let cumulative_value = self.decode_target(total)?;
+
+        let symbol = match model.find_in_context(cumulative_value) {
+            Some((symbol, symbol_low, symbol_high)) => {
+                self.decode_range(symbol_low, symbol_high, total)?;
+                symbol
+            }
+            None => {
+                let (escape_low, escape_high) = model.escape_interval();
+                self.decode_range(escape_low, escape_high, total)?;
+
+                let total = model.fallback_total();
+                let cumulative_value = self.decode_target(total)?;
+                let (symbol, symbol_low, symbol_high) = model.find_in_fallback(cumulative_value);
+                self.decode_range(symbol_low, symbol_high, total)?;
+                symbol
+            }
+        };
+        model.update(symbol, CoderCore::<BITS>::MODEL_LIMIT);
+
+        anyhow::Ok(symbol)
+    }
+
+    /// Decodes a symbol written by [`Encoder::encode_with`] with the same `model`, updating it the way
+    /// the encoder did.
+    pub fn decode_with<C: Count>(&mut self, model: &mut SymbolTable<C>) -> Result<usize> {
+        let cumulative_value = self.decode_target(model.symbol_count)?;
+        let (symbol, symbol_low, symbol_high) = model.find_symbol(cumulative_value)?;
+        self.decode_range(symbol_low, symbol_high, model.symbol_count)?;
+        model.update_within(symbol, CoderCore::<BITS>::MODEL_LIMIT);
+
+        anyhow::Ok(symbol)
+    }
+
+    /// Decodes `count` bits written by [`Encoder::encode_raw_bits`], into the low bits of the result.
+    pub fn decode_raw_bits(&mut self, count: u32) -> Result<u64> {
+        anyhow::ensure!(
+            count <= u64::BITS,
+            "can't decode {count} raw bits, at most 64"
+        );
+
+        let mut value = 0u64;
+        let mut remaining = count;
+        while remaining > 0 {
+            let chunk = remaining.min(RAW_CHUNK_BITS).min(BITS - 2);
+            remaining -= chunk;
+            let bits = self.decode_target(1 << chunk)?;
+            self.decode_range(bits, bits + 1, 1 << chunk)?;
+            value = value << chunk | bits as u64;
+        }
+
+        anyhow::Ok(value)
+    }
+
+    pub(crate) fn decode_target(&self, total: usize) -> Result<usize> {
+        CoderCore::<BITS>::check_total(total)?;
+
+        // Decoding from the start of a stream keeps the code inside the interval whatever the bits
+        // are, but a decoder put at a checkpoint of some other stream can find it outside, and the
+        // subtraction below would wrap.
+        if !(self.core.low..=self.core.high).contains(&self.code) {
+            return Err(AeError::CorruptStream.into());
+        }
+
+        // Decoding is almost identical to encoding except that we have a stream of already encoded bits that we have to deal with.
+        let range = self.core.range();
+
+        // This is essentially the major difference between encoding and decoding.
+        // In decoding we determine the symbol from the already encoded stream by where it lies in the range between high and low.
+        // in encoding we calculate the range directly as we are given the symbol.
+        let offset = self.code - self.core.low;
+        let target = if BITS <= u32::BITS {
+            ((offset + 1) * total as u64 - 1) / range as u64
+        } else {
+            (((offset as u128 + 1) * total as u128 - 1) / range) as u64
+        };
+
+        anyhow::Ok(target as usize)
+    }
+
+    pub(crate) fn decode_range(
+        &mut self,
+        symbol_low: usize,
+        symbol_high: usize,
+        total: usize,
+    ) -> Result<()> {
+        // The following is identical to encoding.
+        self.core.narrow(symbol_low, symbol_high, total);
+        if let Some(verifier) = &mut self.verifier {
+            verifier.encode(symbol_low, symbol_high, total)?;
+        }
+
+        loop {
+            match self.core.shift() {
+                // Since we are decoding then there's nothing to do here, the bit is already in the code.
+                Shift::Settled(_) => {}
+                // More precision hacks.
+                Shift::Underflow => self.code -= CoderCore::<BITS>::SECOND_MSB_MASK,
+                // Can't do anything.
+                Shift::Done => break,
+            }
+
+            // This is the other major difference from encoding.
+            // This is just reading the stream of bits from the encoded value, we don't have this while encoding.
+            self.code = CoderCore::<BITS>::push_bit(self.code, self.read_bit()?);
+
+            // The next shifted in MSBs might also match, so we loop.
+        }
+
+        anyhow::Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::renormalize;
+    use super::BitReader;
+    use super::BitWriter;
+    use super::Checkpoint;
+    use super::CoderCore;
+    use super::Decoder;
+    use super::DynEncoder;
+    use super::EncodedBytes;
+    use super::Encoder;
+    use super::EncoderState;
+    use super::Termination;
+    use crate::error::AeError;
+    use crate::model::{
+        AdaptiveModel, ContextModel, ExtendableModel, ModelSnapshot, RunModel, SymbolTable,
+        EOF_SYMBOL, MAX_DATA_SYMBOL, MAX_STATIC_TOTAL,
+    };
+    use crate::xorshift::Xorshift;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn can_read_and_write_same_bytes(input: Vec<u8>) {
+        let mut output = Vec::new();
+        let mut output2 = Vec::new();
+
+        {
+            let mut encoder = Encoder::new(&mut output);
+            for s in &input {
+                encoder.encode_next(*s as usize).unwrap();
+            }
+            encoder.encode_end().unwrap();
+        }
+
+        {
+            let mut cursor = std::io::Cursor::new(&output);
+            let mut decoder = Decoder::new(&mut cursor).unwrap();
+
+            loop {
+                let s = decoder.decode_next().unwrap();
+
+                if s == EOF_SYMBOL {
+                    break;
+                }
+
+                output2.push(s as u8);
+            }
+        }
+
+        assert_eq!(input, output2);
+    }
+
+    #[test]
+    fn extendable_model_grows_identically_on_both_sides() {
+        let input = [0, 1, 2, 3, 3, 4, 1, 4, 5, 0, 5, 5, 2, 4];
+        let mut output = Vec::new();
+
+        {
+            let mut model = ExtendableModel::new(4, 16, 2).unwrap();
+            let mut encoder = Encoder::new(&mut output);
+            for &s in &input {
+                encoder.encode_extendable(&mut model, s).unwrap();
+            }
+            encoder.encode_end().unwrap();
+
+            assert_eq!(model.alphabet(), [0, 1, 2, 3, 4, 5]);
+        }
+
+        let mut model = ExtendableModel::new(4, 16, 2).unwrap();
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        let decoded = (0..input.len())
+            .map(|_| decoder.decode_extendable(&mut model).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(decoded, input);
+        assert_eq!(model.alphabet(), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn extendable_model_rejects_symbols_beyond_capacity() {
+        let mut output = Vec::new();
+        let mut model = ExtendableModel::new(4, 8, 1).unwrap();
+        let mut encoder = Encoder::new(&mut output);
+
+        assert!(encoder.encode_extendable(&mut model, 8).is_err());
+    }
+
+    #[test]
+    fn empty_models_are_rejected() {
+        // Without a symbol to escape to, decoding the escape would have nothing to narrow to.
+        assert!(ExtendableModel::new(0, 0, 1).is_err());
+        assert!(ExtendableModel::new(0, 1, 1).is_ok());
+
+        let input = [0xFF; 16];
+        let mut source = &input[..];
+        let decoder = Decoder::new(&mut source).unwrap();
+        assert!(decoder.decode_target(0).is_err());
+        let mut encoder = Encoder::new_vec();
+        assert!(encoder.encode_range(0, 0, 0).is_err());
+    }
+
+    #[quickcheck]
+    fn context_model_round_trips(input: Vec<u8>) {
+        let mut output = Vec::new();
+        {
+            let mut model = ContextModel::new();
+            let mut encoder = Encoder::new(&mut output);
+            for &b in &input {
+                encoder.encode_context(&mut model, b as usize).unwrap();
+            }
+            encoder.encode_context(&mut model, EOF_SYMBOL).unwrap();
+            encoder.encode_end_without_eof().unwrap();
+        }
+
+        let mut model = ContextModel::new();
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        let mut decoded = Vec::new();
+        loop {
+            match decoder.decode_context(&mut model).unwrap() {
+                EOF_SYMBOL => break,
+                s => decoded.push(s as u8),
+            }
+        }
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    fn context_model_beats_order_0_on_structured_text() {
+        let input = (0..400)
+            .map(|i| {
+                format!(
+                    "id={:04};state={};\n",
+                    i * 7,
+                    ["ok", "fail", "retry"][i % 3]
+                )
+            })
+            .collect::<String>()
+            .into_bytes();
+
+        let mut order_1 = Vec::new();
+        {
+            let mut model = ContextModel::new();
+            let mut encoder = Encoder::new(&mut order_1);
+            for &b in &input {
+                encoder.encode_context(&mut model, b as usize).unwrap();
+            }
+            encoder.encode_context(&mut model, EOF_SYMBOL).unwrap();
+            encoder.encode_end_without_eof().unwrap();
+        }
+        let mut order_0 = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut order_0);
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        assert!(
+            order_1.len() < order_0.len() * 3 / 4,
+            "order-1 {} bytes, order-0 {} bytes",
+            order_1.len(),
+            order_0.len()
+        );
+
+        let mut model = ContextModel::new();
+        let mut cursor = std::io::Cursor::new(&order_1);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        for &b in &input {
+            assert_eq!(decoder.decode_context(&mut model).unwrap(), b as usize);
+        }
+        assert_eq!(decoder.decode_context(&mut model).unwrap(), EOF_SYMBOL);
+    }
+
+    #[test]
+    fn symbols_past_eof_are_rejected() {
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output);
+
+        encoder.encode_next(MAX_DATA_SYMBOL).unwrap();
+        assert!(encoder.encode_next(EOF_SYMBOL + 1).is_err());
+        encoder.encode_end().unwrap();
+    }
+
+    #[test]
+    fn only_eof_terminated_streams_take_the_eof_symbol() {
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output).with_termination(Termination::Length);
+            encoder.encode_next(MAX_DATA_SYMBOL).unwrap();
+            let error = encoder.encode_next(EOF_SYMBOL).unwrap_err();
+            assert!(error.to_string().contains("length terminated"), "{error}");
+            let mut model = SymbolTable::new();
+            assert!(encoder.encode_with(&mut model, EOF_SYMBOL).is_err());
+            encoder.encode_end().unwrap();
+        }
+        let mut decoder = Decoder::from_slice(&output).unwrap().with_length(1);
+        assert_eq!(decoder.decode_to_vec().unwrap(), [MAX_DATA_SYMBOL as u8]);
+
+        // With the default termination EOF is a symbol like any other.
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_next(MAX_DATA_SYMBOL).unwrap();
+            encoder.encode_next(EOF_SYMBOL).unwrap();
+            encoder.encode_end_without_eof().unwrap();
+        }
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        assert_eq!(decoder.decode_next().unwrap(), MAX_DATA_SYMBOL);
+        assert_eq!(decoder.decode_next().unwrap(), EOF_SYMBOL);
+    }
+
+    #[test]
+    fn register_constants_match_the_register_width() {
+        assert_eq!(CoderCore::<32>::MAX, u32::MAX as u64);
+        assert_eq!(CoderCore::<32>::MSB_MASK, 0x80000000);
+        assert_eq!(CoderCore::<32>::SECOND_MSB_MASK, 0x40000000);
+        assert_eq!(CoderCore::<16>::MAX, u16::MAX as u64);
+        assert_eq!(CoderCore::<16>::MSB_MASK, 0x8000);
+        assert_eq!(CoderCore::<16>::MAX_TOTAL, 0x4000);
+        assert_eq!(CoderCore::<64>::MAX, u64::MAX);
+        assert_eq!(CoderCore::<64>::MSB_MASK.leading_zeros(), 0);
+        assert_eq!(CoderCore::<64>::SECOND_MSB_MASK.leading_zeros(), 1);
+        assert_eq!(
+            CoderCore::<64>::MSB_MASK
+                | CoderCore::<64>::SECOND_MSB_MASK
+                | (CoderCore::<64>::SECOND_MSB_MASK - 1),
+            u64::MAX
+        );
+    }
+
+    #[quickcheck]
+    fn decoding_carries_on_after_a_parsed_header(input: Vec<u8>) {
+        let mut output = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut output);
+            writer.write_bits(0b1010, 4).unwrap();
+            writer.write_bits(input.len() as u64 & 0xFFF, 12).unwrap();
+            writer.flush().unwrap();
+        }
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        // The header read pulls in the start of the body too, which the decoder has to pick up.
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut reader = BitReader::new(&mut cursor);
+        assert_eq!(reader.read_bits(4).unwrap(), 0b1010);
+        assert_eq!(reader.read_bits(12).unwrap(), input.len() as u64 & 0xFFF);
+        let mut decoder = Decoder::from_bit_reader(reader).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+        assert_eq!(decoder.bytes_consumed(), output.len() as u64);
+    }
+
+    fn encode_bytewise<const BITS: u32>(input: &[u8], bytewise: bool) -> Vec<u8> {
+        let mut output = Vec::new();
+        {
+            let mut encoder =
+                Encoder::<_, BITS>::with_register_bits(&mut output, SymbolTable::new())
+                    .with_byte_renormalization(bytewise);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        output
+    }
+
+    #[quickcheck]
+    fn byte_renormalization_writes_the_same_stream(input: Vec<u8>, skewed: bool) {
+        // Skewed input narrows the interval slowly and settles few bits per symbol, random input
+        // settles whole bytes at once.
+        let input = if skewed {
+            input.iter().map(|b| b % 3).collect()
+        } else {
+            input
+        };
+
+        let output = encode_bytewise::<32>(&input, true);
+        assert_eq!(output, encode_bytewise::<32>(&input, false));
+        assert_eq!(
+            encode_bytewise::<16>(&input, true),
+            encode_bytewise::<16>(&input, false)
+        );
+        assert_eq!(
+            encode_bytewise::<64>(&input, true),
+            encode_bytewise::<64>(&input, false)
+        );
+
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+    }
+
+    #[quickcheck]
+    fn verified_decoding_accepts_what_the_encoder_wrote(input: Vec<u8>) {
+        let (first, second) = input.split_at(input.len() / 2);
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(first).unwrap();
+            encoder.flush_boundary().unwrap();
+            encoder.encode_slice(second).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut reader = &output[..];
+        let mut decoder = Decoder::new_verified(&mut reader).unwrap();
+        let mut decoded = (0..first.len())
+            .map(|_| decoder.decode_byte().unwrap().unwrap())
+            .collect::<Vec<_>>();
+        decoder.resync_at_boundary().unwrap();
+        decoded.extend(decoder.decode_to_vec().unwrap());
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    fn verified_decoding_fails_at_the_inconsistent_symbol() {
+        let input = b"The quick brown fox jumps over the lazy dog. ".repeat(40);
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        let is_corrupt_at = |e: anyhow::Error, symbol: usize| {
+            assert_eq!(e.downcast_ref::<AeError>(), Some(&AeError::CorruptStream));
+            assert!(
+                e.to_string()
+                    .starts_with(&std::format!("decoded symbol {symbol} ")),
+                "{e:#}"
+            );
+        };
+
+        // A decoder whose model drifts from the encoder's goes on to decode garbage, the verified one
+        // stops at the first symbol coded with the drifted model.
+        let drift = |decoder: &mut Decoder<&[u8]>| {
+            for _ in 0..100 {
+                decoder.decode_next().unwrap();
+            }
+            decoder.model_mut().increment_symbol(b'q' as usize);
+            decoder.decode_next()
+        };
+        let mut reader = &output[..];
+        assert!(drift(&mut Decoder::new(&mut reader).unwrap()).is_ok());
+        let mut reader = &output[..];
+        is_corrupt_at(
+            drift(&mut Decoder::new_verified(&mut reader).unwrap()).unwrap_err(),
+            100,
+        );
+
+        // A flipped bit decodes consistently, here to 48 bytes of garbage and an EOF, which the plain
+        // decoder returns. The verified one fails at that EOF, which the stream doesn't end after.
+        let mut corrupt = output.clone();
+        corrupt[97 / 8] ^= 0x80 >> (97 % 8);
+        let mut reader = &corrupt[..];
+        let garbage = Decoder::new(&mut reader).unwrap().decode_to_vec().unwrap();
+        assert_eq!(garbage.len(), 48);
+        let mut reader = &corrupt[..];
+        let mut decoder = Decoder::new_verified(&mut reader).unwrap();
+        is_corrupt_at(decoder.decode_to_vec().unwrap_err(), garbage.len());
+    }
+
+    /// Renormalization written out plainly on `u32` registers, the way it's usually described.
+    fn reference_renormalize(
+        mut high: u32,
+        mut low: u32,
+        mut underflow: usize,
+    ) -> (u32, u32, usize, Vec<bool>) {
+        let mut bits = Vec::new();
+        loop {
+            if high >> 31 == low >> 31 {
+                let bit = high >> 31 == 1;
+                bits.push(bit);
+                bits.extend(core::iter::repeat_n(!bit, underflow));
+                underflow = 0;
+                high = high << 1 | 1;
+                low <<= 1;
+            } else if high >> 30 == 0b10 && low >> 30 == 0b01 {
+                underflow += 1;
+                high = high << 1 | 1 | 1 << 31;
+                low = low << 1 & !(1 << 31);
+            } else {
+                return (high, low, underflow, bits);
+            }
+        }
+    }
+
+    #[quickcheck]
+    fn renormalize_matches_the_reference(a: u32, b: u32, underflow: u8) {
+        let (high, low) = (a.max(b), a.min(b));
+        let (new_high, new_low, new_underflow, bits) =
+            renormalize::<32>(high as u64, low as u64, underflow as usize);
+
+        assert_eq!(
+            (new_high as u32, new_low as u32, new_underflow, bits),
+            reference_renormalize(high, low, underflow as usize)
+        );
+    }
+
+    #[quickcheck]
+    fn renormalize_matches_the_encoder(input: Vec<u8>) {
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output);
+        let mut model = SymbolTable::new();
+        let mut core = CoderCore::<32>::new();
+        let mut underflow = 0;
+        let mut bits = Vec::new();
+        for &b in &input {
+            encoder.encode_next(b as usize).unwrap();
+
+            let (low, high) = model.get_symbol(b as usize);
+            core.narrow(low, high, model.symbol_count);
+            model.update(b as usize);
+            let shifted;
+            (core.high, core.low, underflow, shifted) =
+                renormalize::<32>(core.high, core.low, underflow);
+            bits.extend(shifted);
+
+            assert_eq!((encoder.core.high, encoder.core.low), (core.high, core.low));
+            assert_eq!(encoder.underflow, underflow);
+            assert_eq!(encoder.bits_written(), bits.len() as u64);
+        }
+        encoder.encode_end().unwrap();
+        drop(encoder);
+
+        let mut reader = &output[..];
+        let mut reader = BitReader::new(&mut reader);
+        let written = (0..bits.len())
+            .map(|_| reader.read_bits(1).unwrap() == 1)
+            .collect::<Vec<_>>();
+        assert_eq!(written, bits);
+    }
+
+    #[test]
+    fn full_range_narrows_without_overflow() {
+        // At the start of a stream the interval is the whole register, one more value than the
+        // register itself can count. None of it may go through usize, which is 32 bits on some targets.
+        let mut core = CoderCore::<32>::new();
+        assert_eq!(core.range(), 1 << 32);
+        core.narrow(0, 257, 257);
+        assert_eq!((core.low, core.high), (0, u32::MAX as u64));
+        core.narrow(256, 257, 257);
+        assert_eq!(core.high, u32::MAX as u64);
+        assert_eq!(core.low, (1u64 << 32) * 256 / 257);
+
+        let mut core = CoderCore::<64>::new();
+        assert_eq!(core.range(), 1 << 64);
+        core.narrow(0, 1 << 30, 1 << 30);
+        assert_eq!((core.low, core.high), (0, u64::MAX));
+        core.narrow((1 << 30) - 1, 1 << 30, 1 << 30);
+        assert_eq!(core.high, u64::MAX);
+        assert_eq!(core.low, u64::MAX - ((1 << 34) - 1));
+    }
+
+    fn round_trip_with_width<const BITS: u32>(input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        {
+            let mut encoder =
+                Encoder::<_, BITS>::with_register_bits(&mut output, SymbolTable::new());
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder =
+            Decoder::<_, BITS>::with_register_bits(&mut cursor, SymbolTable::new()).unwrap();
+        let decoded = decoder.decode_to_vec().unwrap();
+        assert!(decoder.bytes_consumed() <= output.len() as u64);
+
+        decoded
+    }
+
+    #[quickcheck]
+    fn narrow_and_wide_registers_round_trip(input: Vec<u8>) {
+        assert_eq!(round_trip_with_width::<16>(&input), input);
+        assert_eq!(round_trip_with_width::<24>(&input), input);
+        assert_eq!(round_trip_with_width::<64>(&input), input);
+    }
+
+    #[test]
+    fn wide_registers_round_trip_skewed_input() {
+        // Long runs push the interval through many underflows, which is where the masking of the
+        // registers matters.
+        let input = (0..40_000u32)
+            .map(|i| if i % 97 == 0 { (i % 251) as u8 } else { b'a' })
+            .collect::<Vec<_>>();
+
+        assert_eq!(round_trip_with_width::<64>(&input), input);
+        assert_eq!(round_trip_with_width::<16>(&input), input);
+    }
+
+    #[test]
+    fn narrow_registers_limit_the_model_total() {
+        // The adaptive table's total grows by one per symbol, past what 16 bit registers can take,
+        // so its counts get halved whenever they would.
+        let input = [0; 20_000];
+        let mut output = Vec::new();
+        let mut encoder = Encoder::<_, 16>::with_register_bits(&mut output, SymbolTable::new());
+        encoder.encode_slice(&input).unwrap();
+        let total = encoder.model().symbol_count;
+        assert!(total <= CoderCore::<16>::MAX_TOTAL as usize, "{total}");
+        encoder.encode_end().unwrap();
+        drop(encoder);
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder =
+            Decoder::<_, 16>::with_register_bits(&mut cursor, SymbolTable::new()).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+
+        // 32 bit registers take as much as the table itself does, so nothing changes for them.
+        let mut encoder = Encoder::new_vec();
+        encoder.encode_slice(&input).unwrap();
+        assert_eq!(encoder.model().symbol_count, 257 + input.len());
+    }
+
+    #[test]
+    fn narrow_registers_limit_the_context_and_run_models() {
+        // Every byte after an `a` is a `b` and no two neighbours are the same, so both the context of
+        // `a` and the run model's counts grow past what 16 bit registers can take.
+        let input = b"ab".repeat(20_000);
+
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::<_, 16>::with_register_bits(&mut output, SymbolTable::new());
+            let mut model = ContextModel::new();
+            for &b in &input {
+                encoder.encode_context(&mut model, b as usize).unwrap();
+            }
+            encoder.encode_context(&mut model, EOF_SYMBOL).unwrap();
+            encoder.encode_end_without_eof().unwrap();
+        }
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder =
+            Decoder::<_, 16>::with_register_bits(&mut cursor, SymbolTable::new()).unwrap();
+        let mut model = ContextModel::new();
+        let mut decoded = Vec::new();
+        loop {
+            match decoder.decode_context(&mut model).unwrap() {
+                EOF_SYMBOL => break,
+                symbol => decoded.push(symbol as u8),
+            }
+        }
+        assert_eq!(decoded, input);
+
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::<_, 16>::with_register_bits(&mut output, SymbolTable::new());
+            let mut model = RunModel::new();
+            encoder.encode_runs(&mut model, &input).unwrap();
+            encoder.encode_runs_end(&mut model).unwrap();
+        }
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder =
+            Decoder::<_, 16>::with_register_bits(&mut cursor, SymbolTable::new()).unwrap();
+        assert_eq!(
+            decoder.decode_runs_to_vec(&mut RunModel::new()).unwrap(),
+            input
+        );
+    }
+
+    #[test]
+    fn streams_depend_on_the_register_width() {
+        let input = b"the same input coded with different register widths";
+        let mut narrow = Vec::new();
+        let mut wide = Vec::new();
+        {
+            let mut encoder = Encoder::<_, 16>::with_register_bits(&mut narrow, SymbolTable::new());
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        {
+            let mut encoder = Encoder::<_, 64>::with_register_bits(&mut wide, SymbolTable::new());
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        // A decoder of another width reads garbage or fails, it never gets the input back.
+        let mut cursor = std::io::Cursor::new(&narrow);
+        let decoded = Decoder::new(&mut cursor).and_then(|mut d| d.decode_to_vec());
+        assert!(decoded.map_or(true, |d| d != input));
+        let mut cursor = std::io::Cursor::new(&wide);
+        let decoded = Decoder::new(&mut cursor).and_then(|mut d| d.decode_to_vec());
+        assert!(decoded.map_or(true, |d| d != input));
+    }
+
+    #[test]
+    fn messages_share_a_preloaded_model() {
+        let mut trained = SymbolTable::new();
+        for &s in b"the quick brown fox jumps over the lazy dog, the end" {
+            trained.increment_symbol(s as usize);
+        }
+        let snapshot = ModelSnapshot::from_bytes(&trained.export().to_bytes()).unwrap();
+        assert_eq!(snapshot, trained.export());
+
+        let messages: [&[u8]; 2] = [b"the lazy fox", b"the brown dog"];
+        let mut streams = Vec::new();
+        for message in messages {
+            let mut output = Vec::new();
+            {
+                let model = SymbolTable::import(&snapshot).unwrap();
+                let mut encoder = Encoder::with_model(&mut output, model);
+                for &s in message {
+                    encoder.encode_next(s as usize).unwrap();
+                }
+                encoder.encode_end().unwrap();
+            }
+            streams.push(output);
+        }
+
+        for (message, stream) in messages.iter().zip(&streams) {
+            let model = SymbolTable::import(&snapshot).unwrap();
+            let mut cursor = std::io::Cursor::new(stream);
+            let mut decoder = Decoder::with_model(&mut cursor, model).unwrap();
+            let mut decoded = Vec::new();
+            loop {
+                let s = decoder.decode_next().unwrap();
+                if s == EOF_SYMBOL {
+                    break;
+                }
+                decoded.push(s as u8);
+            }
+
+            assert_eq!(&decoded, message);
+        }
+    }
+
+    #[test]
+    fn model_snapshot_rejects_inconsistent_counts() {
+        let mut snapshot = SymbolTable::new().export();
+        snapshot.symbol_count += 1;
+        assert!(SymbolTable::import(&snapshot).is_err());
+
+        let mut snapshot = SymbolTable::new().export();
+        snapshot.frequencies[7] = 0;
+        snapshot.symbol_count -= 1;
+        assert!(SymbolTable::import(&snapshot).is_err());
+
+        // Frequencies that overflow, with the total they wrap around to.
+        let mut snapshot = SymbolTable::new().export();
+        snapshot.frequencies[0] = usize::MAX;
+        snapshot.symbol_count = 255;
+        assert!(SymbolTable::import(&snapshot).is_err());
+
+        let mut snapshot = SymbolTable::new().export();
+        snapshot.frequencies[0] = MAX_STATIC_TOTAL;
+        snapshot.symbol_count = MAX_STATIC_TOTAL + 256;
+        assert!(SymbolTable::import(&snapshot).is_err());
+    }
+
+    #[test]
+    fn static_model_with_frequency_floor_codes_unseen_bytes() {
+        let mut counts = [0; 256];
+        for &b in b"aaaabbbc" {
+            counts[b as usize] += 1;
+        }
+        let input = b"abcxyz\x00\xff";
+
+        let mut output = Vec::new();
+        {
+            let model = SymbolTable::from_counts(&counts, true).unwrap();
+            let mut encoder = Encoder::with_model(&mut output, model);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let model = SymbolTable::from_counts(&counts, true).unwrap();
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::with_model(&mut cursor, model).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+
+        // Without the floor the unseen bytes have no interval at all.
+        let model = SymbolTable::from_counts(&counts, false).unwrap();
+        let mut output = Vec::new();
+        let mut encoder = Encoder::with_model(&mut output, model);
+        encoder.encode_slice(b"abc").unwrap();
+        assert!(encoder.encode_next(b'x' as usize).is_err());
+    }
+
+    #[test]
+    fn decaying_model_tracks_a_distribution_shift() {
+        // Skewed towards a few letters for the first half, towards a few others for the second.
+        let input = (0..40_000u32)
+            .map(|i| {
+                let pick = (i.wrapping_mul(2654435761) >> 29) as u8;
+                if i < 20_000 {
+                    b"aaaabbcd"[pick as usize]
+                } else {
+                    b"wwwwxxyz"[pick as usize]
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let encode = |model: SymbolTable| {
+            let mut output = Vec::new();
+            {
+                let mut encoder = Encoder::with_model(&mut output, model);
+                encoder.encode_slice(&input).unwrap();
+                encoder.encode_end().unwrap();
+            }
+            output
+        };
+        let decaying = || AdaptiveModel::with_decay(1024, 1, 2).unwrap().build();
+
+        let plain = encode(SymbolTable::new());
+        let decayed = encode(decaying());
+        assert!(decayed.len() < plain.len());
+
+        let mut cursor = std::io::Cursor::new(&decayed);
+        let mut decoder = Decoder::with_model(&mut cursor, decaying()).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+    }
+
+    #[test]
+    fn resetting_the_model_helps_when_the_data_changes() {
+        // Two phases over different letters, each long enough for the model to settle on them.
+        let mut random = Xorshift::new();
+        let mut phase = |letters: &[u8]| {
+            (&mut random)
+                .take(4000)
+                .map(|r| letters[(r % letters.len() as u64) as usize])
+                .collect::<Vec<_>>()
+        };
+        let first = phase(b"abcdefgh");
+        let second = phase(b"stuvwxyz");
+
+        // Bits spent on the second phase, with and without a reset before it.
+        let mut second_bits = [0; 2];
+        let mut streams = [Vec::new(), Vec::new()];
+        for (reset, (bits, output)) in second_bits.iter_mut().zip(&mut streams).enumerate() {
+            let mut encoder = Encoder::new(output);
+            encoder.encode_slice(&first).unwrap();
+            if reset == 1 {
+                encoder.reset_model();
+            }
+            let before = encoder.bits_written();
+            encoder.encode_slice(&second).unwrap();
+            *bits = encoder.bits_written() - before;
+            encoder.encode_end().unwrap();
+        }
+        assert!(second_bits[1] < second_bits[0], "{second_bits:?}");
+
+        let mut decoder = Decoder::from_slice(&streams[1]).unwrap();
+        let mut decoded = vec![0; first.len()];
+        decoder.decode_exact(&mut decoded).unwrap();
+        assert_eq!(decoded, first);
+        decoder.reset_model();
+        assert_eq!(
+            decoder.model().frequencies(),
+            SymbolTable::new().frequencies()
+        );
+        assert_eq!(decoder.decode_to_vec().unwrap(), second);
+
+        // The same on a schedule, with nothing to mirror by hand.
+        let window = first.len();
+        let resetting = || AdaptiveModel::with_reset(window).unwrap().build();
+        let input = [first, second].concat();
+        let mut output = Vec::new();
+        let mut encoder = Encoder::with_model(&mut output, resetting());
+        encoder.encode_slice(&input).unwrap();
+        encoder.encode_end().unwrap();
+        drop(encoder);
+        assert!(output.len() < streams[0].len());
+
+        let mut source = &output[..];
+        let mut decoder = Decoder::with_model(&mut source, resetting()).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+    }
+
+    #[test]
+    fn scheduled_resets_keep_the_eof_scale() {
+        let input = b"a window of bytes, then another, then a few more";
+        let model = || {
+            AdaptiveModel::with_reset(16)
+                .unwrap()
+                .with_eof_scale(16)
+                .unwrap()
+                .build()
+        };
+
+        let mut output = Vec::new();
+        let mut encoder = Encoder::with_model(&mut output, model());
+        encoder.encode_slice(&input[..32]).unwrap();
+        assert_eq!(encoder.model().frequencies(), model().frequencies());
+        encoder.encode_slice(&input[32..]).unwrap();
+        encoder.encode_end().unwrap();
+        drop(encoder);
+
+        let mut reader = &output[..];
+        let mut decoder = Decoder::with_model(&mut reader, model()).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+    }
+
+    #[test]
+    fn a_low_weight_eof_saves_bits_on_long_inputs() {
+        let input = Xorshift::new()
+            .take(1 << 18)
+            .map(|r| b"etaoin shrdlu"[(r % 13) as usize])
+            .collect::<Vec<_>>();
+        let scaled = || AdaptiveModel::new().with_eof_scale(16).unwrap().build();
+
+        let frequencies = scaled().frequencies();
+        assert!(frequencies[..EOF_SYMBOL].iter().all(|&f| f == 16));
+        assert_eq!(frequencies[EOF_SYMBOL], 1);
+
+        // Bits spent on the data, with the default and the scaled EOF. The saving is a few bits
+        // spread over the whole input, so the padded stream doesn't always come out a byte shorter.
+        let mut data_bits = [0; 2];
+        let mut streams = [Vec::new(), Vec::new()];
+        for (model, (bits, output)) in [SymbolTable::new(), scaled()]
+            .into_iter()
+            .zip(data_bits.iter_mut().zip(&mut streams))
+        {
+            let mut encoder = Encoder::with_model(output, model);
+            encoder.encode_slice(&input).unwrap();
+            *bits = encoder.bits_written();
+            encoder.encode_end().unwrap();
+        }
+        assert!(data_bits[1] < data_bits[0], "{data_bits:?}");
+        assert!(streams[1].len() <= streams[0].len());
+
+        let mut reader = &streams[1][..];
+        let mut decoder = Decoder::with_model(&mut reader, scaled()).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+        // EOF was coded, but kept its count.
+        assert_eq!(decoder.model().frequencies()[EOF_SYMBOL], 1);
+
+        assert!(AdaptiveModel::new().with_eof_scale(0).is_err());
+        assert!(AdaptiveModel::new()
+            .with_eof_scale(MAX_STATIC_TOTAL)
+            .is_err());
+    }
+
+    #[test]
+    fn larger_increment_helps_short_skewed_input() {
+        let input = b"aaaaaaaaaaaaaaaabaaaaaaaaaaaaaaaaaaacaaaaaaaaaaaaa";
+
+        let encode = |model: SymbolTable| {
+            let mut output = Vec::new();
+            {
+                let mut encoder = Encoder::with_model(&mut output, model);
+                encoder.encode_slice(input).unwrap();
+                encoder.encode_end().unwrap();
+            }
+            output
+        };
+        let stepped = || AdaptiveModel::new().with_increment(8).unwrap().build();
+
+        let plain = encode(SymbolTable::new());
+        let fast = encode(stepped());
+        assert!(fast.len() < plain.len());
+
+        let mut cursor = std::io::Cursor::new(&fast);
+        let mut decoder = Decoder::with_model(&mut cursor, stepped()).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+    }
+
+    #[test]
+    fn finish_returns_the_writer_for_a_trailer() {
+        let input = b"payload followed by a trailer";
+        let mut output = Vec::new();
+
+        let writer = {
+            let mut encoder = Encoder::new(&mut output);
+            for &s in input {
+                encoder.encode_next(s as usize).unwrap();
+            }
+            encoder.finish().unwrap()
+        };
+        let compressed_len = writer.len();
+        writer.extend_from_slice(b"TRAILER");
+
+        assert_eq!(&output[compressed_len..], b"TRAILER");
+
+        let mut cursor = std::io::Cursor::new(&output[..compressed_len]);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        let mut decoded = Vec::new();
+        loop {
+            let s = decoder.decode_next().unwrap();
+            if s == EOF_SYMBOL {
+                break;
+            }
+            decoded.push(s as u8);
+        }
+
+        assert_eq!(decoded, input);
+    }
+
+    struct Sink {
+        encoder: Encoder<'static, Vec<u8>>,
+    }
+
+    #[test]
+    fn owned_encoder_can_live_in_a_struct() {
+        let input = b"stored in a struct and finalized later";
+        let mut sink = Sink {
+            encoder: Encoder::new_owned(Vec::new()),
+        };
+        sink.encoder.encode_slice(input).unwrap();
+
+        let output = sink.encoder.into_inner().unwrap();
+
+        let mut decoder = Decoder::new_owned(std::io::Cursor::new(output)).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+        assert!(decoder.into_inner().is_ok());
+    }
+
+    #[test]
+    fn decode_to_vec_stops_at_the_output_limit() {
+        let mut encoder = Encoder::new_vec();
+        encoder.encode_slice(&[b'z'; 100_000]).unwrap();
+        let output = encoder.into_inner().unwrap();
+
+        let mut decoder = Decoder::from_slice(&output)
+            .unwrap()
+            .with_max_output_bytes(100);
+        let err = decoder.decode_to_vec().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AeError>(),
+            Some(&AeError::OutputTooLarge { limit: 100 })
+        );
+
+        let mut decoder = Decoder::from_slice(&output)
+            .unwrap()
+            .with_max_output_bytes(100_000);
+        assert_eq!(decoder.decode_to_vec().unwrap().len(), 100_000);
+    }
+
+    #[test]
+    fn boxed_encoders_are_driven_alike() {
+        /// A `Box<dyn Write>` with the `Debug` the encoder asks of its writer.
+        struct Boxed<'a>(Box<dyn std::io::Write + 'a>);
+
+        impl std::fmt::Debug for Boxed<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("Boxed")
+            }
+        }
+
+        impl std::io::Write for Boxed<'_> {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.0.flush()
+            }
+        }
+
+        let input = b"every encoder gets the same symbols";
+        let mut plain = Vec::new();
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let mut boxed_output = Vec::new();
+        let mut boxed = Boxed(Box::new(&mut boxed_output));
+        let mut wide = Vec::new();
+
+        let mut encoders: Vec<Box<dyn DynEncoder + '_>> = vec![
+            Box::new(Encoder::new(&mut plain)),
+            Box::new(Encoder::new(&mut cursor)),
+            Box::new(Encoder::new(&mut boxed)),
+            Box::new(Encoder::<_, 64>::with_register_bits(
+                &mut wide,
+                SymbolTable::new(),
+            )),
+        ];
+        for encoder in &mut encoders {
+            encoder.encode_slice(&input[..10]).unwrap();
+            for &b in &input[10..] {
+                encoder.encode_next(b as usize).unwrap();
+            }
+            assert!(encoder.bits_written() > 0);
+        }
+        for encoder in encoders {
+            encoder.finish().unwrap();
+        }
+        drop(boxed);
+
+        assert_eq!(cursor.into_inner(), plain);
+        assert_eq!(boxed_output, plain);
+        assert_eq!(
+            Decoder::from_slice(&plain)
+                .unwrap()
+                .decode_to_vec()
+                .unwrap(),
+            input
+        );
+        let mut source = &wide[..];
+        let mut decoder =
+            Decoder::<_, 64>::with_register_bits(&mut source, SymbolTable::new()).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+    }
+
+    #[quickcheck]
+    fn runs_round_trip(input: Vec<u8>, stretch: u8) {
+        // Stretch every byte into a run, short ones included, so every way of coding a run is hit.
+        let input = input
+            .iter()
+            .flat_map(|&b| std::iter::repeat_n(b, (b ^ stretch) as usize % 8))
+            .collect::<Vec<_>>();
+
+        let mut encoder = Encoder::new_vec();
+        let mut model = RunModel::new();
+        let (head, tail) = input.split_at(input.len() / 2);
+        encoder.encode_runs(&mut model, head).unwrap();
+        encoder.encode_runs(&mut model, tail).unwrap();
+        encoder.encode_runs_end(&mut model).unwrap();
+        let output = encoder.into_inner().unwrap();
+
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        let decoded = decoder.decode_runs_to_vec(&mut RunModel::new()).unwrap();
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    fn runs_code_much_smaller_than_plain_symbols() {
+        let mut input = Vec::new();
+        for r in Xorshift::new() {
+            if input.len() >= 100_000 {
+                break;
+            }
+            input.extend(std::iter::repeat_n(r as u8, (r >> 32) as usize % 200 + 1));
+        }
+
+        let mut plain = Encoder::new_vec();
+        plain.encode_slice(&input).unwrap();
+        let plain = plain.into_inner().unwrap();
+
+        let mut runs = Encoder::new_vec();
+        let mut model = RunModel::new();
+        runs.encode_runs(&mut model, &input).unwrap();
+        runs.encode_runs_end(&mut model).unwrap();
+        let runs = runs.into_inner().unwrap();
+        assert!(
+            runs.len() * 4 < plain.len(),
+            "{} vs {}",
+            runs.len(),
+            plain.len()
+        );
+
+        let mut model = RunModel::new();
+        let mut decoder = Decoder::from_slice(&runs).unwrap();
+        let mut decoded = Vec::new();
+        let mut count = 0;
+        while let Some((b, n)) = decoder.decode_run(&mut model).unwrap() {
+            decoded.extend(std::iter::repeat_n(b, n as usize));
+            count += 1;
+        }
+        assert_eq!(decoded, input);
+        // Most runs come back in one piece, each from three symbols rather than one per byte.
+        assert!(count < input.len() / 40, "{count} runs");
+
+        // A claimed run past the limit is refused before anything is allocated for it.
+        let mut decoder = Decoder::from_slice(&runs)
+            .unwrap()
+            .with_max_output_bytes(1000);
+        let err = decoder
+            .decode_runs_to_vec(&mut RunModel::new())
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AeError>(),
+            Some(&AeError::OutputTooLarge { limit: 1000 })
+        );
+    }
+
+    const GOLDEN_NARROW: [u8; 63] = [
+        0x72, 0x8D, 0x72, 0x90, 0x09, 0x55, 0x55, 0x4B, 0x0B, 0x6D, 0xB6, 0xEF, 0x00, 0x00, 0x00,
+        0x45, 0x29, 0x4A, 0x50, 0xFB, 0xFF, 0xFF, 0xEE, 0xC7, 0xDF, 0xBF, 0x77, 0xDB, 0x98, 0xDD,
+        0x14, 0x91, 0xB0, 0x3A, 0x7C, 0xA7, 0xD6, 0x49, 0x74, 0x09, 0xFA, 0x1B, 0xD4, 0x9E, 0xC0,
+        0xC2, 0xFF, 0xF0, 0x31, 0xB4, 0x47, 0x13, 0x9F, 0x22, 0x1F, 0x98, 0xFB, 0x13, 0xED, 0x7C,
+        0x90, 0x8F, 0x80,
+    ];
+
+    const GOLDEN_WIDE: [u8; 63] = [
+        0x72, 0x8D, 0x72, 0x90, 0x7C, 0xD8, 0x64, 0x7B, 0x72, 0x70, 0x31, 0x32, 0x8C, 0x7B, 0xDB,
+        0x52, 0x00, 0x2E, 0x0F, 0xEC, 0x89, 0xC8, 0x24, 0xEA, 0x03, 0x38, 0xDB, 0x0C, 0x46, 0x6F,
+        0xE3, 0x27, 0x00, 0x9C, 0x55, 0x35, 0x9E, 0x17, 0xC1, 0x38, 0x9E, 0x52, 0x99, 0x7C, 0x5F,
+        0x59, 0x77, 0xED, 0x37, 0xBD, 0xDA, 0x95, 0x0A, 0x7A, 0x2F, 0x14, 0xCE, 0xA9, 0x0D, 0xF6,
+        0x30, 0x0A, 0x80,
+    ];
+
+    #[test]
+    fn streams_are_the_same_on_every_target() {
+        // Streams must come out byte for byte the same on 32 and 64 bit targets, so this also runs
+        // under `--target i686-unknown-linux-gnu`. The largest allowed increment pushes the model
+        // totals as high as they can go.
+        let input = b"same bytes on every target";
+        let model = || {
+            AdaptiveModel::new()
+                .with_increment(MAX_STATIC_TOTAL)
+                .unwrap()
+                .build()
+        };
+        let mut narrow = Vec::new();
+        {
+            let mut encoder = Encoder::with_model(&mut narrow, model());
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        let mut wide = Vec::new();
+        {
+            let mut encoder = Encoder::<_, 64>::with_register_bits(&mut wide, model());
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        assert_eq!(narrow, GOLDEN_NARROW);
+        assert_eq!(wide, GOLDEN_WIDE);
+
+        let mut reader = &narrow[..];
+        let mut decoder = Decoder::with_model(&mut reader, model()).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+        let mut reader = &wide[..];
+        let mut decoder = Decoder::<_, 64>::with_register_bits(&mut reader, model()).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+
+        assert!(AdaptiveModel::new()
+            .with_increment(MAX_STATIC_TOTAL + 1)
+            .is_err());
+    }
+
+    #[quickcheck]
+    fn bits_per_symbol_add_up_to_the_stream(input: Vec<u8>) {
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut reader = &output[..];
+        let mut decoder = Decoder::new(&mut reader).unwrap();
+        let mut decoded = Vec::new();
+        let mut bits = 0u64;
+        loop {
+            let (symbol, consumed) = decoder.decode_next_with_bits().unwrap();
+            bits += consumed as u64;
+            if symbol == EOF_SYMBOL {
+                break;
+            }
+            decoded.push(symbol as u8);
+        }
+
+        assert_eq!(decoded, input);
+        assert_eq!((bits + 2).div_ceil(8), output.len() as u64);
+    }
+
+    #[quickcheck]
+    fn slices_decode_like_cursors(input: Vec<u8>, trailing: Vec<u8>) {
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        output.extend_from_slice(&trailing);
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut expected = Decoder::new(&mut cursor).unwrap();
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        loop {
+            let symbol = decoder.decode_next().unwrap();
+            assert_eq!(symbol, expected.decode_next().unwrap());
+            if symbol == EOF_SYMBOL {
+                break;
+            }
+        }
+        assert_eq!(decoder.bytes_consumed(), expected.bytes_consumed());
+    }
+
+    #[quickcheck]
+    fn vec_and_slice_helpers_match_the_general_path(input: Vec<u8>) {
+        let mut expected = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut expected);
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut encoder = Encoder::new_vec();
+        encoder.encode_slice(&input).unwrap();
+        let output = encoder.into_inner().unwrap();
+        assert_eq!(output, expected);
+
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+    }
+
+    #[quickcheck]
+    fn chunked_encoding_matches_one_chunk(input: Vec<u8>, splits: Vec<u8>) {
+        let mut whole = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut whole);
+            encoder.encode_chunk(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut chunked = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut chunked);
+            let mut rest = &input[..];
+            for split in splits.into_iter().chain(core::iter::once(u8::MAX)) {
+                let (chunk, tail) = rest.split_at((split as usize % 16).min(rest.len()));
+                encoder.encode_chunk(chunk).unwrap();
+                rest = tail;
+            }
+            encoder.encode_chunk(rest).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        assert_eq!(chunked, whole);
+    }
+
+    #[test]
+    fn encode_chunk_hands_over_completed_bytes() {
+        let input = b"a chunk that's long enough to produce a few whole bytes of output";
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output);
+        encoder.encode_chunk(input).unwrap();
+        let bits = encoder.bits_written();
+        // Dropping would write out whatever is still batched, so skip it to see what's already there.
+        core::mem::forget(encoder);
+
+        assert!(bits >= 8);
+        assert_eq!(output.len() as u64, bits / 8);
+    }
+
+    #[quickcheck]
+    fn restored_encoders_carry_on_the_stream(input: Vec<u8>, split: usize) {
+        let split = split % (input.len() + 1);
+        // A decaying model with a bigger step and a scaled EOF, so all of the adaptation has to
+        // survive the trip.
+        let model = || {
+            AdaptiveModel::with_decay(64, 1, 2)
+                .unwrap()
+                .with_increment(3)
+                .unwrap()
+                .with_eof_scale(5)
+                .unwrap()
+                .build()
+        };
+
+        let mut expected = Vec::new();
+        {
+            let mut encoder = Encoder::with_model(&mut expected, model());
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut output = Vec::new();
+        let bytes = {
+            let mut encoder = Encoder::with_model(&mut output, model());
+            encoder.encode_slice(&input[..split]).unwrap();
+            encoder.save_state().unwrap().to_bytes()
+        };
+        let state: EncoderState = EncoderState::from_bytes(&bytes).unwrap();
+        assert_eq!(state.symbols(), split as u64);
+        {
+            let mut encoder = Encoder::restore_state(&mut output, state);
+            encoder.encode_slice(&input[split..]).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        assert_eq!(output, expected);
+
+        let mut reader = &output[..];
+        let mut decoder = Decoder::with_model(&mut reader, model()).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+
+        assert!(EncoderState::<32>::from_bytes(&bytes[8..]).is_err());
+        assert!(EncoderState::<64>::from_bytes(&bytes).is_err());
+    }
+
+    /// Encodes `segments` with a boundary after each, returning the stream and a checkpoint at the
+    /// start of every segment plus one for the EOF after the last.
+    fn encode_segments(segments: &[&[u8]]) -> (Vec<u8>, Vec<Checkpoint>) {
+        let mut output = Vec::new();
+        let mut checkpoints = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            checkpoints.push(encoder.checkpoint());
+            for segment in segments {
+                encoder.encode_slice(segment).unwrap();
+                encoder.flush_boundary().unwrap();
+                checkpoints.push(encoder.checkpoint());
+            }
+            encoder.encode_end().unwrap();
+        }
+
+        (output, checkpoints)
+    }
+
+    fn decode_bytes<T: std::io::Read + std::fmt::Debug>(
+        decoder: &mut Decoder<'_, T>,
+        length: usize,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut output = vec![0; length];
+        decoder.decode_exact(&mut output)?;
+
+        anyhow::Ok(output)
+    }
+
+    #[quickcheck]
+    fn decoder_follows_boundaries(segments: Vec<Vec<u8>>) {
+        let segments = segments.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let (output, checkpoints) = encode_segments(&segments);
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        for (segment, next) in segments.iter().zip(&checkpoints[1..]) {
+            assert_eq!(decode_bytes(&mut decoder, segment.len()).unwrap(), *segment);
+            assert_eq!(decoder.bytes_consumed(), next.byte_offset());
+            decoder.resync_at_boundary().unwrap();
+        }
+        assert_eq!(decoder.decode_next().unwrap(), EOF_SYMBOL);
+        assert_eq!(decoder.bytes_consumed(), output.len() as u64);
+    }
+
+    #[test]
+    fn boundary_recovers_from_corruption() {
+        let segments: [&[u8]; 3] = [
+            b"the first segment decodes normally, ",
+            b"the second one gets corrupted, ",
+            b"and the third comes back from its boundary",
+        ];
+        let (mut output, checkpoints) = encode_segments(&segments);
+
+        let second = checkpoints[1].byte_offset() as usize;
+        let third = checkpoints[2].byte_offset() as usize;
+        for b in &mut output[second..third] {
+            *b = !*b;
+        }
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        assert_eq!(
+            decode_bytes(&mut decoder, segments[0].len()).unwrap(),
+            segments[0]
+        );
+        decoder.resync_at_boundary().unwrap();
+        // What inverted bytes decode to is undefined, but it isn't the segment, if it decodes at all.
+        if let Ok(damaged) = decode_bytes(&mut decoder, segments[1].len()) {
+            assert_ne!(damaged, segments[1]);
+        }
+
+        let mut cursor = std::io::Cursor::new(&output);
+        cursor.set_position(checkpoints[2].byte_offset());
+        let mut decoder = Decoder::at_checkpoint(&mut cursor, &checkpoints[2]).unwrap();
+        assert_eq!(
+            decode_bytes(&mut decoder, segments[2].len()).unwrap(),
+            segments[2]
+        );
+        decoder.resync_at_boundary().unwrap();
+        assert_eq!(decoder.decode_next().unwrap(), EOF_SYMBOL);
+    }
+
+    #[test]
+    fn text_prior_beats_uniform_on_short_text() {
+        let input = b"a short message in lower case ascii";
+
+        // Lower case letters and the space ten times as likely as anything else.
+        let mut counts = [1; 257];
+        for b in b'a'..=b'z' {
+            counts[b as usize] = 10;
+        }
+        counts[b' ' as usize] = 10;
+
+        let mut primed = Vec::new();
+        {
+            let mut encoder = Encoder::with_initial_counts(&mut primed, &counts).unwrap();
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        let mut uniform = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut uniform);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        assert!(primed.len() < uniform.len());
+
+        let mut cursor = std::io::Cursor::new(&primed);
+        let mut decoder = Decoder::with_initial_counts(&mut cursor, &counts).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+    }
+
+    #[test]
+    fn priming_shrinks_similar_data_and_round_trips() {
+        let sample =
+            b"{\"name\": \"sample\", \"tags\": [\"alpha\", \"beta\"], \"count\": 17}\n".repeat(20);
+        let payload = b"{\"name\": \"payload\", \"tags\": [\"beta\", \"alpha\"], \"count\": 3}\n";
+
+        let encode = |sample: &[u8]| {
+            let mut output = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut output);
+                encoder.prime(sample);
+                encoder.encode_slice(payload).unwrap();
+                encoder.encode_end().unwrap();
+            }
+            output
+        };
+        let plain = encode(&[]);
+        let primed = encode(&sample);
+        assert!(
+            primed.len() * 4 < plain.len() * 3,
+            "{} vs {}",
+            primed.len(),
+            plain.len()
+        );
+
+        let mut decoder = Decoder::from_slice(&primed).unwrap();
+        decoder.prime(&sample);
+        assert_eq!(decoder.decode_to_vec().unwrap(), payload);
+
+        // Without the same priming the decoder goes astray.
+        let unprimed = Decoder::from_slice(&primed).and_then(|mut d| d.decode_to_vec());
+        assert!(unprimed.map_or(true, |d| d != payload));
+    }
+
+    #[test]
+    fn matching_model_changes_keep_the_coders_in_step() {
+        let input = b"first half, then a long run: zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+        let split = input.iter().position(|&b| b == b':').unwrap() + 1;
+        let boost = |model: &mut SymbolTable| {
+            for _ in 0..100 {
+                model.increment_symbol(b'z' as usize);
+            }
+        };
+
+        let mut output = Vec::new();
+        let mut plain = Vec::new();
+        for (stream, boosted) in [(&mut output, true), (&mut plain, false)] {
+            let mut encoder = Encoder::new(stream);
+            encoder.encode_slice(&input[..split]).unwrap();
+            if boosted {
+                boost(encoder.model_mut());
+            }
+            encoder.encode_slice(&input[split..]).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        assert!(output.len() < plain.len());
+
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        let mut decoded = vec![0; split];
+        decoder.decode_exact(&mut decoded).unwrap();
+        boost(decoder.model_mut());
+        decoded.extend(decoder.decode_to_vec().unwrap());
+        assert_eq!(decoded, input);
+    }
+
+    #[quickcheck]
+    fn skipping_matches_decoding_and_discarding(input: Vec<u8>, n: usize) {
+        let n = n % (input.len() + 1);
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output);
+        encoder.encode_slice(&input).unwrap();
+        encoder.encode_end().unwrap();
+        drop(encoder);
+
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        decoder.skip(n).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input[n..]);
+
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        let err = decoder.skip(input.len() + 1).unwrap_err();
+        assert!(err.to_string().contains("stream ended"), "{err}");
+    }
+
+    #[test]
+    fn progress_is_reported_every_n_bytes() {
+        let input = [7u8; 1000];
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output);
+        encoder.encode_slice(&input).unwrap();
+        encoder.encode_end().unwrap();
+        drop(encoder);
+
+        let mut reported = Vec::new();
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        let decoded = decoder
+            .decode_to_vec_with_progress(300, |n| reported.push(n))
+            .unwrap();
+        assert_eq!(decoded, input);
+        assert_eq!(reported, [300, 600, 900]);
+
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        assert!(decoder.decode_to_vec_with_progress(0, |_| ()).is_err());
+    }
+
+    #[test]
+    fn cloned_models_diverge_from_the_original() {
+        let input = b"a model worth keeping a copy of";
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output);
+        encoder.encode_slice(input).unwrap();
+        let before = encoder.model().frequencies();
+
+        let mut tentative = encoder.clone_model();
+        assert_eq!(tentative.frequencies(), before);
+        for _ in 0..100 {
+            tentative.increment_symbol(b'z' as usize);
+        }
+        assert_ne!(tentative.frequencies(), before);
+        assert_eq!(encoder.model().frequencies(), before);
+        encoder.encode_end().unwrap();
+        drop(encoder);
+
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+        let decoded = decoder.model().frequencies();
+        let mut tentative = decoder.clone_model();
+        tentative.update(0);
+        assert_ne!(tentative.frequencies(), decoded);
+        assert_eq!(decoder.model().frequencies(), decoded);
+    }
+
+    #[test]
+    fn interleaved_models_follow_the_same_schedule() {
+        // Records of a type tag from a small set followed by a value byte spread over a wide range.
+        let mut input = Vec::new();
+        for r in Xorshift::new().take(2000) {
+            input.push(b"TLV"[(r % 3) as usize]);
+            input.push((r >> 24) as u8);
+        }
+
+        let mut output = Vec::new();
+        {
+            let mut models = [SymbolTable::new(), SymbolTable::new()];
+            let mut encoder = Encoder::new(&mut output);
+            for (i, &b) in input.iter().enumerate() {
+                encoder.encode_with(&mut models[i % 2], b as usize).unwrap();
+            }
+            encoder.encode_end().unwrap();
+        }
+        let mut shared = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut shared);
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        assert!(output.len() < shared.len());
+
+        let mut models = [SymbolTable::new(), SymbolTable::new()];
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        for (i, &b) in input.iter().enumerate() {
+            assert_eq!(decoder.decode_with(&mut models[i % 2]).unwrap(), b as usize);
+        }
+        assert_eq!(decoder.decode_next().unwrap(), EOF_SYMBOL);
+
+        // The tag model only ever saw tags.
+        let tags = input.iter().step_by(2).filter(|&&b| b == b'T').count();
+        assert_eq!(models[0].frequencies()[b'T' as usize], tags + 1);
+    }
+
+    #[quickcheck]
+    fn raw_bits_mix_with_symbols(fields: Vec<(u8, u64, u8)>) {
+        // Each field is a modeled byte followed by a raw value of some width up to 64 bits.
+        let fields = fields
+            .into_iter()
+            .map(|(b, value, width)| {
+                let width = width as u32 % 65;
+                (b, value.checked_shr(64 - width).unwrap_or(0), width)
+            })
+            .collect::<Vec<_>>();
+
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            for &(b, value, width) in &fields {
+                encoder.encode_next(b as usize).unwrap();
+                encoder.encode_raw_bits(value, width).unwrap();
+            }
+            encoder.encode_end().unwrap();
+        }
+
+        let mut decoder = Decoder::from_slice(&output).unwrap();
+        for &(b, value, width) in &fields {
+            assert_eq!(decoder.decode_next().unwrap(), b as usize);
+            assert_eq!(decoder.decode_raw_bits(width).unwrap(), value);
+        }
+        assert_eq!(decoder.decode_next().unwrap(), EOF_SYMBOL);
+    }
+
+    #[test]
+    fn raw_bits_cost_about_their_width() {
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            for i in 0..1000u64 {
+                encoder
+                    .encode_raw_bits(i.wrapping_mul(0x9E3779B97F4A7C15), 64)
+                    .unwrap();
+            }
+            assert!(encoder.encode_raw_bits(4, 2).is_err());
+            encoder.encode_end().unwrap();
+        }
+
+        // 8000 bytes of raw bits, plus the EOF symbol and the flush.
+        assert!((8000..8004).contains(&output.len()), "{}", output.len());
+    }
+
+    /// Counts the events logged while it's the default subscriber.
+    struct EventCounter(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+    impl tracing::Subscriber for EventCounter {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    /// How many warnings `f` logs.
+    fn count_warnings(f: impl FnOnce()) -> usize {
+        let count = std::sync::Arc::default();
+        tracing::subscriber::with_default(EventCounter(std::sync::Arc::clone(&count)), f);
+
+        count.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    #[test]
+    fn dropping_an_unfinished_encoder_warns() {
+        let mut output = Vec::new();
+
+        let finished = count_warnings(|| {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(b"finished").unwrap();
+            encoder.encode_end().unwrap();
+        });
+        assert_eq!(finished, 0);
+
+        let unfinished = count_warnings(|| {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(b"never finished").unwrap();
+        });
+        assert_eq!(unfinished, 1);
+
+        // Symbols after a boundary need finishing again.
+        let after_boundary = count_warnings(|| {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(b"first part").unwrap();
+            encoder.flush_boundary().unwrap();
+            encoder.encode_slice(b"second part").unwrap();
+        });
+        assert_eq!(after_boundary, 1);
+
+        let abandoned = count_warnings(|| {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(b"thrown away").unwrap();
+            encoder.abandon();
+        });
+        assert_eq!(abandoned, 0);
+
+        let into_inner = count_warnings(|| {
+            let mut encoder = Encoder::new_vec();
+            encoder.encode_slice(b"owned").unwrap();
+            encoder.into_inner().unwrap();
+        });
+        assert_eq!(into_inner, 0);
+    }
+
+    #[test]
+    fn oversized_model_is_rejected() {
+        // The largest total a snapshot can have, far more than 16 bit registers take, which would
+        // narrow the rarer symbols down to less than a single step of the range.
+        let mut frequencies = [1; 257];
+        frequencies[0] = MAX_STATIC_TOTAL - 256;
+        let snapshot = ModelSnapshot {
+            frequencies,
+            symbol_count: MAX_STATIC_TOTAL,
+        };
+
+        let mut output = Vec::new();
+        {
+            let model = SymbolTable::import(&snapshot).unwrap();
+            let mut encoder = Encoder::<_, 16>::with_register_bits(&mut output, model);
+            assert!(encoder.encode_next(1).is_err());
+        }
+
+        // The default 32 bit registers have room for it.
+        output.clear();
+        {
+            let model = SymbolTable::import(&snapshot).unwrap();
+            let mut encoder = Encoder::with_model(&mut output, model);
+            encoder.encode_slice(b"\x00\x01\x00").unwrap();
+            encoder.encode_end().unwrap();
+        }
+        let mut cursor = std::io::Cursor::new(&output);
+        let model = SymbolTable::import(&snapshot).unwrap();
+        let mut decoder = Decoder::with_model(&mut cursor, model).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), b"\x00\x01\x00");
+    }
+
+    #[test]
+    fn model_reports_counts_on_top_of_the_prior() {
+        let input = b"aaaaaaaabbbbccd";
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output);
+        encoder.encode_slice(input).unwrap();
+
+        let frequencies = encoder.model().frequencies();
+        let mut expected = [1; 257];
+        for &b in input {
+            expected[b as usize] += 1;
+        }
+        assert_eq!(frequencies, expected);
+        assert_eq!(
+            encoder.model().probability(b'a' as usize),
+            9.0 / (257 + input.len()) as f64
+        );
+        let total = (0..257)
+            .map(|s| encoder.model().probability(s))
+            .sum::<f64>();
+        assert!((total - 1.0).abs() < 1e-9);
+        encoder.encode_end().unwrap();
+        drop(encoder);
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        decoder.decode_to_vec().unwrap();
+        expected[EOF_SYMBOL] += 1;
+        assert_eq!(decoder.model().frequencies(), expected);
+    }
+
+    /// Accepts `limit` bytes, then fails every write.
+    #[derive(Debug)]
+    struct FailingWriter {
+        written: Vec<u8>,
+        limit: usize,
+    }
+
+    impl std::io::Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.limit - self.written.len());
+            if n == 0 {
+                return Err(std::io::Error::other("disk full"));
+            }
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn encoder_is_poisoned_by_a_failed_write() {
+        let mut writer = FailingWriter {
+            written: Vec::new(),
+            limit: 100,
+        };
+        let input = (0..20_000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect::<Vec<_>>();
+
+        let mut encoder = Encoder::new(&mut writer);
+        let err = encoder.encode_slice(&input).unwrap_err();
+        assert!(err.to_string().contains("disk full"), "{err}");
+
+        for result in [
+            encoder.encode_next(b'a' as usize),
+            encoder.encode_slice(b"more"),
+            encoder.flush_boundary(),
+            encoder.encode_end(),
+        ] {
+            let err = result.unwrap_err();
+            assert_eq!(err.downcast_ref::<AeError>(), Some(&AeError::Poisoned));
+        }
+        drop(encoder);
+
+        assert_eq!(writer.written.len(), 100);
+    }
+
+    #[test]
+    fn dropping_a_bit_writer_warns_about_a_failed_write() {
+        let mut writer = FailingWriter {
+            written: Vec::new(),
+            limit: 0,
+        };
+        let warnings = count_warnings(|| {
+            let mut bits = BitWriter::new(&mut writer);
+            bits.write_bits(0xabcd, 16).unwrap();
+        });
+        assert_eq!(warnings, 1);
+
+        // A failure the caller has already been handed isn't logged again.
+        let warnings = count_warnings(|| {
+            let mut bits = BitWriter::new(&mut writer);
+            bits.write_bits(0xabcd, 16).unwrap();
+            assert!(bits.flush().is_err());
+            bits.write_bits(0xef, 8).unwrap();
+        });
+        assert_eq!(warnings, 0);
+
+        let mut output = Vec::new();
+        let warnings = count_warnings(|| {
+            let mut bits = BitWriter::new(&mut output);
+            bits.write_bits(0xabcd, 16).unwrap();
+        });
+        assert_eq!(warnings, 0);
+        assert_eq!(output, [0xab, 0xcd]);
+    }
+
+    /// Decoder fuzz harness: whatever the bytes, decoding has to end in symbols or an error, never a
+    /// panic or a hang. Run it longer with e.g. `QUICKCHECK_TESTS=1000000 cargo test arbitrary_bytes`.
+    #[quickcheck]
+    fn decoder_survives_arbitrary_bytes(input: Vec<u8>, window: u8, counts: Vec<u16>) {
+        const MAX_SYMBOLS: usize = 4096;
+
+        let mut static_counts = [0; 256];
+        for (count, &c) in static_counts.iter_mut().zip(&counts) {
+            *count = c as usize;
+        }
+        let models = [
+            SymbolTable::new(),
+            AdaptiveModel::with_decay(window as usize % 32 + 1, 1, 2)
+                .unwrap()
+                .build(),
+            SymbolTable::from_counts(&static_counts, false).unwrap(),
+        ];
+
+        for model in models {
+            let mut cursor = std::io::Cursor::new(&input);
+            let Ok(mut decoder) = Decoder::with_model(&mut cursor, model) else {
+                continue;
+            };
+            for _ in 0..MAX_SYMBOLS {
+                match decoder.decode_next() {
+                    Ok(EOF_SYMBOL) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+        }
+
+        let mut cursor = std::io::Cursor::new(&input);
+        if let Ok(mut decoder) = Decoder::new(&mut cursor) {
+            let mut model = ExtendableModel::new(4, 1 << 20, 2).unwrap();
+            for _ in 0..MAX_SYMBOLS {
+                if decoder.decode_extendable(&mut model).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn code_outside_the_interval_is_a_corrupt_stream() {
+        // Find a checkpoint whose interval sits well above zero, then start from it on a stream of
+        // zeroes, which puts the code below low.
+        let input = b"zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output).with_checkpoints(1);
+        encoder.encode_slice(input).unwrap();
+        let checkpoint = encoder
+            .take_checkpoints()
+            .into_iter()
+            .find(|c| c.low > 1 << 20)
+            .unwrap();
+        drop(encoder);
+
+        let zeroes = vec![0; 64];
+        let mut cursor = std::io::Cursor::new(&zeroes);
+        let mut decoder = Decoder::at_checkpoint(&mut cursor, &checkpoint).unwrap();
+        let err = decoder.decode_next().unwrap_err();
+        assert_eq!(err.downcast_ref::<AeError>(), Some(&AeError::CorruptStream));
+    }
+
+    #[test]
+    fn into_inner_and_finish_reject_the_other_kind_of_encoder() {
+        let input = b"never ended";
+        let mut abandoned = Vec::new();
+        let mut encoder = Encoder::new(&mut abandoned);
+        encoder.encode_slice(input).unwrap();
+        encoder.abandon();
+
+        // The wrong call fails before ending the stream, so no EOF reaches the writer.
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output);
+        encoder.encode_slice(input).unwrap();
+        assert!(encoder.into_inner().is_err());
+        assert_eq!(output, abandoned);
+
+        let mut encoder = Encoder::new_owned(Vec::new());
+        encoder.encode_slice(input).unwrap();
+        assert!(encoder.finish().is_err());
+    }
+
+    #[test]
+    fn decode_exact_reads_a_stream_without_eof() {
+        let input = b"length is stored elsewhere";
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end_without_eof().unwrap();
+        }
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoded = [0; 26];
+        Decoder::new(&mut cursor)
+            .unwrap()
+            .decode_exact(&mut decoded)
+            .unwrap();
+
+        assert_eq!(&decoded, input);
+    }
+
+    #[test]
+    fn decode_exact_errors_on_premature_eof() {
+        let input = b"short";
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoded = [0; 8];
+        assert!(Decoder::new(&mut cursor)
+            .unwrap()
+            .decode_exact(&mut decoded)
+            .is_err());
+    }
+
+    #[test]
+    fn decode_exact_with_a_mismatched_length() {
+        let input = b"ten bytes!";
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        // Declaring too few bytes can't be detected, it just yields a prefix.
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        let mut prefix = [0; 4];
+        decoder.decode_exact(&mut prefix).unwrap();
+        assert_eq!(&prefix, b"ten ");
+
+        // Declaring too many runs into the EOF symbol.
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        let mut too_long = [0; 11];
+        assert!(decoder.decode_exact(&mut too_long).is_err());
+    }
+
+    fn encode_terminated(input: &[u8], termination: Termination) -> Vec<u8> {
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output).with_termination(termination);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        output
+    }
+
+    #[test]
+    fn length_terminated_stream_decodes_with_its_length() {
+        let input = b"length prefixed data";
+        let output = encode_terminated(input, Termination::Length);
+        assert!(output.len() <= encode_terminated(input, Termination::Eof).len());
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor)
+            .unwrap()
+            .with_length(input.len() as u64);
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+        assert_eq!(decoder.decode_next().unwrap(), EOF_SYMBOL);
+    }
+
+    #[test]
+    fn length_mode_without_a_length_refuses_to_decode_to_the_end() {
+        let input = b"no length given";
+        let output = encode_terminated(input, Termination::Length);
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor)
+            .unwrap()
+            .with_termination(Termination::Length);
+        assert!(decoder.decode_to_vec().is_err());
+
+        // Symbol by symbol still works, the caller is the one counting.
+        for &b in input {
+            assert_eq!(decoder.decode_next().unwrap(), b as usize);
+        }
+    }
+
+    #[test]
+    fn eof_terminated_stream_decodes_with_a_length() {
+        let input = b"an eof terminated stream";
+        let output = encode_terminated(input, Termination::Eof);
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap().with_length(5);
+        assert_eq!(decoder.decode_to_vec().unwrap(), &input[..5]);
+
+        // A length past the EOF symbol is an error, not garbage.
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor)
+            .unwrap()
+            .with_length(input.len() as u64 + 1);
+        assert!(decoder.decode_to_vec().is_err());
+    }
+
+    #[test]
+    fn length_terminated_stream_does_not_decode_as_eof_terminated() {
+        let input = b"a stream with no eof symbol in it";
+        let output = encode_terminated(input, Termination::Length);
+
+        // Without the EOF symbol the decoder runs off the end, which is reported as truncation.
+        let mut cursor = std::io::Cursor::new(&output);
+        let decoded = Decoder::new(&mut cursor).unwrap().decode_to_vec();
+        assert!(decoded.map_or(true, |decoded| decoded != input));
+    }
+
+    #[test]
+    fn decoding_resumes_at_the_second_checkpoint() {
+        let input = b"a long stream that someone wants to seek into without decoding all of it. "
+            .repeat(40);
+
+        let mut output = Vec::new();
+        let checkpoints = {
+            let mut encoder = Encoder::new(&mut output).with_checkpoints(1000);
+            encoder.encode_slice(&input).unwrap();
+            let checkpoints = encoder.take_checkpoints();
+            encoder.encode_end().unwrap();
+            checkpoints
+        };
+        assert_eq!(checkpoints.len(), input.len() / 1000);
+
+        let second = &checkpoints[1];
+        assert_eq!(second.symbols(), 2000);
+
+        let mut source = &output[second.byte_offset() as usize..];
+        let mut decoder = Decoder::at_checkpoint(&mut source, second).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), &input[2000..]);
+        assert_eq!(decoder.bytes_consumed(), output.len() as u64);
+    }
+
+    #[quickcheck]
+    fn decoding_resumes_at_any_checkpoint(input: Vec<u8>) {
+        let mut output = Vec::new();
+        let checkpoints = {
+            let mut encoder = Encoder::new(&mut output).with_checkpoints(1);
+            encoder.encode_slice(&input).unwrap();
+            let checkpoints = encoder.take_checkpoints();
+            encoder.encode_end().unwrap();
+            checkpoints
+        };
+
+        for checkpoint in &checkpoints {
+            let mut source = &output[checkpoint.byte_offset() as usize..];
+            let mut decoder = Decoder::at_checkpoint(&mut source, checkpoint).unwrap();
+            let decoded = decoder.decode_to_vec().unwrap();
+            assert_eq!(decoded, &input[checkpoint.symbols() as usize..]);
+        }
+    }
+
+    #[cfg(feature = "sync-check")]
+    #[quickcheck]
+    fn models_stay_in_sync(input: Vec<u8>, window: u8, step: u8) {
+        let models = || {
+            [
+                SymbolTable::new(),
+                AdaptiveModel::with_decay(window as usize % 16 + 1, 1, 2)
+                    .unwrap()
+                    .build(),
+                AdaptiveModel::new()
+                    .with_increment(step as usize + 1)
+                    .unwrap()
+                    .build(),
+            ]
+        };
+
+        for (encoder_model, decoder_model) in models().into_iter().zip(models()) {
+            let mut output = Vec::new();
+            let trace = {
+                let mut encoder = Encoder::with_model(&mut output, encoder_model);
+                encoder.encode_slice(&input).unwrap();
+                encoder.encode_end().unwrap();
+                encoder.model_trace().to_vec()
+            };
+            assert_eq!(trace.len(), input.len() + 1);
+
+            let mut cursor = std::io::Cursor::new(&output);
+            let mut decoder = Decoder::with_model(&mut cursor, decoder_model)
+                .unwrap()
+                .with_model_trace(trace);
+            assert_eq!(decoder.decode_to_vec().unwrap(), input);
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics_count_the_encoders_work() {
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output);
+        assert_eq!(encoder.metrics(), super::Metrics::default());
+
+        encoder.encode_slice(&[0; 1000]).unwrap();
+        let metrics = encoder.metrics();
+        assert_eq!(metrics.symbols_encoded, 1000);
+        assert_eq!(metrics.model_rescales, 0);
+        // Every shift is either written already or waiting as underflow.
+        assert_eq!(metrics.bits_written, encoder.bits_written());
+        assert_eq!(
+            metrics.renormalizations,
+            metrics.bits_written + encoder.underflow as u64
+        );
+        encoder.encode_end().unwrap();
+        assert_eq!(encoder.metrics().symbols_encoded, 1001);
+
+        // A decaying model is scaled down once per window.
+        let model = AdaptiveModel::with_decay(100, 1, 2).unwrap().build();
+        let mut other = Vec::new();
+        let mut encoder = Encoder::with_model(&mut other, model);
+        encoder.encode_slice(&[b'x'; 1000]).unwrap();
+        assert_eq!(encoder.metrics().model_rescales, 10);
+        encoder.abandon();
+    }
+
+    #[cfg(feature = "sync-check")]
+    #[test]
+    fn model_trace_catches_a_desync() {
+        let input = b"the decoder's model is set up differently";
+        let mut output = Vec::new();
+        let trace = {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+            encoder.model_trace().to_vec()
+        };
+
+        let model = AdaptiveModel::new().with_increment(2).unwrap().build();
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::with_model(&mut cursor, model)
+            .unwrap()
+            .with_model_trace(trace);
+        let err = decoder.decode_to_vec().unwrap_err();
+        assert!(err.to_string().contains("symbol 0"), "{err}");
+    }
+
+    #[quickcheck]
+    fn bytes_consumed_is_the_stream_length(input: Vec<u8>, trailer: Vec<u8>) {
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        let length = output.len() as u64;
+        output.extend_from_slice(&trailer);
+
+        let mut source = &output[..];
+        let mut decoder = Decoder::new(&mut source).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+        assert_eq!(decoder.bytes_consumed(), length);
+    }
+
+    #[test]
+    fn concatenated_messages_decode_in_sequence() {
+        let messages: [&[u8]; 3] = [b"first message", b"", b"and the third one"];
+
+        let mut output = Vec::new();
+        for message in messages {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(message).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut cursor = std::io::Cursor::new(&output);
+        for message in messages {
+            let start = cursor.position();
+            let consumed = {
+                let mut decoder = Decoder::new(&mut cursor).unwrap();
+                assert_eq!(decoder.decode_to_vec().unwrap(), message);
+                decoder.bytes_consumed()
+            };
+            cursor.set_position(start + consumed);
+        }
+        assert_eq!(cursor.position(), output.len() as u64);
+    }
+
+    #[test]
+    fn coders_can_move_across_threads() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Encoder<'_, Vec<u8>>>();
+        assert_send::<Encoder<'_, Vec<u8>, 64>>();
+        assert_send::<Decoder<'_, std::io::Cursor<&[u8]>>>();
+        assert_send::<Decoder<'_, &[u8], 16>>();
+
+        let input = b"encoded on one thread, decoded on another";
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output);
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                encoder.encode_slice(input).unwrap();
+                encoder.encode_end().unwrap();
+            });
+        });
+
+        let mut source = &output[..];
+        let mut decoder = Decoder::new(&mut source).unwrap();
+        let decoded = std::thread::scope(|scope| {
+            scope
+                .spawn(move || decoder.decode_to_vec().unwrap())
+                .join()
+                .unwrap()
+        });
+        assert_eq!(decoded, input);
+    }
+
+    #[quickcheck]
+    fn pulled_bytes_match_the_buffered_stream(input: Vec<u8>, pulled: usize) {
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output);
+        encoder.encode_slice(&input).unwrap();
+        encoder.encode_end().unwrap();
+        drop(encoder);
+
+        let lazy = EncodedBytes::new(input.iter().copied())
+            .collect::<anyhow::Result<Vec<u8>>>()
+            .unwrap();
+        assert_eq!(lazy, output);
+        if let [crate::compress::MODE_CODED, coded @ ..] = &crate::compress(&input).unwrap()[..] {
+            assert_eq!(lazy, coded);
+        }
+
+        // Pulling some of it and finishing gives the rest.
+        let mut bytes = EncodedBytes::new(input.iter().copied());
+        let mut split = bytes
+            .by_ref()
+            .take(pulled % (output.len() + 1))
+            .collect::<anyhow::Result<Vec<u8>>>()
+            .unwrap();
+        split.extend(bytes.finish().unwrap());
+        assert_eq!(split, output);
+    }
+
+    #[test]
+    fn bytes_are_pulled_before_the_input_runs_out() {
+        let consumed = std::cell::Cell::new(0);
+        let input = b"lazily encoded ".repeat(10_000);
+        let mut bytes = EncodedBytes::new(
+            input
+                .iter()
+                .inspect(|_| consumed.set(consumed.get() + 1))
+                .copied(),
+        );
+
+        let mut output = vec![bytes.next().unwrap().unwrap()];
+        assert!(consumed.get() < 1000, "{}", consumed.get());
+
+        output.extend(bytes.map(Result::unwrap));
+        assert_eq!(consumed.get(), input.len());
+        assert_eq!(
+            Decoder::from_slice(&output)
+                .unwrap()
+                .decode_to_vec()
+                .unwrap(),
+            input
+        );
+    }
+
+    #[quickcheck]
+    fn ending_a_stream_twice_changes_nothing(input: Vec<u8>) {
+        let mut once = Vec::new();
+        let mut encoder = Encoder::new(&mut once);
+        encoder.encode_slice(&input).unwrap();
+        encoder.encode_end().unwrap();
+        drop(encoder);
+
+        let mut twice = Vec::new();
+        let mut encoder = Encoder::new(&mut twice);
+        encoder.encode_slice(&input).unwrap();
+        encoder.encode_end().unwrap();
+        let bits = encoder.bits_written();
+        encoder.encode_end().unwrap();
+        encoder.encode_end_without_eof().unwrap();
+        assert_eq!(encoder.bits_written(), bits);
+
+        // More symbols can't go after the end, they'd never be decoded.
+        assert!(encoder.encode_next(0).is_err());
+        assert!(encoder.encode_slice(b"late").is_err());
+        // Nor can another flush, or a state to carry on from.
+        let err = encoder.flush_boundary().unwrap_err();
+        assert!(err.to_string().contains("already been ended"), "{err}");
+        let err = encoder.save_state().unwrap_err();
+        assert!(err.to_string().contains("already been ended"), "{err}");
+        assert_eq!(twice, once);
+        assert_eq!(
+            Decoder::from_slice(&twice)
+                .unwrap()
+                .decode_to_vec()
+                .unwrap(),
+            input
+        );
+    }
+
+    #[quickcheck]
+    fn independent_encodes_decode_independently(first: Vec<u8>, second: Vec<u8>) {
+        let mut output = Vec::new();
+        for message in [&first, &second] {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(message).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let consumed = {
+            let mut decoder = Decoder::new(&mut cursor).unwrap();
+            assert_eq!(decoder.decode_to_vec().unwrap(), first);
+            decoder.bytes_consumed()
+        };
+        assert!(consumed < output.len() as u64);
+
+        let mut rest = &output[consumed as usize..];
+        let mut decoder = Decoder::new(&mut rest).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), second);
+        assert_eq!(consumed + decoder.bytes_consumed(), output.len() as u64);
+
+        // A reader that can't seek back is handed back no more than a register's worth past the end.
+        let mut decoder = Decoder::new_owned(std::io::Cursor::new(output.clone())).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), first);
+        let position = decoder.into_inner().unwrap().position();
+        assert!(position - consumed <= 4);
+    }
+
+    #[test]
+    fn encode_iter_matches_encode_slice() {
+        let text = "Only The Capitals Of This Sentence Get Kept, Lowercased";
+        let expected = text
+            .bytes()
+            .filter(u8::is_ascii_uppercase)
+            .map(|b| b.to_ascii_lowercase())
+            .collect::<Vec<_>>();
+
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder
+                .encode_iter(
+                    text.bytes()
+                        .filter(u8::is_ascii_uppercase)
+                        .map(|b| b.to_ascii_lowercase()),
+                )
+                .unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut from_slice = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut from_slice);
+            encoder.encode_slice(&expected).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        assert_eq!(output, from_slice);
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), expected);
+    }
+
+    #[test]
+    fn decode_into_fills_exactly_under_and_over() {
+        let input = b"sixteen bytes!!!";
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        let decode_into = |out: &mut [u8]| {
+            let mut cursor = std::io::Cursor::new(&output);
+            Decoder::new(&mut cursor).unwrap().decode_into(out)
+        };
+
+        let mut exact = [0; 16];
+        assert_eq!(decode_into(&mut exact).unwrap(), 16);
+        assert_eq!(&exact, input);
+
+        let mut roomy = [0xAA; 20];
+        assert_eq!(decode_into(&mut roomy).unwrap(), 16);
+        assert_eq!(&roomy[..16], input);
+        assert_eq!(roomy[16..], [0xAA; 4]);
+
+        let mut cramped = [0; 15];
+        assert!(decode_into(&mut cramped).is_err());
+    }
+
+    #[test]
+    fn decode_byte_hides_the_eof_symbol() {
+        let input = b"bytes in, bytes out";
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        let mut decoded = Vec::new();
+        while let Some(b) = decoder.decode_byte().unwrap() {
+            decoded.push(b);
+        }
+
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    fn interval_bits_shrink_as_the_interval_converges() {
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output);
+        assert_eq!(encoder.interval_bits(), 32);
+
+        // The middle half of the interval straddles its midpoint, so every one of these is an underflow
+        // rather than a bit that can be written out.
+        let mut previous = encoder.interval_bits();
+        for _ in 0..20 {
+            encoder.encode_range(1, 3, 4).unwrap();
+            assert!(encoder.interval_bits() < previous);
+            previous = encoder.interval_bits();
+        }
+
+        // Picking a side settles the underflow, and the interval is wide again.
+        encoder.encode_range(0, 1, 4).unwrap();
+        assert!(encoder.interval_bits() >= 30);
+    }
+
+    #[test]
+    fn empty_input_round_trips() {
+        let mut output = Vec::new();
+        Encoder::new(&mut output).encode_end().unwrap();
+
+        // The smallest valid stream: only the EOF symbol and the flush.
+        assert_eq!(output, [0xFF, 0x40]);
+
+        // The decoder primes 32 bits from a 16 bit stream, so the rest come from the EOF branch.
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        assert_eq!(decoder.decode_next().unwrap(), EOF_SYMBOL);
+
+        let mut cursor = std::io::Cursor::new(&output);
+        assert_eq!(
+            Decoder::new(&mut cursor).unwrap().decode_to_vec().unwrap(),
+            []
+        );
+    }
+
+    #[test]
+    fn truncated_stream_is_an_error() {
+        let input = b"a stream that gets cut off part way through its data".repeat(10);
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        for length in [0, 1, output.len() / 2, output.len() - 8] {
+            let mut source = &output[..length];
+            let err = Decoder::new(&mut source)
+                .and_then(|mut decoder| decoder.decode_to_vec())
+                .unwrap_err();
+            assert_eq!(err.downcast_ref::<AeError>(), Some(&AeError::UnexpectedEof));
+        }
+    }
+
+    #[test]
+    fn single_byte_input_round_trips() {
+        for b in 0..=u8::MAX {
+            let mut output = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut output);
+                encoder.encode_next(b as usize).unwrap();
+                encoder.encode_end().unwrap();
+            }
+
+            let mut cursor = std::io::Cursor::new(&output);
+            let mut decoder = Decoder::new(&mut cursor).unwrap();
+            assert_eq!(decoder.decode_to_vec().unwrap(), [b]);
+        }
+    }
+
+    fn round_trips(input: &[u8]) -> bool {
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut source = &output[..];
+        Decoder::new(&mut source)
+            .and_then(|mut decoder| decoder.decode_to_vec())
+            .is_ok_and(|decoded| decoded == input)
+    }
+
+    #[test]
+    fn short_inputs_round_trip_across_byte_values() {
+        // Streams this short are mostly made up bits as far as the decoder's priming is concerned.
+        // Every first byte against a spread of second bytes, including both ends of the alphabet.
+        for a in 0..=u8::MAX {
+            for b in (0..=u8::MAX).step_by(17).chain([1, 0x7F, 0x80, 0xFE]) {
+                assert!(round_trips(&[a, b]), "{a} {b}");
+                assert!(round_trips(&[a, b, a]), "{a} {b} {a}");
+            }
+        }
+    }
+
+    #[quickcheck]
+    fn tiny_inputs_round_trip(input: Vec<u8>) {
+        for length in 0..=input.len().min(6) {
+            assert!(round_trips(&input[..length]), "{:?}", &input[..length]);
+        }
+    }
+
+    #[test]
+    fn tiny_inputs_of_extreme_bytes_round_trip() {
+        // Runs of the highest and lowest symbols push the interval to its edges.
+        for length in 0..=6 {
+            for pattern in [0x00, 0xFF, 0x7F, 0x80] {
+                assert!(round_trips(&vec![pattern; length]));
+            }
+            for split in 0..=length {
+                let mut input = vec![0xFF; split];
+                input.resize(length, 0x00);
+                assert!(round_trips(&input), "{input:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn symbol_costs_add_up_to_the_compressed_size() {
+        let input =
+            b"estimates of the cost of every symbol should add up to the output size ".repeat(50);
+
+        let mut output = Vec::new();
+        let mut estimate = 0.0;
+        {
+            let mut encoder = Encoder::new(&mut output);
+            assert_eq!(encoder.last_symbol_cost(), 0.0);
+            for &b in &input {
+                encoder.encode_next(b as usize).unwrap();
+                estimate += encoder.last_symbol_cost();
+            }
+            encoder.encode_next(EOF_SYMBOL).unwrap();
+            estimate += encoder.last_symbol_cost();
+            encoder.encode_end_without_eof().unwrap();
+        }
+
+        // The coder loses a little to integer rounding and the flush, nothing more.
+        let actual = (output.len() * 8) as f64;
+        assert!(actual >= estimate, "{actual} < {estimate}");
+        assert!(actual - estimate < 64.0, "{actual} vs {estimate}");
     }
 }
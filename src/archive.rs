@@ -0,0 +1,149 @@
+use crate::io::{Read, Write};
+use crate::model::EOF_SYMBOL;
+use crate::{Decoder, Encoder};
+use alloc::string::String;
+use alloc::vec::Vec;
+use anyhow::Result;
+use core::fmt;
+
+/// A minimal container of named byte entries, compressed as a single stream.
+///
+/// All entries go through one encoder and therefore share one adaptive model: the entry count, then
+/// for every entry its name length (u32 LE), name, data length (u64 LE) and data, followed by EOF.
+/// Sharing the model means later entries benefit from the statistics of earlier ones, which is the
+/// right trade for many small similar files, at the cost of not being able to decode an entry without
+/// decoding everything before it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Archive {
+    entries: Vec<(String, Vec<u8>)>,
+}
+
+impl Archive {
+    pub fn new() -> Archive {
+        Archive::default()
+    }
+
+    /// Adds an entry. Adding a name that's already present replaces its contents.
+    pub fn add(&mut self, name: &str, bytes: &[u8]) {
+        match self.entries.iter_mut().find(|(n, _)| n == name) {
+            Some((_, data)) => *data = bytes.to_vec(),
+            None => self.entries.push((name.into(), bytes.to_vec())),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// The entry names in the order they were added.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn write_to<W: Write + fmt::Debug>(&self, writer: &mut W) -> Result<()> {
+        let mut encoder = Encoder::new(writer);
+
+        encoder.encode_slice(&(self.entries.len() as u32).to_le_bytes())?;
+        for (name, data) in &self.entries {
+            encoder.encode_slice(&(name.len() as u32).to_le_bytes())?;
+            encoder.encode_slice(name.as_bytes())?;
+            encoder.encode_slice(&(data.len() as u64).to_le_bytes())?;
+            encoder.encode_slice(data)?;
+        }
+
+        encoder.encode_end()
+    }
+
+    pub fn read_from<R: Read + fmt::Debug>(reader: &mut R) -> Result<Archive> {
+        let mut decoder = Decoder::new(reader)?;
+        let mut archive = Archive::new();
+
+        let count = u32::from_le_bytes(decode_array(&mut decoder)?);
+        for _ in 0..count {
+            let name_len = u32::from_le_bytes(decode_array(&mut decoder)?);
+            let name = String::from_utf8(decode_bytes(&mut decoder, name_len as usize)?)?;
+            let data_len = u64::from_le_bytes(decode_array(&mut decoder)?);
+            let data = decode_bytes(&mut decoder, data_len as usize)?;
+            archive.entries.push((name, data));
+        }
+
+        anyhow::ensure!(
+            decoder.decode_next()? == EOF_SYMBOL,
+            "archive has trailing data after its last entry"
+        );
+
+        Ok(archive)
+    }
+}
+
+fn decode_bytes<R: Read + fmt::Debug>(decoder: &mut Decoder<'_, R>, len: usize) -> Result<Vec<u8>> {
+    // The length comes from the stream, so don't trust it for the allocation.
+    let mut bytes = Vec::with_capacity(len.min(0x10000));
+    for _ in 0..len {
+        let symbol = decoder.decode_next()?;
+        anyhow::ensure!(
+            symbol != EOF_SYMBOL,
+            "archive ended in the middle of an entry"
+        );
+        bytes.push(symbol as u8);
+    }
+
+    Ok(bytes)
+}
+
+fn decode_array<R: Read + fmt::Debug, const N: usize>(
+    decoder: &mut Decoder<'_, R>,
+) -> Result<[u8; N]> {
+    let mut array = [0; N];
+    array.copy_from_slice(&decode_bytes(decoder, N)?);
+
+    Ok(array)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Archive;
+
+    #[test]
+    fn entries_can_be_read_back_by_name() {
+        let mut archive = Archive::new();
+        archive.add(
+            "README",
+            b"A rewrite in rust of the original ae arithmetic encoder",
+        );
+        archive.add("empty", b"");
+        archive.add("data/zeroes.bin", &[0; 1000]);
+
+        let mut output = Vec::new();
+        archive.write_to(&mut output).unwrap();
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let read = Archive::read_from(&mut cursor).unwrap();
+
+        assert_eq!(read, archive);
+        assert_eq!(
+            read.get("README").unwrap(),
+            b"A rewrite in rust of the original ae arithmetic encoder"
+        );
+        assert_eq!(read.get("empty").unwrap(), b"");
+        assert_eq!(read.get("data/zeroes.bin").unwrap(), [0; 1000]);
+        assert_eq!(read.get("missing"), None);
+        assert_eq!(
+            read.names().collect::<Vec<_>>(),
+            ["README", "empty", "data/zeroes.bin"]
+        );
+    }
+
+    #[test]
+    fn adding_an_existing_name_replaces_it() {
+        let mut archive = Archive::new();
+        archive.add("a", b"first");
+        archive.add("a", b"second");
+
+        assert_eq!(archive.get("a").unwrap(), b"second");
+        assert_eq!(archive.names().count(), 1);
+    }
+}
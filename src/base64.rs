@@ -0,0 +1,101 @@
+//! Text-safe wrappers around [`compress`] and [`decompress`], for putting compressed blobs in JSON,
+//! URLs and the like. Uses the URL-safe base64 alphabet (`-` and `_`) without padding, so the text
+//! needs no further escaping.
+
+use crate::{compress, decompress};
+use alloc::string::String;
+use alloc::vec::Vec;
+use anyhow::Result;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Compresses `input` like [`compress`] and encodes the result as base64 text.
+pub fn compress_to_base64(input: &[u8]) -> Result<String> {
+    Ok(encode(&compress(input)?))
+}
+
+/// Decodes base64 text from [`compress_to_base64`] and decompresses it.
+pub fn decompress_from_base64(text: &str) -> Result<Vec<u8>> {
+    decompress(&decode(text)?)
+}
+
+fn encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let mut group = [0; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+
+        // A chunk of n bytes needs n + 1 characters, the rest would only be padding.
+        for i in 0..=chunk.len() {
+            text.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+        }
+    }
+
+    text
+}
+
+fn decode(text: &str) -> Result<Vec<u8>> {
+    anyhow::ensure!(
+        text.len() % 4 != 1,
+        "base64 text can't be {} characters long",
+        text.len()
+    );
+
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3 + 2);
+    for chunk in text.as_bytes().chunks(4) {
+        let mut bits = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            let value = ALPHABET
+                .iter()
+                .position(|&a| a == c)
+                .ok_or_else(|| anyhow::anyhow!("{:?} isn't a base64 character", c as char))?;
+            bits |= (value as u32) << (18 - 6 * i);
+        }
+
+        bytes.extend_from_slice(&bits.to_be_bytes()[1..chunk.len()]);
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress_to_base64, decode, decompress_from_base64, encode};
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn round_trips_through_base64(input: Vec<u8>) {
+        let text = compress_to_base64(&input).unwrap();
+        assert!(text
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_'));
+        assert_eq!(decompress_from_base64(&text).unwrap(), input);
+    }
+
+    #[test]
+    fn matches_the_standard_encoding() {
+        // RFC 4648 test vectors, minus the padding.
+        for (bytes, text) in [
+            (&b""[..], ""),
+            (b"f", "Zg"),
+            (b"fo", "Zm8"),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg"),
+            (b"fooba", "Zm9vYmE"),
+            (b"foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(encode(bytes), text);
+            assert_eq!(decode(text).unwrap(), bytes);
+        }
+        assert_eq!(encode(&[0xFB, 0xFF]), "-_8");
+    }
+
+    #[test]
+    fn rejects_bad_text() {
+        assert!(decode("Zm9vY").is_err());
+        assert!(decode("Zm9v+g").is_err());
+        assert!(decompress_from_base64("not base64!").is_err());
+    }
+}
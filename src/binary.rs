@@ -0,0 +1,182 @@
+//! An adaptive binary arithmetic coder, for sources that come as bits rather than bytes, e.g. the
+//! output of a bit-plane or context modeling stage in an image codec.
+//!
+//! Each bit is coded with a [`BitModel`], a single probability that adapts towards the bits it sees,
+//! instead of a 257-symbol [`crate::SymbolTable`]. The interval narrowing and renormalization are the
+//! bit coder's, so the streams are the same kind of arithmetic code, but a binary stream can only be
+//! decoded by a [`BinaryDecoder`]. There is no EOF, the decoder has to know how many bits to decode.
+
+use crate::ae::{Decoder, Encoder};
+use crate::io::{Read, Write};
+use crate::model::BitModel;
+use anyhow::Result;
+use core::fmt;
+
+/// Encodes bits, each with an adaptive probability.
+#[derive(Debug)]
+pub struct BinaryEncoder<'a, T: Write> {
+    model: BitModel,
+    encoder: Encoder<'a, T>,
+}
+
+/// Decodes bits written by a [`BinaryEncoder`].
+#[derive(Debug)]
+pub struct BinaryDecoder<'a, T: Read> {
+    model: BitModel,
+    decoder: Decoder<'a, T>,
+}
+
+impl<'a, T: Write + fmt::Debug> BinaryEncoder<'a, T> {
+    pub fn new(writer: &'a mut T) -> BinaryEncoder<'a, T> {
+        BinaryEncoder {
+            model: BitModel::new(),
+            encoder: Encoder::new(writer),
+        }
+    }
+
+    /// Encodes `bit` with the encoder's own model, which then adapts towards it.
+    pub fn encode_bit(&mut self, bit: bool) -> Result<()> {
+        let mut model = self.model;
+        self.encode_bit_with(&mut model, bit)?;
+        self.model = model;
+
+        Ok(())
+    }
+
+    /// Encodes `bit` with `model` instead of the encoder's own, for sources that keep a model per
+    /// context. The decoder has to use the same model for it in [`BinaryDecoder::decode_bit_with`].
+    pub fn encode_bit_with(&mut self, model: &mut BitModel, bit: bool) -> Result<()> {
+        let (low, high) = model.interval(bit);
+        self.encoder.encode_range(low, high, BitModel::TOTAL)?;
+        model.update(bit);
+
+        Ok(())
+    }
+
+    /// Finishes the stream. The decoder has to be told how many bits it holds.
+    pub fn encode_end(&mut self) -> Result<()> {
+        self.encoder.encode_end_without_eof()
+    }
+}
+
+impl<'a, T: Read + fmt::Debug> BinaryDecoder<'a, T> {
+    pub fn new(reader: &'a mut T) -> Result<BinaryDecoder<'a, T>> {
+        Ok(BinaryDecoder {
+            model: BitModel::new(),
+            decoder: Decoder::new(reader)?,
+        })
+    }
+
+    /// Decodes a bit written by [`BinaryEncoder::encode_bit`].
+    pub fn decode_bit(&mut self) -> Result<bool> {
+        let mut model = self.model;
+        let bit = self.decode_bit_with(&mut model)?;
+        self.model = model;
+
+        Ok(bit)
+    }
+
+    /// Decodes a bit written by [`BinaryEncoder::encode_bit_with`] with the same `model`.
+    pub fn decode_bit_with(&mut self, model: &mut BitModel) -> Result<bool> {
+        let bit = self.decoder.decode_target(BitModel::TOTAL)? >= model.zero_count();
+        let (low, high) = model.interval(bit);
+        self.decoder.decode_range(low, high, BitModel::TOTAL)?;
+        model.update(bit);
+
+        Ok(bit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BinaryDecoder, BinaryEncoder};
+    use crate::model::BitModel;
+    use crate::xorshift::Xorshift;
+    use crate::Encoder;
+    use quickcheck_macros::quickcheck;
+
+    fn biased_bits(length: usize, ones_in_256: u64) -> Vec<bool> {
+        Xorshift::with_seed(0x9E3779B97F4A7C15)
+            .take(length)
+            .map(|r| r >> 56 < ones_in_256)
+            .collect()
+    }
+
+    #[quickcheck]
+    fn bits_round_trip(bits: Vec<bool>) {
+        let mut output = Vec::new();
+        {
+            let mut encoder = BinaryEncoder::new(&mut output);
+            for &bit in &bits {
+                encoder.encode_bit(bit).unwrap();
+            }
+            encoder.encode_end().unwrap();
+        }
+
+        let mut reader = &output[..];
+        let mut decoder = BinaryDecoder::new(&mut reader).unwrap();
+        for &bit in &bits {
+            assert_eq!(decoder.decode_bit().unwrap(), bit);
+        }
+    }
+
+    #[test]
+    fn biased_bits_compress_close_to_their_entropy() {
+        // One bit in 16 set: about 0.34 bits of information per bit.
+        let bits = biased_bits(100_000, 16);
+        let mut output = Vec::new();
+        {
+            let mut encoder = BinaryEncoder::new(&mut output);
+            for &bit in &bits {
+                encoder.encode_bit(bit).unwrap();
+            }
+            encoder.encode_end().unwrap();
+        }
+        assert!(output.len() < 100_000 * 36 / 100 / 8, "{}", output.len());
+
+        // The same bits as byte symbols through the 257-symbol table cost a lot more.
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut bytes);
+            encoder
+                .encode_iter(bits.iter().map(|&bit| bit as u8))
+                .unwrap();
+            encoder.encode_end().unwrap();
+        }
+        assert!(output.len() < bytes.len());
+
+        let mut reader = &output[..];
+        let mut decoder = BinaryDecoder::new(&mut reader).unwrap();
+        for &bit in &bits {
+            assert_eq!(decoder.decode_bit().unwrap(), bit);
+        }
+    }
+
+    #[test]
+    fn models_per_context_stay_in_step() {
+        // Even positions are mostly zero, odd ones mostly one.
+        let bits = biased_bits(10_000, 20)
+            .into_iter()
+            .enumerate()
+            .map(|(i, bit)| bit ^ (i % 2 == 1))
+            .collect::<Vec<_>>();
+
+        let mut output = Vec::new();
+        {
+            let mut models = [BitModel::new(); 2];
+            let mut encoder = BinaryEncoder::new(&mut output);
+            for (i, &bit) in bits.iter().enumerate() {
+                encoder.encode_bit_with(&mut models[i % 2], bit).unwrap();
+            }
+            encoder.encode_end().unwrap();
+        }
+        assert!(output.len() < 10_000 / 8 / 2);
+
+        let mut models = [BitModel::new(); 2];
+        let mut reader = &output[..];
+        let mut decoder = BinaryDecoder::new(&mut reader).unwrap();
+        for (i, &bit) in bits.iter().enumerate() {
+            assert_eq!(decoder.decode_bit_with(&mut models[i % 2]).unwrap(), bit);
+        }
+    }
+}
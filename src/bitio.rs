@@ -1,89 +1,472 @@
-use anyhow::Result;
-use std::io::{Read, Write};
+use crate::error::AeError;
+use crate::io::{self, Read, Write};
+use alloc::vec::Vec;
+use anyhow::{Context, Result};
+use core::ops::{Deref, DerefMut};
 
+/// A reader or writer that's either borrowed from the caller or owned outright.
 #[derive(Debug)]
-pub(crate) struct BitWriter<'a, Writer: Write> {
-    writer: &'a mut Writer,
-    buffer_length: usize,
-    buffer: u8,
+pub(crate) enum Handle<'a, T> {
+    Borrowed(&'a mut T),
+    Owned(T),
+}
+
+impl<T> Deref for Handle<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            Handle::Borrowed(inner) => inner,
+            Handle::Owned(inner) => inner,
+        }
+    }
 }
 
+impl<T> DerefMut for Handle<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        match self {
+            Handle::Borrowed(inner) => inner,
+            Handle::Owned(inner) => inner,
+        }
+    }
+}
+
+/// How many completed bytes [`BitWriter`] collects before handing them to the writer in one call.
+const BATCH_SIZE: usize = 4096;
+
+/// Writes a stream one bit at a time, most significant bit of each byte first. Completed bytes are
+/// collected and passed on to the writer [`BATCH_SIZE`] at a time, so an unbuffered writer doesn't see
+/// a call per byte. [`BitWriter::flush`] writes out everything, padding a partial byte with zeroes;
+/// dropping the writer without it still writes the completed bytes, but loses the partial one, and
+/// a failure to write them is only logged through `tracing`.
 #[derive(Debug)]
-pub(crate) struct BitReader<'a, Reader: Read> {
-    reader: &'a mut Reader,
+pub struct BitWriter<'a, Writer: Write> {
+    /// Only `None` once [`BitWriter::into_inner`] has taken it, so `Drop` knows there's nothing to do.
+    writer: Option<Handle<'a, Writer>>,
+    batch: Vec<u8>,
+    /// Set once the writer returns an error, after which nothing more is written.
+    failed: bool,
     buffer_length: usize,
     buffer: u8,
+    bits_written: u64,
+    /// Writing past this many bits is an error, see [`BitWriter::with_budget`].
+    budget: u64,
+}
+
+/// Reads a stream written by [`BitWriter`] one bit at a time.
+#[derive(Debug)]
+pub struct BitReader<'a, Reader: Read> {
+    reader: Handle<'a, Reader>,
+    /// Number of unread bits, which are the low `buffer_length` bits of `buffer`. Usually at most 8,
+    /// peeking can pull in more, up to 64.
+    buffer_length: u32,
+    buffer: u64,
+    bits_read: u64,
+    /// Reads past this many bits come back as `EOF`, see [`BitReader::take`].
+    limit: u64,
 }
 
+/// The most bits [`BitReader::peek_bits`] can look ahead. Bytes are buffered whole, so 7 bits left
+/// over from the last one plus 7 more bytes is all that's guaranteed to fit in the buffer.
+pub const MAX_PEEK_BITS: u32 = u64::BITS - 7;
+
+/// One step of [`BitReader::read`]: the next bit, or `EOF` once the underlying reader is exhausted.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Eq, PartialEq)]
-pub(crate) enum ReadResult {
+pub enum ReadResult {
     EOF,
     Bit(bool),
 }
 
-impl<T: Read> BitReader<'_, T> {
-    pub(crate) fn new(reader: &mut T) -> BitReader<T> {
+impl<'a, T: Read> BitReader<'a, T> {
+    pub fn new(reader: &mut T) -> BitReader<'_, T> {
+        BitReader::with_handle(Handle::Borrowed(reader))
+    }
+
+    pub(crate) fn with_handle(reader: Handle<'a, T>) -> BitReader<'a, T> {
         BitReader {
             reader,
             buffer_length: 0,
             buffer: 0,
+            bits_read: 0,
+            limit: u64::MAX,
         }
     }
 
-    pub(crate) fn read(&mut self) -> Result<ReadResult> {
-        if self.buffer_length == 0 {
-            let mut buff: &mut [u8] = &mut [0];
+    /// Ends the stream `bits` bits from the current position: reads after that return `EOF` even if
+    /// the underlying reader has more, e.g. when the stream is embedded in a container that frames it
+    /// with a bit length. The underlying reader isn't read past the byte the limit falls in.
+    pub fn take(mut self, bits: u64) -> Self {
+        self.limit = self.bits_read.saturating_add(bits);
+        self
+    }
 
-            if self.reader.read(&mut buff)? == 1 {
-                self.buffer = buff[0];
-                self.buffer_length = 8;
-            } else {
-                return Ok(ReadResult::EOF);
-            }
+    /// Number of bits asked for so far, counting the ones past the end of the stream that came back as
+    /// `EOF`.
+    pub fn bits_read(&self) -> u64 {
+        self.bits_read
+    }
+
+    /// Hands back the underlying reader. Bits already pulled into the buffer are lost.
+    pub(crate) fn into_inner(self) -> Handle<'a, T> {
+        self.reader
+    }
+
+    /// Counts `bits` that were skipped over before this reader took over the stream, so
+    /// [`BitReader::bits_read`] stays relative to the start of the stream.
+    pub(crate) fn skipped(&mut self, bits: u64) {
+        self.bits_read += bits;
+    }
+
+    pub fn read(&mut self) -> Result<ReadResult> {
+        self.bits_read += 1;
+
+        if self.bits_read > self.limit || (self.buffer_length == 0 && !self.fill()?) {
+            return Ok(ReadResult::EOF);
         }
-        let ret = self.buffer & (1 << (self.buffer_length - 1)) != 0;
         self.buffer_length -= 1;
-        Ok(ReadResult::Bit(ret))
+        Ok(ReadResult::Bit(self.buffer >> self.buffer_length & 1 == 1))
+    }
+
+    /// Returns the bit the next [`BitReader::read`] will, without consuming it.
+    pub fn peek(&mut self) -> Result<ReadResult> {
+        if self.bits_read >= self.limit || (self.buffer_length == 0 && !self.fill()?) {
+            return Ok(ReadResult::EOF);
+        }
+        Ok(ReadResult::Bit(
+            self.buffer >> (self.buffer_length - 1) & 1 == 1,
+        ))
+    }
+
+    /// Returns the value the next [`BitReader::read_bits`] with the same `count` will, without consuming
+    /// anything. `count` can be at most [`MAX_PEEK_BITS`].
+    pub fn peek_bits(&mut self, count: u32) -> Result<u64> {
+        anyhow::ensure!(
+            count <= MAX_PEEK_BITS,
+            "can only peek {MAX_PEEK_BITS} bits ahead, not {count}"
+        );
+        if self.limit - self.bits_read.min(self.limit) < count as u64 {
+            return Err(AeError::UnexpectedEof.into());
+        }
+
+        while self.buffer_length < count {
+            if !self.fill()? {
+                return Err(AeError::UnexpectedEof.into());
+            }
+        }
+
+        let mask = u64::MAX.checked_shr(u64::BITS - count).unwrap_or(0);
+
+        Ok(self.buffer >> (self.buffer_length - count) & mask)
+    }
+
+    /// Appends the next byte of the underlying reader to the buffer, returning `false` if it's
+    /// exhausted. Only ever one byte, so the reader is never further ahead than the bits asked for
+    /// need: whoever gets it back afterwards, e.g. to decode the stream after this one, finds it at
+    /// most a byte past them.
+    fn fill(&mut self) -> Result<bool> {
+        let buff: &mut [u8] = &mut [0];
+        if self.reader.read(buff)? != 1 {
+            return Ok(false);
+        }
+
+        self.buffer = self.buffer << 8 | buff[0] as u64;
+        self.buffer_length += 8;
+
+        Ok(true)
+    }
+
+    /// Reads `count` bits (at most 64), most significant first, into the low bits of the result. Running
+    /// out of stream part way is an [`AeError::UnexpectedEof`].
+    pub fn read_bits(&mut self, count: u32) -> Result<u64> {
+        debug_assert!(count <= u64::BITS);
+
+        let mut value = 0u64;
+        for _ in 0..count {
+            match self.read()? {
+                ReadResult::Bit(bit) => value = (value << 1) | bit as u64,
+                ReadResult::EOF => return Err(AeError::UnexpectedEof.into()),
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Reads a count written by [`BitWriter::write_unary`].
+    pub fn read_unary(&mut self) -> Result<u64> {
+        let mut n = 0u64;
+        loop {
+            match self.read()? {
+                ReadResult::Bit(true) => n += 1,
+                ReadResult::Bit(false) => return Ok(n),
+                ReadResult::EOF => return Err(AeError::UnexpectedEof.into()),
+            }
+        }
+    }
+
+    /// Reads a value written by [`BitWriter::write_gamma`].
+    pub fn read_gamma(&mut self) -> Result<u64> {
+        let width = self.read_unary()?;
+        if width > u64::BITS as u64 {
+            return Err(AeError::CorruptStream)
+                .context(alloc::format!("a gamma code can't be {width} bits wide"));
+        }
+
+        let value = (1u128 << width | self.read_bits(width as u32)? as u128) - 1;
+        u64::try_from(value)
+            .map_err(|_| AeError::CorruptStream)
+            .context("a gamma code is out of range")
+    }
+
+    /// Fills `buf` with whole bytes read from the current bit position, which doesn't have to be byte
+    /// aligned.
+    pub fn read_bytes(&mut self, buf: &mut [u8]) -> Result<()> {
+        for byte in buf {
+            *byte = self.read_bits(8)? as u8;
+        }
+
+        Ok(())
     }
 }
 
-impl<T: Write> BitWriter<'_, T> {
-    pub(crate) fn new(writer: &mut T) -> BitWriter<'_, T> {
+impl<'a, T: Write> BitWriter<'a, T> {
+    pub fn new(writer: &mut T) -> BitWriter<'_, T> {
+        BitWriter::with_handle(Handle::Borrowed(writer))
+    }
+
+    pub(crate) fn with_handle(writer: Handle<'a, T>) -> BitWriter<'a, T> {
         BitWriter {
-            writer,
+            writer: Some(writer),
+            batch: Vec::with_capacity(BATCH_SIZE),
+            failed: false,
             buffer_length: 0,
             buffer: 0,
+            bits_written: 0,
+            budget: u64::MAX,
         }
     }
 
-    pub(crate) fn write(&mut self, x: bool) -> Result<()> {
+    /// Allows at most `bits` more bits to be written, padding included. Going over fails instead of
+    /// writing, for a stream that has to fit a fixed size slot of a larger container.
+    pub fn with_budget(mut self, bits: u64) -> Self {
+        self.budget = self.bits_written.saturating_add(bits);
+        self
+    }
+
+    fn check_budget(&self, bits: u64) -> Result<()> {
+        if self.bits_written + bits > self.budget {
+            return Err(self.over_budget(bits));
+        }
+
+        Ok(())
+    }
+
+    #[cold]
+    fn over_budget(&self, bits: u64) -> anyhow::Error {
+        anyhow::anyhow!(
+            "writing {bits} more bits at bit {} goes over the budget of {}",
+            self.bits_written,
+            self.budget
+        )
+    }
+
+    pub fn write(&mut self, x: bool) -> Result<()> {
+        self.check_budget(1)?;
+
         self.buffer |= (if x { 1 } else { 0 }) << (7 - self.buffer_length);
         self.buffer_length += 1;
+        self.bits_written += 1;
 
         if self.buffer_length == 8 {
-            anyhow::ensure!(self.writer.write(&[self.buffer])? == 1);
+            self.batch.push(self.buffer);
             self.buffer_length = 0;
             self.buffer = 0;
+
+            if self.batch.len() == BATCH_SIZE {
+                self.write_batch()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Passes the completed bytes collected so far on to the writer. Once that has failed, every later
+    /// call fails with [`AeError::Poisoned`] instead, since some unknown part of the batch is missing.
+    pub(crate) fn write_batch(&mut self) -> Result<()> {
+        self.ensure_usable()?;
+
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = io::write_all(&mut **writer, &self.batch) {
+                self.failed = true;
+                return Err(e);
+            }
+        }
+        self.batch.clear();
+
+        Ok(())
+    }
+
+    /// Fails with [`AeError::Poisoned`] if an earlier write to the underlying writer failed.
+    pub(crate) fn ensure_usable(&self) -> Result<()> {
+        if self.failed {
+            return Err(AeError::Poisoned.into());
+        }
+
+        Ok(())
+    }
+
+    /// Writes the low `count` bits of `value` (at most 64), most significant first.
+    pub fn write_bits(&mut self, value: u64, count: u32) -> Result<()> {
+        debug_assert!(count <= u64::BITS);
+
+        for i in (0..count).rev() {
+            self.write(value >> i & 1 == 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `n` as that many one bits and a zero, for counts that are nearly always small.
+    pub fn write_unary(&mut self, n: u64) -> Result<()> {
+        for _ in 0..n {
+            self.write(true)?;
+        }
+
+        self.write(false)
+    }
+
+    /// Writes `n` as an Elias gamma code: the width of `n + 1` in unary followed by its bits below the
+    /// leading one, so `0` takes a single bit and the bigger values about twice their width.
+    pub fn write_gamma(&mut self, n: u64) -> Result<()> {
+        let value = n.wrapping_add(1);
+        let width = match value {
+            0 => u64::BITS,
+            _ => u64::BITS - 1 - value.leading_zeros(),
+        };
+
+        self.write_unary(width as u64)?;
+        self.write_bits(value, width)
+    }
+
+    /// Writes `bytes` from the current bit position, without aligning to a byte boundary first.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        for &b in bytes {
+            self.write_byte(b)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the 8 bits of `byte` in one go, the same as [`BitWriter::write_bits`] with 8.
+    pub(crate) fn write_byte(&mut self, byte: u8) -> Result<()> {
+        self.check_budget(8)?;
+
+        if self.buffer_length == 0 {
+            self.batch.push(byte);
+        } else {
+            self.batch.push(self.buffer | byte >> self.buffer_length);
+            self.buffer = byte << (8 - self.buffer_length);
+        }
+        self.bits_written += 8;
+
+        if self.batch.len() == BATCH_SIZE {
+            self.write_batch()?;
         }
 
         Ok(())
     }
 
-    pub(crate) fn flush(&mut self) -> Result<()> {
+    /// Number of bits written so far, including ones still sitting in the partial byte buffer and the
+    /// padding of earlier calls to `flush`, so it's always the current position in the stream.
+    pub fn bits_written(&self) -> u64 {
+        self.bits_written
+    }
+
+    /// Hands back the underlying writer after writing out the completed bytes. A partial byte is lost,
+    /// so call `flush` first.
+    pub(crate) fn into_inner(mut self) -> Result<Handle<'a, T>> {
+        self.write_batch()?;
+
+        Ok(self
+            .writer
+            .take()
+            .expect("only into_inner takes the writer"))
+    }
+
+    /// Whether the writer was handed over by value rather than borrowed.
+    pub(crate) fn owns_writer(&self) -> bool {
+        matches!(self.writer, Some(Handle::Owned(_)))
+    }
+
+    /// The writer, e.g. to take what has reached an in-memory one so far.
+    pub(crate) fn writer_mut(&mut self) -> Option<&mut T> {
+        self.writer.as_deref_mut()
+    }
+
+    /// Writes out the completed bytes and flushes the underlying writer like `flush`, but without
+    /// padding: the partial byte is returned instead, its bits at the top, for [`BitWriter::resume`].
+    pub(crate) fn suspend(mut self) -> Result<u8> {
+        self.write_batch()?;
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+
+        Ok(self.buffer)
+    }
+
+    /// Carries on a stream [`BitWriter::suspend`] stopped `bits_written` bits in, with `partial` the
+    /// partial byte it returned. The writer has to be positioned right after the completed bytes.
+    pub(crate) fn resume(mut self, bits_written: u64, partial: u8) -> Self {
+        self.bits_written = bits_written;
+        self.buffer_length = (bits_written % 8) as usize;
+        self.buffer = partial;
+        self
+    }
+
+    /// Pads a partial byte with zeroes, writes out everything buffered and flushes the underlying
+    /// writer, so the bytes reach the sink even through a `BufWriter`.
+    pub fn flush(&mut self) -> Result<()> {
         if self.buffer_length > 0 {
-            anyhow::ensure!(self.writer.write(&[self.buffer])? == 1);
+            self.check_budget(8 - self.buffer_length as u64)?;
+            self.batch.push(self.buffer);
+            self.bits_written += 8 - self.buffer_length as u64;
             self.buffer_length = 0;
             self.buffer = 0;
         }
 
+        self.write_batch()?;
+
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = writer.flush() {
+                self.failed = true;
+                return Err(e);
+            }
+        }
+
         Ok(())
     }
 }
 
+impl<T: Write> Drop for BitWriter<'_, T> {
+    fn drop(&mut self) {
+        // Nowhere to return a failure from here, so it's only logged; callers that care flush first.
+        // After an earlier failure the caller has already seen the error.
+        if self.failed || self.batch.is_empty() {
+            return;
+        }
+        let unwritten = self.batch.len();
+        if let Err(e) = self.write_batch() {
+            tracing::warn!("bit writer dropped without writing its last {unwritten} bytes: {e}");
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::ReadResult;
+    use super::MAX_PEEK_BITS;
     use super::{BitReader, BitWriter};
+    use crate::{AeError, Decoder, Encoder};
     use quickcheck_macros::quickcheck;
 
     #[quickcheck]
@@ -151,4 +534,404 @@ mod test {
 
         assert_eq!(output, [0b11110000]);
     }
+
+    #[derive(Debug, Default)]
+    struct CountingWriter {
+        bytes: Vec<u8>,
+        calls: usize,
+        flushes: usize,
+    }
+
+    impl std::io::Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.calls += 1;
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    /// Accepts nothing on every third call and at most 100 bytes on the others, like a pipe that's
+    /// being drained slowly.
+    #[derive(Debug, Default)]
+    struct StutteringWriter {
+        bytes: Vec<u8>,
+        calls: usize,
+    }
+
+    impl std::io::Write for StutteringWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.calls += 1;
+            if self.calls.is_multiple_of(3) {
+                return Ok(0);
+            }
+
+            let n = buf.len().min(100);
+            self.bytes.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn short_and_empty_writes_are_retried() {
+        let input = (0..20_000)
+            .map(|i| (i * 13 % 251) as u8)
+            .collect::<Vec<_>>();
+        let mut expected = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut expected);
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        let mut output = StutteringWriter::default();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        assert_eq!(output.bytes, expected);
+        assert!(output.calls > expected.len() / 100);
+
+        // A writer that never takes anything fails instead of spinning forever.
+        let mut full: &mut [u8] = &mut [];
+        let mut writer = BitWriter::new(&mut full);
+        writer.write_bits(0xAB, 8).unwrap();
+        assert!(writer.flush().is_err());
+    }
+
+    #[test]
+    fn writes_are_batched() {
+        let input = (0..10_000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let mut output = CountingWriter::default();
+
+        {
+            let mut writer = BitWriter::new(&mut output);
+            writer.write_bytes(&input).unwrap();
+            writer.write(true).unwrap();
+            writer.flush().unwrap();
+        }
+
+        // Two full batches on the way, the rest and the padded bit on flush.
+        assert_eq!(output.calls, 3);
+        assert_eq!(output.bytes[..input.len()], input);
+        assert_eq!(output.bytes[input.len()..], [0b10000000]);
+        assert_eq!(output.flushes, 1);
+    }
+
+    #[test]
+    fn encode_end_flushes_through_a_buf_writer() {
+        let input = b"everything has to reach the sink without flushing the BufWriter by hand";
+        let mut sink = CountingWriter::default();
+        let mut buffered = std::io::BufWriter::new(&mut sink);
+
+        {
+            let mut encoder = Encoder::new(&mut buffered);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        // The BufWriter is still alive, so its own flush on drop hasn't run yet.
+        let stream = buffered.get_ref().bytes.clone();
+        assert_eq!(buffered.get_ref().flushes, 1);
+        let decoded = Decoder::from_slice(&stream)
+            .unwrap()
+            .decode_to_vec()
+            .unwrap();
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    fn bytes_and_bits_interleave() {
+        let mut output = Vec::new();
+
+        {
+            let mut writer = BitWriter::new(&mut output);
+
+            writer.write(true).unwrap();
+            writer.write_bytes(b"header").unwrap();
+            writer.write_bits(0b101, 3).unwrap();
+            writer.write_bytes(&0xDEADBEEFu32.to_le_bytes()).unwrap();
+            writer.write(false).unwrap();
+            writer.write_bytes(&[]).unwrap();
+            writer.write_bits(u64::MAX, 64).unwrap();
+            writer.flush().unwrap();
+        }
+
+        // 1 + 48 + 3 + 32 + 1 + 64 bits, padded to 19 bytes.
+        assert_eq!(output.len(), 19);
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut reader = BitReader::new(&mut cursor);
+        let mut header = [0; 6];
+        let mut checksum = [0; 4];
+
+        assert_eq!(reader.read().unwrap(), ReadResult::Bit(true));
+        reader.read_bytes(&mut header).unwrap();
+        assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+        reader.read_bytes(&mut checksum).unwrap();
+        assert_eq!(reader.read().unwrap(), ReadResult::Bit(false));
+        assert_eq!(reader.read_bits(64).unwrap(), u64::MAX);
+
+        assert_eq!(&header, b"header");
+        assert_eq!(u32::from_le_bytes(checksum), 0xDEADBEEF);
+
+        // Only the 5 padding bits are left.
+        assert!(reader.read_bytes(&mut [0]).is_err());
+    }
+
+    #[test]
+    fn peek_does_not_consume() {
+        let src = vec![0b10110010, 0b01111111, 0xA5];
+        let mut cursor = std::io::Cursor::new(src);
+        let mut reader = BitReader::new(&mut cursor);
+
+        assert_eq!(reader.peek().unwrap(), ReadResult::Bit(true));
+        assert_eq!(reader.peek().unwrap(), ReadResult::Bit(true));
+        assert_eq!(reader.read().unwrap(), ReadResult::Bit(true));
+        assert_eq!(reader.peek().unwrap(), ReadResult::Bit(false));
+        assert_eq!(reader.read().unwrap(), ReadResult::Bit(false));
+
+        // Across the byte boundary, more bits than a single byte holds.
+        assert_eq!(reader.peek_bits(0).unwrap(), 0);
+        assert_eq!(reader.peek_bits(12).unwrap(), 0b110010_011111);
+        assert_eq!(reader.read_bits(12).unwrap(), 0b110010_011111);
+        assert_eq!(reader.bits_read(), 14);
+
+        assert_eq!(reader.peek_bits(10).unwrap(), 0b11_10100101);
+        assert!(reader.peek_bits(11).is_err());
+        assert_eq!(reader.read_bits(10).unwrap(), 0b11_10100101);
+
+        // At the end, peeking reports EOF and leaves the reader where it was.
+        assert_eq!(reader.peek().unwrap(), ReadResult::EOF);
+        assert_eq!(reader.bits_read(), 24);
+        assert_eq!(reader.read().unwrap(), ReadResult::EOF);
+
+        let src = (0..16u8).collect::<Vec<_>>();
+        let mut cursor = std::io::Cursor::new(&src);
+        let mut reader = BitReader::new(&mut cursor);
+        reader.read_bits(7).unwrap();
+        let peeked = reader.peek_bits(MAX_PEEK_BITS).unwrap();
+        assert!(reader.peek_bits(MAX_PEEK_BITS + 1).is_err());
+        assert_eq!(reader.read_bits(MAX_PEEK_BITS).unwrap(), peeked);
+    }
+
+    /// Hands out at most `chunk` bytes per read, so refills come up short at every offset.
+    struct ShortReader<'a> {
+        bytes: &'a [u8],
+        chunk: usize,
+    }
+
+    impl std::io::Read for ShortReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.chunk).min(self.bytes.len());
+            buf[..n].copy_from_slice(&self.bytes[..n]);
+            self.bytes = &self.bytes[n..];
+            Ok(n)
+        }
+    }
+
+    #[quickcheck]
+    fn reads_cross_refills_unchanged(input: Vec<u8>, chunk: u8) {
+        let bit = |i: usize| input[i / 8] >> (7 - i % 8) & 1 == 1;
+        let mut source = ShortReader {
+            bytes: &input,
+            chunk: chunk as usize % 11 + 1,
+        };
+        let mut reader = BitReader::new(&mut source);
+
+        // Widths that don't divide a byte or the buffer, mixing single bits with wide peeks.
+        let mut position = 0;
+        for width in (1..=13).cycle() {
+            if position + width > input.len() * 8 {
+                break;
+            }
+            let expected = (position..position + width).fold(0, |v, i| v << 1 | bit(i) as u64);
+            if width % 3 == 0 {
+                assert_eq!(reader.peek_bits(width as u32).unwrap(), expected);
+            }
+            if width == 1 {
+                assert_eq!(reader.read().unwrap(), ReadResult::Bit(expected == 1));
+            } else {
+                assert_eq!(reader.read_bits(width as u32).unwrap(), expected);
+            }
+            position += width;
+        }
+
+        for i in position..input.len() * 8 {
+            assert_eq!(reader.read().unwrap(), ReadResult::Bit(bit(i)));
+        }
+        assert_eq!(reader.read().unwrap(), ReadResult::EOF);
+        assert_eq!(reader.bits_read(), input.len() as u64 * 8 + 1);
+    }
+
+    #[test]
+    fn reader_stops_at_its_limit() {
+        let src = [0b1011_0110, 0xFF, 0xFF];
+
+        // Exactly the budget: every bit, then EOF although the source goes on.
+        let mut cursor = std::io::Cursor::new(&src);
+        let mut reader = BitReader::new(&mut cursor).take(8);
+        assert_eq!(reader.read_bits(8).unwrap(), 0b1011_0110);
+        assert_eq!(reader.peek().unwrap(), ReadResult::EOF);
+        assert_eq!(reader.read().unwrap(), ReadResult::EOF);
+
+        // Under the budget reads are unaffected, and peeking can't see past the limit.
+        let mut cursor = std::io::Cursor::new(&src);
+        let mut reader = BitReader::new(&mut cursor);
+        reader.read_bits(3).unwrap();
+        let mut reader = reader.take(10);
+        assert_eq!(reader.read_bits(4).unwrap(), 0b1_011);
+        assert_eq!(reader.peek_bits(6).unwrap(), 0b0_11111);
+        assert!(reader.peek_bits(7).is_err());
+
+        // Over the budget is the same as running out of stream.
+        assert!(reader.read_bits(7).is_err());
+        assert_eq!(reader.bits_read(), 14);
+    }
+
+    #[test]
+    fn writer_keeps_to_its_budget() {
+        // Exactly the budget, padding included.
+        let mut output = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut output).with_budget(16);
+            writer.write_bits(0b1_0110_0111, 9).unwrap();
+            writer.flush().unwrap();
+            assert_eq!(writer.bits_written(), 16);
+        }
+        assert_eq!(output, [0b1011_0011, 0b1000_0000]);
+
+        // Under the budget leaves room to spare.
+        let mut output = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut output).with_budget(24);
+            writer.write_bytes(b"a").unwrap();
+            writer.flush().unwrap();
+            writer.write(true).unwrap();
+        }
+        assert_eq!(output, b"a");
+
+        // Over the budget fails without writing the bit, whether it's data or padding.
+        let mut output = Vec::new();
+        let mut writer = BitWriter::new(&mut output).with_budget(12);
+        writer.write_bits(0xABC, 12).unwrap();
+        assert!(writer.write(false).is_err());
+        assert_eq!(writer.bits_written(), 12);
+        assert!(writer.flush().is_err());
+    }
+
+    #[quickcheck]
+    fn unary_and_gamma_codes_round_trip(small: Vec<u8>, large: Vec<u64>) {
+        let fixed = [
+            0,
+            1,
+            2,
+            3,
+            255,
+            256,
+            u32::MAX as u64,
+            u64::MAX - 1,
+            u64::MAX,
+        ];
+
+        let mut output = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut output);
+            for &n in &small {
+                writer.write_unary(n as u64).unwrap();
+            }
+            for &n in fixed.iter().chain(&large) {
+                writer.write_gamma(n).unwrap();
+            }
+            writer.flush().unwrap();
+        }
+
+        let mut source = &output[..];
+        let mut reader = BitReader::new(&mut source);
+        for &n in &small {
+            assert_eq!(reader.read_unary().unwrap(), n as u64);
+        }
+        for &n in fixed.iter().chain(&large) {
+            assert_eq!(reader.read_gamma().unwrap(), n);
+        }
+    }
+
+    #[test]
+    fn gamma_codes_are_short_for_small_values() {
+        let mut output = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut output);
+            writer.write_gamma(0).unwrap();
+            assert_eq!(writer.bits_written(), 1);
+            writer.write_gamma(6).unwrap();
+            assert_eq!(writer.bits_written(), 1 + 5);
+            writer.write_gamma(u64::MAX).unwrap();
+            assert_eq!(writer.bits_written(), 1 + 5 + 129);
+        }
+
+        // A width past 64 bits can't be a gamma code.
+        let mut source = &[0xFF; 9][..];
+        assert!(BitReader::new(&mut source).read_gamma().is_err());
+    }
+
+    /// Interrupted on its first read, then hands out `bytes`, then fails for good.
+    struct FailingReader<'a> {
+        bytes: &'a [u8],
+        interrupted: bool,
+    }
+
+    impl std::io::Read for FailingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if !self.interrupted {
+                self.interrupted = true;
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            if self.bytes.is_empty() {
+                return Err(std::io::Error::other("disk on fire"));
+            }
+
+            let n = buf.len().min(self.bytes.len());
+            buf[..n].copy_from_slice(&self.bytes[..n]);
+            self.bytes = &self.bytes[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn read_failures_are_not_the_end_of_the_stream() {
+        let mut source = FailingReader {
+            bytes: &[0xFF],
+            interrupted: false,
+        };
+        let mut reader = BitReader::new(&mut source);
+
+        // The interrupted read is retried.
+        assert_eq!(reader.read_bits(8).unwrap(), 0xFF);
+
+        let err = reader.read().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AeError>(),
+            Some(&AeError::Io {
+                kind: std::io::ErrorKind::Other
+            })
+        );
+        assert_eq!(
+            err.downcast_ref::<std::io::Error>().unwrap().to_string(),
+            "disk on fire"
+        );
+
+        let mut cursor = std::io::Cursor::new(&[0xFF]);
+        let mut reader = BitReader::new(&mut cursor);
+        assert_eq!(reader.read_bits(8).unwrap(), 0xFF);
+        assert_eq!(reader.read().unwrap(), ReadResult::EOF);
+    }
 }
@@ -0,0 +1,1178 @@
+//! One-shot helpers over in-memory buffers, and their streaming counterparts over readers and
+//! writers.
+//!
+//! The output starts with a mode byte. [`MODE_CODED`] is followed by an arithmetic coded stream, EOF
+//! symbol included, [`MODE_CONTEXT`] by the same but coded with a [`ContextModel`], and [`MODE_STORED`]
+//! by the input as is, which [`compress`] falls back to when coding wouldn't make the data any smaller.
+//! [`MODE_STATIC`], from [`compress_static`], is followed by a quantized histogram of the input and a
+//! stream coded with a static model built from it, and [`MODE_BINARY`] by the length of the input and
+//! its bits coded one at a time. Either way the output is at most one byte longer than the input.
+//!
+//! Every mode but the stored one stands for a [`ModelKind`], so [`decompress`] can decode a stream
+//! whichever model [`compress_with`] coded it with.
+
+use crate::bitio::BitReader;
+use crate::error::AeError;
+use crate::io::{self, Read, Write};
+use crate::model::{BitModel, EOF_SYMBOL, MAX_SYMBOLS};
+use crate::{BinaryDecoder, BinaryEncoder, BitWriter, ContextModel, Decoder, Encoder, SymbolTable};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::Result;
+use core::fmt;
+
+pub(crate) const MODE_CODED: u8 = 0;
+pub(crate) const MODE_STORED: u8 = 1;
+pub(crate) const MODE_CONTEXT: u8 = 2;
+pub(crate) const MODE_STATIC: u8 = 3;
+pub(crate) const MODE_BINARY: u8 = 4;
+
+/// The largest count in the histogram a [`MODE_STATIC`] stream stores, so each fits in a byte.
+const STATIC_COUNT_LIMIT: u64 = u8::MAX as u64;
+
+/// How much of the input [`compress`] looks at to pick the model for all of it.
+const SCAN_PREFIX: usize = 16 * 1024;
+
+/// How much of the input [`compress_stream`] reads, and [`decompress_stream`] decodes, at a time.
+const STREAM_CHUNK: usize = 64 * 1024;
+
+/// The models a stream can be coded with, see [`compress_with`]. Which one it was is recorded in the
+/// mode byte, so [`decompress`] doesn't need to be told.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModelKind {
+    /// The adaptive order-0 [`SymbolTable`].
+    Adaptive,
+    /// A static model of the input's own byte counts, stored ahead of the stream, see
+    /// [`compress_static`].
+    Static,
+    /// The adaptive order-1 [`ContextModel`], which predicts each byte from the one before it.
+    Order1,
+    /// A [`BitModel`] for every bit of a byte, given the bits above it, coded with a
+    /// [`BinaryEncoder`].
+    Binary,
+}
+
+impl ModelKind {
+    /// Every kind, e.g. to try them all on some input.
+    pub const ALL: [ModelKind; 4] = [
+        ModelKind::Adaptive,
+        ModelKind::Static,
+        ModelKind::Order1,
+        ModelKind::Binary,
+    ];
+
+    /// The mode byte a stream coded with this model starts with.
+    pub(crate) fn mode(self) -> u8 {
+        match self {
+            ModelKind::Adaptive => MODE_CODED,
+            ModelKind::Static => MODE_STATIC,
+            ModelKind::Order1 => MODE_CONTEXT,
+            ModelKind::Binary => MODE_BINARY,
+        }
+    }
+
+    /// The model a stream starting with `mode` is coded with, `None` if it's stored or unknown.
+    pub(crate) fn from_mode(mode: u8) -> Option<ModelKind> {
+        ModelKind::ALL.into_iter().find(|kind| kind.mode() == mode)
+    }
+}
+
+/// Compresses `input` into a complete stream, with whichever model does better on the start of it,
+/// storing it raw if coding doesn't shrink it.
+pub fn compress(input: &[u8]) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() / 2 + 1);
+    compress_into(input, &mut output)?;
+
+    Ok(output)
+}
+
+/// Like [`compress`], but writes to `out`, replacing whatever it held, so that repeated calls can
+/// reuse one buffer rather than allocate a new one each time.
+pub fn compress_into(input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    out.clear();
+    let kind = choose_model(input);
+    out.push(kind.mode());
+    code(kind, input, &mut *out)?;
+    store_if_larger(input, out);
+
+    Ok(())
+}
+
+/// Compresses `input` with the model `kind`, storing it raw if coding doesn't shrink it. Decompress
+/// with [`decompress`], which reads which model it was from the stream.
+pub fn compress_with(input: &[u8], kind: ModelKind) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() / 2 + 1);
+    output.push(kind.mode());
+    code(kind, input, &mut output)?;
+    store_if_larger(input, &mut output);
+
+    Ok(output)
+}
+
+/// Replaces a coded `output` that came out longer than `input` with `input` stored as is.
+fn store_if_larger(input: &[u8], output: &mut Vec<u8>) {
+    if output.len() > input.len() {
+        output.clear();
+        output.push(MODE_STORED);
+        output.extend_from_slice(input);
+    }
+}
+
+/// Decompresses a stream produced by [`compress`] or [`compress_with`], whichever model it used.
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    decompress_into(input, &mut output)?;
+
+    Ok(output)
+}
+
+/// Like [`decompress`], for data known to be text. Output that isn't valid UTF-8 is an
+/// [`AeError::InvalidUtf8`] rather than a lossy conversion.
+pub fn decompress_to_string(input: &[u8]) -> Result<String> {
+    String::from_utf8(decompress(input)?).map_err(|e| {
+        AeError::InvalidUtf8 {
+            valid_up_to: e.utf8_error().valid_up_to(),
+        }
+        .into()
+    })
+}
+
+/// Like [`decompress`], but fails with [`AeError::OutputTooLarge`] instead of returning more than
+/// `max_output_bytes`, for input that isn't trusted: a few bytes of it can decode to an enormous
+/// output. Decoding stops as soon as the limit is passed, so no more than that is ever allocated.
+pub fn decompress_with_limit(input: &[u8], max_output_bytes: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    decompress_limited(input, &mut output, max_output_bytes)?;
+
+    Ok(output)
+}
+
+/// Like [`decompress`], but writes to `out`, replacing whatever it held. If decompression fails `out`
+/// is left holding whatever was decoded before the error.
+pub fn decompress_into(input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    decompress_limited(input, out, usize::MAX)
+}
+
+fn decompress_limited(input: &[u8], out: &mut Vec<u8>, limit: usize) -> Result<()> {
+    let Some((&mode, mut payload)) = input.split_first() else {
+        anyhow::bail!("compressed data is empty, it needs at least a mode byte");
+    };
+
+    let too_large = || AeError::OutputTooLarge {
+        limit: limit as u64,
+    };
+    out.clear();
+    if mode == MODE_STORED {
+        anyhow::ensure!(payload.len() <= limit, too_large());
+        out.extend_from_slice(payload);
+
+        return Ok(());
+    }
+
+    let mut decoder = ModelDecoder::new(mode, &mut payload)?;
+    while let Some(b) = decoder.decode_byte()? {
+        anyhow::ensure!(out.len() < limit, too_large());
+        out.push(b);
+    }
+
+    Ok(())
+}
+
+/// Decodes the bytes of a stream coded with any [`ModelKind`], after its mode byte.
+enum ModelDecoder<'a, R: Read> {
+    /// [`ModelKind::Adaptive`], or [`ModelKind::Static`] with the model read from the header.
+    Table(Decoder<'a, R>),
+    Order1(Decoder<'a, R>, ContextModel),
+    Binary {
+        decoder: BinaryDecoder<'a, R>,
+        models: Box<[BitModel; 256]>,
+        /// Bytes left to decode, from the header.
+        remaining: u64,
+    },
+}
+
+impl<'a, R: Read + fmt::Debug> ModelDecoder<'a, R> {
+    /// Reads the header of a stream with `mode` from `src`, if it has one, and starts decoding it.
+    fn new(mode: u8, src: &'a mut R) -> Result<ModelDecoder<'a, R>> {
+        let Some(kind) = ModelKind::from_mode(mode) else {
+            anyhow::bail!("unknown compression mode {mode}");
+        };
+
+        Ok(match kind {
+            ModelKind::Adaptive => ModelDecoder::Table(Decoder::new(src)?),
+            ModelKind::Static => {
+                let model = static_model(&read_static_header(src)?)?;
+                ModelDecoder::Table(Decoder::with_model(src, model)?)
+            }
+            ModelKind::Order1 => ModelDecoder::Order1(Decoder::new(src)?, ContextModel::new()),
+            ModelKind::Binary => {
+                let remaining = BitReader::new(&mut OneByte(&mut *src)).read_gamma()?;
+                ModelDecoder::Binary {
+                    decoder: BinaryDecoder::new(src)?,
+                    models: Box::new([BitModel::new(); 256]),
+                    remaining,
+                }
+            }
+        })
+    }
+
+    /// The next byte, `None` at the end of the stream.
+    fn decode_byte(&mut self) -> Result<Option<u8>> {
+        match self {
+            ModelDecoder::Table(decoder) => decoder.decode_byte(),
+            ModelDecoder::Order1(decoder, model) => {
+                let symbol = decoder.decode_context(model)?;
+                Ok((symbol != EOF_SYMBOL).then_some(symbol as u8))
+            }
+            ModelDecoder::Binary {
+                decoder,
+                models,
+                remaining,
+            } => {
+                if *remaining == 0 {
+                    return Ok(None);
+                }
+                *remaining -= 1;
+
+                // Walks down a binary tree from the top bit, the node so far picking the model.
+                let mut node = 1;
+                while node < 256 {
+                    let bit = decoder.decode_bit_with(&mut models[node])?;
+                    node = node << 1 | bit as usize;
+                }
+
+                Ok(Some((node - 256) as u8))
+            }
+        }
+    }
+}
+
+/// Picks the model for `input` from up to [`SCAN_PREFIX`] bytes of it. Rather than coding them, this
+/// only counts them the way the order-0 and the order-1 model would, adding up what each byte would
+/// cost with the counts so far, escapes included. The order-0 model is cheaper to decode, so the
+/// context model has to come out strictly smaller.
+fn choose_model(input: &[u8]) -> ModelKind {
+    let prefix = &input[..input.len().min(SCAN_PREFIX)];
+
+    // Order-0: every symbol, EOF included, starts with a count of one. The order-1 model's fallback
+    // counts the same way, so it shares these.
+    let mut counts = [1u32; 256];
+    let mut order0_cost = 0;
+
+    // Order-1: the contexts start empty, and a byte a context hasn't seen yet costs its escape plus
+    // the byte in the fallback. The fallback's exclusions are left out, they barely matter here.
+    let mut contexts = vec![0u16; 256 * 256];
+    let mut context_totals = [0u32; 256];
+    let mut distinct = [0u32; 256];
+    let mut order1_cost = 0;
+
+    let mut previous = 0;
+    for (total, &b) in (MAX_SYMBOLS as u32..).zip(prefix) {
+        let b = b as usize;
+        let order0 = cost(counts[b], total);
+        order0_cost += order0;
+        counts[b] += 1;
+
+        let count = &mut contexts[previous << 8 | b];
+        let escape = distinct[previous].max(1);
+        let context_total = context_totals[previous] + escape;
+        if *count > 0 {
+            order1_cost += cost(u32::from(*count), context_total);
+        } else {
+            order1_cost += cost(escape, context_total) + order0;
+            distinct[previous] += 1;
+        }
+        *count += 1;
+        context_totals[previous] += 1;
+        previous = b;
+    }
+
+    if order1_cost < order0_cost {
+        ModelKind::Order1
+    } else {
+        ModelKind::Adaptive
+    }
+}
+
+/// What coding a symbol with `count` out of `total` costs, in 1/256ths of a bit.
+fn cost(count: u32, total: u32) -> u64 {
+    log2_fixed(total) - log2_fixed(count)
+}
+
+/// `log2(x)` in 1/256ths of a bit, for `x` of at least one. Between two powers of two it follows a
+/// straight line, which is never off by more than a tenth of a bit: plenty for comparing models, and
+/// it needs no floating point.
+fn log2_fixed(x: u32) -> u64 {
+    let whole = x.ilog2();
+    let fraction = (u64::from(x) << 8 >> whole) - 256;
+
+    u64::from(whole) << 8 | fraction
+}
+
+/// Codes `input` into `output` with the model `kind`, header included, as what follows the mode byte.
+fn code(kind: ModelKind, input: &[u8], output: &mut Vec<u8>) -> Result<()> {
+    match kind {
+        ModelKind::Adaptive => {
+            let mut encoder = Encoder::new(output);
+            encoder.encode_slice(input)?;
+            encoder.encode_end()
+        }
+        ModelKind::Static => {
+            let mut counts = [0u64; 256];
+            for &b in input {
+                counts[b as usize] += 1;
+            }
+            let quantized = quantize_counts(&counts);
+
+            write_static_header(&quantized, output)?;
+            let mut encoder = Encoder::with_model(output, static_model(&quantized)?);
+            encoder.encode_slice(input)?;
+            encoder.encode_end()
+        }
+        ModelKind::Order1 => {
+            let mut encoder = Encoder::new(output);
+            let mut model = ContextModel::new();
+            for &b in input {
+                encoder.encode_context(&mut model, b as usize)?;
+            }
+            encoder.encode_context(&mut model, EOF_SYMBOL)?;
+            encoder.encode_end_without_eof()
+        }
+        ModelKind::Binary => {
+            {
+                let mut header = BitWriter::new(output);
+                header.write_gamma(input.len() as u64)?;
+                header.flush()?;
+            }
+
+            let mut encoder = BinaryEncoder::new(output);
+            let mut models = [BitModel::new(); 256];
+            for &b in input {
+                let mut node = 1;
+                for i in (0..8).rev() {
+                    let bit = b >> i & 1 == 1;
+                    encoder.encode_bit_with(&mut models[node], bit)?;
+                    node = node << 1 | bit as usize;
+                }
+            }
+            encoder.encode_end()
+        }
+    }
+}
+
+/// Compresses `input` in two passes: one to count its bytes, one to code it with a static model of
+/// those counts. The counts are scaled down to fit a byte each and stored ahead of the stream, which
+/// costs a few bits for every distinct byte. Short inputs with a skewed distribution come out
+/// smaller than with [`compress`], which spends a lot of a short input learning the model. Decompress
+/// with [`decompress`].
+pub fn compress_static(input: &[u8]) -> Result<Vec<u8>> {
+    compress_with(input, ModelKind::Static)
+}
+
+/// Scales `counts` down so the largest is at most [`STATIC_COUNT_LIMIT`], keeping every byte that
+/// occurs at a count of at least one so it can still be coded.
+fn quantize_counts(counts: &[u64; 256]) -> [u8; 256] {
+    let max = counts
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or(0)
+        .max(STATIC_COUNT_LIMIT);
+
+    let mut quantized = [0; 256];
+    for (q, &count) in quantized.iter_mut().zip(counts) {
+        if count > 0 {
+            let scaled =
+                (count as u128 * STATIC_COUNT_LIMIT as u128 + max as u128 / 2) / max as u128;
+            *q = scaled.max(1) as u8;
+        }
+    }
+
+    quantized
+}
+
+/// The static model a [`MODE_STATIC`] stream is coded with.
+fn static_model(quantized: &[u8; 256]) -> Result<SymbolTable> {
+    SymbolTable::from_counts(&quantized.map(usize::from), false)
+}
+
+/// Writes the header of a [`MODE_STATIC`] stream in Elias gamma codes: how many distinct bytes there
+/// are, then for each of them its distance from the one before and its count less one, padded to a
+/// byte. A few distinct bytes take a few bytes of header, all 256 of them a few hundred.
+fn write_static_header(quantized: &[u8; 256], output: &mut Vec<u8>) -> Result<()> {
+    let mut writer = BitWriter::new(output);
+    writer.write_gamma(quantized.iter().filter(|&&count| count > 0).count() as u64)?;
+
+    let mut next = 0;
+    for (symbol, &count) in quantized.iter().enumerate() {
+        if count > 0 {
+            writer.write_gamma((symbol - next) as u64)?;
+            writer.write_gamma(count as u64 - 1)?;
+            next = symbol + 1;
+        }
+    }
+
+    writer.flush()
+}
+
+/// Reads what [`write_static_header`] wrote, leaving `src` at the start of the coded stream.
+fn read_static_header<R: Read + ?Sized>(src: &mut R) -> Result<[u8; 256]> {
+    // A byte at a time, so the bit reader doesn't take any of the stream along with the header.
+    let mut src = OneByte(src);
+    let mut reader = BitReader::new(&mut src);
+
+    let mut quantized = [0; 256];
+    let distinct = reader.read_gamma()?;
+    anyhow::ensure!(
+        distinct <= 256,
+        "a static model can't have {distinct} bytes"
+    );
+
+    let mut next = 0;
+    for _ in 0..distinct {
+        let symbol = reader
+            .read_gamma()?
+            .checked_add(next)
+            .filter(|&symbol| symbol < 256)
+            .ok_or_else(|| anyhow::anyhow!("a static model has a byte past 255"))?;
+        let count = reader.read_gamma()?;
+        anyhow::ensure!(
+            count < STATIC_COUNT_LIMIT,
+            "a static model has a count past {STATIC_COUNT_LIMIT}"
+        );
+
+        quantized[symbol as usize] = count as u8 + 1;
+        next = symbol + 1;
+    }
+
+    Ok(quantized)
+}
+
+/// Like [`compress`], but gives up and returns `None` as soon as the output can no longer fit in
+/// `max_bytes`, instead of compressing all of `input` first.
+pub fn compress_within(input: &[u8], max_bytes: usize) -> Result<Option<Vec<u8>>> {
+    // Stored mode always fits when the cap leaves room for it, so there's nothing to give up on.
+    if input.len() < max_bytes {
+        return compress(input).map(Some);
+    }
+
+    let max_bits = max_bytes.saturating_sub(1) as u64 * 8;
+    let kind = choose_model(input);
+    let mut output = Vec::new();
+    output.push(kind.mode());
+
+    {
+        let mut encoder = Encoder::new(&mut output);
+        let mut model = ContextModel::new();
+        for &b in input {
+            if kind == ModelKind::Order1 {
+                encoder.encode_context(&mut model, b as usize)?;
+            } else {
+                encoder.encode_next(b as usize)?;
+            }
+            if encoder.bits_written() > max_bits {
+                encoder.abandon();
+                return Ok(None);
+            }
+        }
+        if kind == ModelKind::Order1 {
+            encoder.encode_context(&mut model, EOF_SYMBOL)?;
+            encoder.encode_end_without_eof()?;
+        } else {
+            encoder.encode_end()?;
+        }
+    }
+
+    // The flush at the end can still push it over.
+    if output.len() > max_bytes {
+        return Ok(None);
+    }
+
+    Ok(Some(output))
+}
+
+/// Compresses everything `src` yields into `dst`, a chunk at a time, so the input never has to fit in
+/// memory. The output is what [`compress`] would produce, except that it's always coded: whether
+/// storing would have been smaller is only known once all of it has been read.
+pub fn compress_stream<R: Read, W: Write>(src: &mut R, dst: &mut W) -> Result<()> {
+    io::write_all(dst, &[MODE_CODED])?;
+
+    let mut dst = Opaque(dst);
+    let mut encoder = Encoder::new(&mut dst);
+    let mut chunk = vec![0; STREAM_CHUNK];
+    loop {
+        let n = src.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        encoder.encode_chunk(&chunk[..n])?;
+    }
+
+    // Also flushes `dst`.
+    encoder.encode_end()
+}
+
+/// Decompresses output of [`compress`], [`compress_with`] or [`compress_stream`] from `src` into
+/// `dst`, a chunk at a time. The decoder reads a few bytes past the end of the stream, so `src`
+/// shouldn't hold anything after it that's still needed.
+pub fn decompress_stream<R: Read, W: Write>(src: &mut R, dst: &mut W) -> Result<()> {
+    let mut mode = [0];
+    anyhow::ensure!(
+        io::read_exact(src, &mut mode)?,
+        "compressed data is empty, it needs at least a mode byte"
+    );
+
+    let mut chunk = vec![0; STREAM_CHUNK];
+    if mode[0] == MODE_STORED {
+        loop {
+            let n = src.read(&mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            io::write_all(dst, &chunk[..n])?;
+        }
+    } else {
+        let mut src = Opaque(src);
+        let mut decoder = ModelDecoder::new(mode[0], &mut src)?;
+        decode_chunks(dst, &mut chunk, || decoder.decode_byte())?;
+    }
+
+    dst.flush()
+}
+
+/// Fills `chunk` from `next` and writes it to `dst`, over and over until `next` runs out.
+fn decode_chunks<W: Write>(
+    dst: &mut W,
+    chunk: &mut [u8],
+    mut next: impl FnMut() -> Result<Option<u8>>,
+) -> Result<()> {
+    loop {
+        let mut n = 0;
+        while n < chunk.len() {
+            match next()? {
+                Some(b) => chunk[n] = b,
+                None => break,
+            }
+            n += 1;
+        }
+        io::write_all(dst, &chunk[..n])?;
+
+        if n < chunk.len() {
+            return Ok(());
+        }
+    }
+}
+
+/// Lends a reader or writer to a coder, which wants to be able to `Debug` print it, without
+/// requiring the caller's type to implement `Debug`.
+struct Opaque<'a, T: ?Sized>(&'a mut T);
+
+impl<T: ?Sized> fmt::Debug for Opaque<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Opaque").finish_non_exhaustive()
+    }
+}
+
+impl<T: Read + ?Sized> Read for Opaque<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: Write + ?Sized> Write for Opaque<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Reads at most one byte per call from the reader it wraps.
+struct OneByte<'a, T: ?Sized>(&'a mut T);
+
+impl<T: Read + ?Sized> Read for OneByte<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = buf.len().min(1);
+        self.0.read(&mut buf[..len])
+    }
+}
+
+/// Compresses `input`, decompresses the result and checks it matches, returning an error that points
+/// at the first differing byte if it doesn't. Meant for test suites and fuzzing harnesses.
+pub fn verify_roundtrip(input: &[u8]) -> Result<()> {
+    check_roundtrip(input, &compress(input)?)
+}
+
+fn check_roundtrip(input: &[u8], compressed: &[u8]) -> Result<()> {
+    let output = decompress(compressed)
+        .map_err(|e| e.context("round trip failed to decompress its own output"))?;
+
+    if let Some(i) = input.iter().zip(&output).position(|(a, b)| a != b) {
+        anyhow::bail!(
+            "round trip differs at byte {i}: expected {:#04x}, got {:#04x}",
+            input[i],
+            output[i]
+        );
+    }
+    anyhow::ensure!(
+        input.len() == output.len(),
+        "round trip returned {} bytes instead of {}",
+        output.len(),
+        input.len()
+    );
+
+    Ok(())
+}
+
+/// A stream produced by [`compress`]. Converting [`Plain`] bytes into one compresses them, and
+/// converting it back decompresses, which fails if the stream is corrupt:
+///
+/// ```
+/// use ae_rs::Compressed;
+///
+/// let compressed: Compressed = b"hello hello hello".as_slice().into();
+/// let plain: Vec<u8> = compressed.try_into()?;
+/// assert_eq!(plain, b"hello hello hello");
+/// # anyhow::Ok(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compressed(pub Vec<u8>);
+
+/// Bytes that aren't compressed, the other side of the conversions of [`Compressed`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plain(pub Vec<u8>);
+
+impl From<&[u8]> for Compressed {
+    fn from(input: &[u8]) -> Self {
+        // The only errors compress can return come from the writer, and a Vec never fails.
+        Compressed(compress(input).expect("compressing into a Vec can't fail"))
+    }
+}
+
+impl From<Plain> for Compressed {
+    fn from(plain: Plain) -> Self {
+        plain.0.as_slice().into()
+    }
+}
+
+impl TryFrom<&[u8]> for Plain {
+    type Error = anyhow::Error;
+
+    /// Decompresses `input`, a stream produced by [`compress`].
+    fn try_from(input: &[u8]) -> Result<Self> {
+        Ok(Plain(decompress(input)?))
+    }
+}
+
+impl TryFrom<Compressed> for Plain {
+    type Error = anyhow::Error;
+
+    fn try_from(compressed: Compressed) -> Result<Self> {
+        compressed.0.as_slice().try_into()
+    }
+}
+
+impl TryFrom<Compressed> for Vec<u8> {
+    type Error = anyhow::Error;
+
+    fn try_from(compressed: Compressed) -> Result<Self> {
+        Ok(Plain::try_from(compressed)?.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        check_roundtrip, choose_model, code, compress, compress_into, compress_static,
+        compress_stream, compress_with, compress_within, decompress, decompress_into,
+        decompress_stream, decompress_to_string, decompress_with_limit, quantize_counts,
+        read_static_header, static_model, verify_roundtrip, Compressed, ModelKind, Plain,
+        MODE_CODED, MODE_CONTEXT, MODE_STATIC, MODE_STORED,
+    };
+    use crate::xorshift::Xorshift;
+    use crate::{AeError, Encoder};
+    use quickcheck_macros::quickcheck;
+
+    /// The coded stream alone, without the mode byte or the stored fallback.
+    fn encode(input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(input).unwrap();
+            encoder.encode_end().unwrap();
+        }
+
+        output
+    }
+
+    fn xorshift_bytes(length: usize) -> Vec<u8> {
+        Xorshift::with_seed(0x9E3779B97F4A7C15)
+            .take(length)
+            .map(|r| (r >> 32) as u8)
+            .collect()
+    }
+
+    #[quickcheck]
+    fn compress_round_trips(input: Vec<u8>) {
+        assert_eq!(decompress(&compress(&input).unwrap()).unwrap(), input);
+    }
+
+    /// The most the coder may expand `length` bytes of input. Two bytes go to the EOF symbol and the
+    /// flush. On top of that the adaptive model has to learn the input's statistics, which on
+    /// incompressible data costs about 16 bytes per doubling of the length (half a log2 per symbol in
+    /// the alphabet), so the overhead isn't quite constant.
+    fn max_expansion(length: usize) -> usize {
+        4 + 16 * (usize::BITS - length.leading_zeros()) as usize
+    }
+
+    #[quickcheck]
+    fn coded_size_is_bounded(input: Vec<u8>) {
+        let output = encode(&input);
+
+        assert!(output.len() <= input.len() + max_expansion(input.len()));
+    }
+
+    #[quickcheck]
+    fn compress_adds_at_most_one_byte(input: Vec<u8>) {
+        assert!(compress(&input).unwrap().len() <= input.len() + 1);
+    }
+
+    #[test]
+    fn incompressible_input_expands_within_the_bound() {
+        for length in [0, 1, 255, 256, 4096, 100_000] {
+            // Every byte value in turn is the worst case for the adaptive model, xorshift output a
+            // more typical one.
+            let random = xorshift_bytes(length);
+            let sweep = (0..length).map(|i| i as u8).collect::<Vec<_>>();
+
+            for input in [random, sweep] {
+                let output = encode(&input);
+                assert!(
+                    output.len() <= length + max_expansion(length),
+                    "{length} bytes grew to {}",
+                    output.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn random_data_is_stored() {
+        let input = xorshift_bytes(10_000);
+        let output = compress(&input).unwrap();
+
+        assert_eq!(output[0], MODE_STORED);
+        assert_eq!(output.len(), input.len() + 1);
+        assert_eq!(decompress(&output).unwrap(), input);
+
+        let text = b"compressible text is still coded ".repeat(100);
+        let output = compress(&text).unwrap();
+        assert_ne!(output[0], MODE_STORED);
+        assert_eq!(decompress(&output).unwrap(), text);
+    }
+
+    #[test]
+    fn the_header_records_the_model_that_fits_the_data() {
+        // Every byte follows from the one before it, which only the context model picks up on.
+        let alternating = b"ab".repeat(5000);
+        let output = compress(&alternating).unwrap();
+        assert_eq!(output[0], MODE_CONTEXT);
+        assert_eq!(decompress(&output).unwrap(), alternating);
+
+        // Independent bytes give the contexts nothing to learn, but each of them would have to learn
+        // all 64 values on its own.
+        let independent = Xorshift::new()
+            .take(20_000)
+            .map(|r| (r >> 58) as u8)
+            .collect::<Vec<_>>();
+        let output = compress(&independent).unwrap();
+        assert_eq!(output[0], MODE_CODED);
+        assert_eq!(decompress(&output).unwrap(), independent);
+
+        // Both decode through the streaming path too, as does whatever the choice was past the
+        // scanned prefix.
+        let mixed = [&independent[..], &alternating[..]].concat().repeat(2);
+        for input in [alternating, independent, mixed] {
+            let mut output = Vec::new();
+            decompress_stream(&mut &compress(&input).unwrap()[..], &mut output).unwrap();
+            assert_eq!(output, input);
+        }
+    }
+
+    #[test]
+    fn the_estimate_picks_the_model_that_codes_smaller() {
+        let mut inputs = vec![
+            b"the quick brown fox jumps over the lazy dog, ".repeat(40),
+            b"abcabcabcabc".to_vec(),
+            b"one short line".to_vec(),
+            Xorshift::new()
+                .take(3000)
+                .map(|r| (r >> 58) as u8)
+                .collect(),
+            Xorshift::new().take(3000).map(|r| r as u8).collect(),
+        ];
+        // Runs of a few different lengths, which both models predict, one better than the other.
+        inputs.push(
+            Xorshift::with_seed(7)
+                .take(500)
+                .flat_map(|r| std::iter::repeat_n(r as u8 % 8, (r >> 8) as usize % 6 + 1))
+                .collect(),
+        );
+
+        for input in inputs {
+            let mut sizes = [ModelKind::Adaptive, ModelKind::Order1].map(|kind| {
+                let mut output = Vec::new();
+                code(kind, &input, &mut output).unwrap();
+                output.len()
+            });
+            let picked = match choose_model(&input) {
+                ModelKind::Order1 => sizes[1],
+                _ => sizes[0],
+            };
+            sizes.sort();
+            // Close calls can go either way, within a byte or two and a few percent.
+            assert!(
+                picked <= sizes[0] + 2 + sizes[0] / 25,
+                "{sizes:?}, picked {picked}"
+            );
+        }
+    }
+
+    #[test]
+    fn reused_buffers_match_the_allocating_path() {
+        let inputs: [&[u8]; 5] = [
+            &b"the quick brown fox jumps over the lazy dog, ".repeat(40),
+            b"",
+            &[7; 3000],
+            b"xy",
+            &(0..=255).collect::<Vec<u8>>(),
+        ];
+
+        let mut compressed = Vec::new();
+        let mut decompressed = Vec::new();
+        for _ in 0..3 {
+            for input in inputs {
+                compress_into(input, &mut compressed).unwrap();
+                assert_eq!(compressed, compress(input).unwrap());
+
+                decompress_into(&compressed, &mut decompressed).unwrap();
+                assert_eq!(decompressed, decompress(&compressed).unwrap());
+                assert_eq!(decompressed, input);
+            }
+        }
+    }
+
+    #[test]
+    fn output_past_the_limit_is_an_error() {
+        // 64KB of zeroes codes to a few hundred bytes.
+        let bomb = compress(&[0; 1 << 16]).unwrap();
+        assert!(bomb.len() < 1000);
+
+        let err = decompress_with_limit(&bomb, 4096).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AeError>(),
+            Some(&AeError::OutputTooLarge { limit: 4096 })
+        );
+        assert_eq!(
+            decompress_with_limit(&bomb, 1 << 16).unwrap().len(),
+            1 << 16
+        );
+
+        let text = b"the limit holds for every mode ".repeat(20);
+        let stored = [&[MODE_STORED][..], &text].concat();
+        for compressed in [compress(&text).unwrap(), stored] {
+            assert!(decompress_with_limit(&compressed, text.len() - 1).is_err());
+            assert_eq!(
+                decompress_with_limit(&compressed, text.len()).unwrap(),
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn decompress_rejects_bad_framing() {
+        assert!(decompress(&[]).is_err());
+        assert!(decompress(&[7, 1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn compress_within_aborts_early_when_the_cap_is_too_small() {
+        // Incompressible input, so the output can't be much smaller than the input.
+        let input = (0..4096u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        let achievable = compress(&input).unwrap().len();
+
+        assert_eq!(compress_within(&input, achievable / 2).unwrap(), None);
+        assert_eq!(compress_within(&input, achievable - 1).unwrap(), None);
+    }
+
+    #[test]
+    fn compress_within_succeeds_with_a_generous_cap() {
+        let input = b"a generous cap leaves plenty of room".repeat(20);
+        let output = compress_within(&input, input.len()).unwrap().unwrap();
+
+        assert_eq!(output, compress(&input).unwrap());
+        assert_eq!(decompress(&output).unwrap(), input);
+        assert_eq!(compress_within(&input, output.len()).unwrap(), Some(output));
+    }
+
+    #[test]
+    fn verify_roundtrip_reports_the_first_difference() {
+        let input = b"verify the round trip of this text, all of this text".repeat(4);
+        verify_roundtrip(&input).unwrap();
+        verify_roundtrip(&[]).unwrap();
+
+        // Flipping a bit near the end of the coded stream leaves the start intact.
+        let mut compressed = compress(&input).unwrap();
+        assert_ne!(compressed[0], MODE_STORED);
+        let last = compressed.len() - 4;
+        compressed[last] ^= 0x10;
+        let err = check_roundtrip(&input, &compressed).unwrap_err();
+        assert!(err.to_string().contains("round trip"), "{err}");
+
+        let mut stored = vec![MODE_STORED];
+        stored.extend_from_slice(&input);
+        stored[11] ^= 1;
+        let err = check_roundtrip(&input, &stored).unwrap_err();
+        assert!(err.to_string().contains("differs at byte 10"), "{err}");
+
+        stored.pop();
+        stored[11] ^= 1;
+        let err = check_roundtrip(&input, &stored).unwrap_err();
+        assert!(err.to_string().contains("instead of"), "{err}");
+    }
+
+    #[test]
+    fn files_stream_through_both_directions() {
+        let dir = std::env::temp_dir();
+        let name = |what: &str| dir.join(format!("ae-stream-{}-{what}", std::process::id()));
+        let (original, compressed, restored) = (name("in"), name("ae"), name("out"));
+
+        // A few megabytes of skewed, only partly repetitive bytes, so coding actually pays off and the
+        // decoder runs through many chunks.
+        let input = xorshift_bytes(2 << 20)
+            .iter()
+            .map(|&b| if b < 200 { b % 16 } else { b })
+            .collect::<Vec<_>>();
+        std::fs::write(&original, &input).unwrap();
+
+        let mut src = std::fs::File::open(&original).unwrap();
+        let mut dst = std::fs::File::create(&compressed).unwrap();
+        compress_stream(&mut src, &mut dst).unwrap();
+        drop(dst);
+
+        let mut src = std::fs::File::open(&compressed).unwrap();
+        let mut dst = std::io::BufWriter::new(std::fs::File::create(&restored).unwrap());
+        decompress_stream(&mut src, &mut dst).unwrap();
+        drop(dst);
+
+        let coded = std::fs::read(&compressed).unwrap();
+        let output = std::fs::read(&restored).unwrap();
+        for path in [original, compressed, restored] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(coded[0], MODE_CODED);
+        assert!(coded.len() < input.len() * 3 / 4);
+        assert!(output == input);
+    }
+
+    #[quickcheck]
+    fn streams_match_the_one_shot_format(input: Vec<u8>) {
+        let mut coded = Vec::new();
+        compress_stream(&mut &input[..], &mut coded).unwrap();
+        assert_eq!(decompress(&coded).unwrap(), input);
+
+        // Stored data comes out of decompress_stream too.
+        let mut output = Vec::new();
+        decompress_stream(&mut &compress(&input).unwrap()[..], &mut output).unwrap();
+        assert_eq!(output, input);
+    }
+
+    /// [`MODE_CODED`] output for [`GOLDEN_INPUT`], committed so that a change to the coder that alters
+    /// the bytes fails here even though it still round trips. Data compressed by earlier versions has
+    /// to keep decoding, so only update this on purpose, for a deliberate format change.
+    const GOLDEN_OUTPUT: [u8; 80] = [
+        0x00, 0x54, 0x14, 0x7F, 0x49, 0xED, 0xBB, 0x2D, 0x0C, 0x85, 0x1A, 0xCB, 0x6B, 0x12, 0xF4,
+        0xC4, 0x87, 0x2D, 0xA2, 0xD4, 0x52, 0xB8, 0xC0, 0xC9, 0x45, 0x09, 0xC5, 0xFD, 0xC1, 0x53,
+        0x6D, 0xD3, 0xDD, 0x8A, 0x64, 0x70, 0xB9, 0xCC, 0xAF, 0xC1, 0xCD, 0x53, 0x1A, 0xC8, 0xB9,
+        0x7F, 0xDF, 0xC0, 0xCD, 0x3C, 0x0B, 0xBC, 0xF6, 0x69, 0xFC, 0xC9, 0x81, 0x5F, 0xCD, 0x70,
+        0xD6, 0x4D, 0xC9, 0x02, 0x8E, 0x48, 0xD9, 0x19, 0x9E, 0xE9, 0x0C, 0x6D, 0x66, 0x1E, 0x5D,
+        0xE9, 0xEF, 0x6C, 0xEC, 0x22,
+    ];
+
+    /// [`compress`] output for [`GOLDEN_INPUT`], which picks [`MODE_CONTEXT`] for it.
+    const GOLDEN_CONTEXT_OUTPUT: [u8; 57] = [
+        0x02, 0x54, 0x14, 0x7F, 0x49, 0xED, 0xBB, 0x2D, 0x0C, 0x85, 0x1B, 0x4C, 0x69, 0x88, 0xFD,
+        0xC2, 0xC9, 0xF2, 0x2C, 0x89, 0x87, 0x55, 0x09, 0x00, 0xA0, 0xB2, 0xCC, 0x1E, 0xDE, 0xF2,
+        0x09, 0xF9, 0xF9, 0xA3, 0xE2, 0xE6, 0xB3, 0x04, 0xCE, 0xC9, 0x5F, 0x69, 0x83, 0x3E, 0x39,
+        0xA6, 0xBC, 0x72, 0x44, 0x0D, 0x5B, 0xBA, 0xD6, 0x46, 0xC8, 0x42, 0x90,
+    ];
+
+    const GOLDEN_INPUT: &[u8] =
+        b"The quick brown fox jumps over the lazy dog. The quick brown fox jumps over the lazy dog.";
+
+    #[test]
+    fn output_matches_the_golden_bytes() {
+        let mut coded = vec![MODE_CODED];
+        code(ModelKind::Adaptive, GOLDEN_INPUT, &mut coded).unwrap();
+        assert_eq!(coded, GOLDEN_OUTPUT);
+        assert_eq!(decompress(&GOLDEN_OUTPUT).unwrap(), GOLDEN_INPUT);
+
+        assert_eq!(compress(GOLDEN_INPUT).unwrap(), GOLDEN_CONTEXT_OUTPUT);
+        assert_eq!(decompress(&GOLDEN_CONTEXT_OUTPUT).unwrap(), GOLDEN_INPUT);
+
+        // Too short to code, so it's stored.
+        assert_eq!(compress(b"ab").unwrap(), [MODE_STORED, b'a', b'b']);
+    }
+
+    #[test]
+    fn conversions_go_through_compress_and_decompress() {
+        let input = b"abcabcabcabcabcabcabcabcabcabc".repeat(20);
+
+        let compressed: Compressed = Plain(input.clone()).into();
+        assert_eq!(compressed, Compressed(compress(&input).unwrap()));
+        assert_eq!(compressed, input.as_slice().into());
+
+        let plain: Plain = compressed.0.as_slice().try_into().unwrap();
+        assert_eq!(plain, Plain(input.clone()));
+        let plain: Plain = compressed.clone().try_into().unwrap();
+        assert_eq!(plain.0, input);
+        let decoded: Vec<u8> = compressed.try_into().unwrap();
+        assert_eq!(decoded, input);
+
+        // An unknown mode byte, and a stream cut off part way, are errors rather than output.
+        assert!(Plain::try_from([0xFF, 1, 2, 3].as_slice()).is_err());
+        let mut truncated = compress(&input).unwrap();
+        truncated.truncate(truncated.len() / 2);
+        assert!(Vec::<u8>::try_from(Compressed(truncated)).is_err());
+    }
+
+    #[quickcheck]
+    fn static_compression_round_trips(input: Vec<u8>) {
+        let compressed = compress_static(&input).unwrap();
+        assert!(compressed.len() <= input.len() + 1);
+        assert_eq!(decompress(&compressed).unwrap(), input);
+
+        let mut streamed = Vec::new();
+        decompress_stream(&mut compressed.as_slice(), &mut streamed).unwrap();
+        assert_eq!(streamed, input);
+    }
+
+    #[test]
+    fn the_static_model_is_rebuilt_exactly() {
+        // Enough of one byte that the counts have to be scaled down to fit.
+        let mut input = vec![b' '; 5000];
+        input.extend(b"a few rare letters, and a lot of spaces".repeat(3));
+        let mut counts = [0u64; 256];
+        for &b in &input {
+            counts[b as usize] += 1;
+        }
+        let quantized = quantize_counts(&counts);
+        assert_eq!(quantized[b' ' as usize], u8::MAX);
+        assert!(counts
+            .iter()
+            .zip(&quantized)
+            .all(|(&count, &q)| (count > 0) == (q > 0)));
+
+        let compressed = compress_static(&input).unwrap();
+        assert_eq!(compressed[0], MODE_STATIC);
+        let read = read_static_header(&mut &compressed[1..]).unwrap();
+        assert_eq!(read, quantized);
+        assert_eq!(
+            static_model(&read).unwrap().frequencies(),
+            static_model(&quantized).unwrap().frequencies()
+        );
+        assert_eq!(decompress(&compressed).unwrap(), input);
+
+        // A header cut off part way is an error.
+        assert!(decompress(&compressed[..3]).is_err());
+    }
+
+    #[test]
+    fn static_models_beat_adaptive_ones_on_short_skewed_input() {
+        // A few letters in no particular order, most of them the same one.
+        let input = Xorshift::new()
+            .take(1000)
+            .map(|r| b"aaaaaaaaaaaabbbc"[(r % 16) as usize])
+            .collect::<Vec<_>>();
+        let adaptive = compress(&input).unwrap();
+        let two_pass = compress_static(&input).unwrap();
+        assert!(
+            two_pass.len() < adaptive.len(),
+            "{} >= {}",
+            two_pass.len(),
+            adaptive.len()
+        );
+    }
+
+    #[test]
+    fn text_decompresses_to_a_string() {
+        let text = "plain ASCII, then some that isn't: déjà vu, 日本語, 🦀🦀🦀";
+        let compressed = compress(text.as_bytes()).unwrap();
+        assert_eq!(decompress_to_string(&compressed).unwrap(), text);
+        assert_eq!(decompress_to_string(&compress(b"").unwrap()).unwrap(), "");
+
+        // A stream that decodes fine to bytes that aren't text.
+        let mut bytes = b"almost text \xF0\x9F".to_vec();
+        bytes.extend_from_slice(b" and more");
+        let compressed = compress(&bytes).unwrap();
+        let err = decompress_to_string(&compressed).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AeError>(),
+            Some(&AeError::InvalidUtf8 { valid_up_to: 12 })
+        );
+        assert!(err.to_string().contains("UTF-8"), "{err}");
+
+        // Errors decoding the stream come through as they are.
+        assert!(decompress_to_string(&[0xFF]).is_err());
+    }
+
+    #[quickcheck]
+    fn every_model_round_trips(input: Vec<u8>) {
+        for kind in ModelKind::ALL {
+            let compressed = compress_with(&input, kind).unwrap();
+            assert!(compressed.len() <= input.len() + 1);
+            assert_eq!(decompress(&compressed).unwrap(), input, "{kind:?}");
+
+            let mut streamed = Vec::new();
+            decompress_stream(&mut compressed.as_slice(), &mut streamed).unwrap();
+            assert_eq!(streamed, input, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn decompress_reads_the_model_from_the_stream() {
+        let input = b"one entry point for every model, one entry point for every model".repeat(20);
+        let mut sizes = Vec::new();
+        for kind in ModelKind::ALL {
+            let compressed = compress_with(&input, kind).unwrap();
+            assert_eq!(ModelKind::from_mode(compressed[0]), Some(kind));
+            assert_eq!(decompress(&compressed).unwrap(), input, "{kind:?}");
+            sizes.push(compressed.len());
+
+            // A limit holds whatever the model, a binary stream's length included.
+            let err = decompress_with_limit(&compressed, 100).unwrap_err();
+            assert!(err.to_string().contains("limit"), "{kind:?}: {err}");
+        }
+        assert_eq!(
+            compress_with(&input, ModelKind::Static).unwrap(),
+            compress_static(&input).unwrap()
+        );
+
+        // Repetitive text is what the order-1 model is for.
+        let order1 = sizes[ModelKind::ALL
+            .iter()
+            .position(|&k| k == ModelKind::Order1)
+            .unwrap()];
+        assert_eq!(sizes.iter().min(), Some(&order1));
+
+        assert_eq!(ModelKind::from_mode(MODE_STORED), None);
+        assert!(decompress(&[0x7F, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use core::fmt;
+
+/// Errors with a specific meaning to callers. They're returned inside [`anyhow::Error`], so match on
+/// them with `err.downcast_ref::<AeError>()`.
+///
+/// New variants get added as callers need to tell more failures apart, and `AeError::Io` only
+/// exists with the `std` feature, which another crate in the build can turn on. So a match needs a
+/// wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AeError {
+    /// A record's checksum didn't match its payload. Records are numbered from 1.
+    ChecksumMismatch { record: usize },
+    /// The compressed stream ended before its last symbol could be decoded, so it was cut short.
+    UnexpectedEof,
+    /// The decoder's state no longer makes sense for the bits it's reading, e.g. because it was started
+    /// from a checkpoint of a different stream.
+    CorruptStream,
+    /// An earlier write to the underlying writer failed part way through the stream. What reached it
+    /// can't be continued into a valid stream, so the encoder refuses to go on.
+    Poisoned,
+    /// The stream decodes to more than the `limit` bytes the caller allowed, see
+    /// [`crate::Decoder::with_max_output_bytes`].
+    OutputTooLarge { limit: u64 },
+    /// The stream decodes fine, but not to text: its bytes stop being valid UTF-8 after the first
+    /// `valid_up_to`, see [`crate::decompress_to_string`].
+    InvalidUtf8 { valid_up_to: usize },
+    /// The underlying reader failed with an error of this `kind`, as opposed to running out of data,
+    /// which coders see as the end of the stream. The [`std::io::Error`] itself is the source of the
+    /// returned [`anyhow::Error`].
+    #[cfg(feature = "std")]
+    Io { kind: std::io::ErrorKind },
+}
+
+impl fmt::Display for AeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AeError::ChecksumMismatch { record } => {
+                write!(f, "record {record} failed its checksum")
+            }
+            AeError::UnexpectedEof => write!(f, "compressed stream is truncated"),
+            AeError::CorruptStream => write!(f, "compressed stream is corrupt"),
+            AeError::Poisoned => {
+                write!(f, "an earlier write failed, the stream can't be continued")
+            }
+            AeError::OutputTooLarge { limit } => {
+                write!(
+                    f,
+                    "decompressed output is larger than the limit of {limit} bytes"
+                )
+            }
+            AeError::InvalidUtf8 { valid_up_to } => {
+                write!(
+                    f,
+                    "decompressed output isn't valid UTF-8 past its first {valid_up_to} bytes"
+                )
+            }
+            #[cfg(feature = "std")]
+            AeError::Io { kind } => write!(f, "reading the input failed: {kind}"),
+        }
+    }
+}
+
+impl core::error::Error for AeError {}
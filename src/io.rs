@@ -0,0 +1,171 @@
+//! Minimal byte source and sink traits used by the coder.
+//!
+//! With the default `std` feature these are implemented for every [`std::io::Read`] and
+//! [`std::io::Write`], so any standard reader or writer can be handed to an
+//! [`Encoder`](crate::Encoder) or [`Decoder`](crate::Decoder). Without `std` they are implemented for
+//! `&[u8]` and `Vec<u8>`, and can be implemented for whatever byte sink the target has.
+
+#[cfg(feature = "std")]
+use crate::AeError;
+use anyhow::Result;
+
+pub trait Read {
+    /// Reads up to `buf.len()` bytes, returning how many were read. `Ok(0)` means the source is
+    /// exhausted.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+}
+
+pub trait Write {
+    /// Writes up to `buf.len()` bytes, returning how many were accepted.
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    fn flush(&mut self) -> Result<()>;
+}
+
+/// How many writes in a row [`write_all`] lets accept nothing before it gives up on the writer.
+const MAX_EMPTY_WRITES: usize = 64;
+
+/// Writes all of `buf`, retrying partial writes. A writer that accepts nothing, e.g. a full pipe, gets
+/// [`MAX_EMPTY_WRITES`] tries in a row to take something before this fails.
+pub(crate) fn write_all<W: Write + ?Sized>(writer: &mut W, mut buf: &[u8]) -> Result<()> {
+    let mut empty_writes = 0;
+    while !buf.is_empty() {
+        let n = writer.write(buf)?;
+        if n == 0 {
+            empty_writes += 1;
+            anyhow::ensure!(
+                empty_writes < MAX_EMPTY_WRITES,
+                "writer accepted no bytes {MAX_EMPTY_WRITES} times in a row"
+            );
+            continue;
+        }
+
+        empty_writes = 0;
+        buf = &buf[n..];
+    }
+
+    Ok(())
+}
+
+/// Fills `buf` completely. Returns `false` if the source was already exhausted before the first byte,
+/// and an error if it ran out part way through.
+pub(crate) fn read_exact<R: Read + ?Sized>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..])?;
+        if n == 0 {
+            anyhow::ensure!(filled == 0, "unexpected end of input");
+            return Ok(false);
+        }
+        filled += n;
+    }
+
+    Ok(true)
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + ?Sized> Read for R {
+    /// Retries reads that were interrupted, and returns any other failure as [`AeError::Io`] so it
+    /// can't be mistaken for the source running out.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            match std::io::Read::read(self, buf) {
+                Ok(n) => return Ok(n),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    let kind = e.kind();
+                    return Err(anyhow::Error::new(e).context(AeError::Io { kind }));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write + ?Sized> Write for W {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Ok(std::io::Write::write(self, buf)?)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(std::io::Write::flush(self)?)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Read for &[u8] {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = buf.len().min(self.len());
+        let (head, tail) = self.split_at(n);
+        buf[..n].copy_from_slice(head);
+        *self = tail;
+        Ok(n)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<R: Read + ?Sized> Read for &mut R {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (**self).read(buf)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Write for alloc::vec::Vec<u8> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<W: Write + ?Sized> Write for &mut W {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (**self).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::EOF_SYMBOL;
+    use crate::{Decoder, Encoder};
+    use alloc::vec::Vec;
+
+    // Only uses sources and sinks that are available without std. The tests are built with std, so
+    // this goes through the blanket `std::io` impls; `tests/no_std.rs` runs the same round trip
+    // against the impls above.
+    #[test]
+    fn round_trips_through_slice_and_vec() {
+        let input = b"no_std needs nothing more than a slice and a vec";
+        let mut output: Vec<u8> = Vec::new();
+
+        {
+            let mut encoder = Encoder::new(&mut output);
+            for &s in input {
+                encoder.encode_next(s as usize).unwrap();
+            }
+            encoder.encode_end().unwrap();
+        }
+
+        let mut source: &[u8] = &output;
+        let mut decoder = Decoder::new(&mut source).unwrap();
+        let mut decoded = Vec::new();
+        loop {
+            let s = decoder.decode_next().unwrap();
+            if s == EOF_SYMBOL {
+                break;
+            }
+            decoded.push(s as u8);
+        }
+
+        assert_eq!(decoded, input);
+    }
+}
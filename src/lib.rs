@@ -1,5 +1,58 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "alloc"))]
+compile_error!("ae-rs needs either the `std` or the `alloc` feature");
+
+extern crate alloc;
+
 mod ae;
+mod archive;
+#[cfg(feature = "base64")]
+mod base64;
+mod binary;
 mod bitio;
+#[cfg(feature = "std")]
+mod block;
+mod compress;
+mod error;
+pub mod io;
+mod model;
+mod range;
+mod records;
+#[cfg(test)]
+mod reference;
+#[cfg(test)]
+mod xorshift;
 
+pub use ae::Checkpoint;
 pub use ae::Decoder;
+pub use ae::DynEncoder;
+pub use ae::EncodedBytes;
 pub use ae::Encoder;
+pub use ae::EncoderState;
+#[cfg(feature = "metrics")]
+pub use ae::Metrics;
+pub use ae::Termination;
+pub use ae::DEFAULT_REGISTER_BITS;
+pub use archive::Archive;
+#[cfg(feature = "base64")]
+pub use base64::{compress_to_base64, decompress_from_base64};
+pub use binary::{BinaryDecoder, BinaryEncoder};
+pub use bitio::{BitReader, BitWriter, ReadResult, MAX_PEEK_BITS};
+#[cfg(feature = "std")]
+pub use block::{compress_parallel, decompress_parallel};
+pub use compress::{
+    compress, compress_into, compress_static, compress_stream, compress_with, compress_within,
+    decompress, decompress_into, decompress_stream, decompress_to_string, decompress_with_limit,
+    verify_roundtrip, Compressed, ModelKind, Plain,
+};
+pub use error::AeError;
+#[cfg(feature = "std")]
+pub use model::{order0_entropy_bits, order0_entropy_bits_of_counts};
+pub use model::{
+    AdaptiveModel, BitModel, ContextModel, Count, ExtendableModel, ModelSnapshot, RunModel,
+    SymbolTable, EOF_SYMBOL, MAX_DATA_SYMBOL, MAX_EXTENDABLE_CAPACITY, MAX_STATIC_TOTAL,
+    REPEAT_SYMBOL,
+};
+pub use range::{RangeDecoder, RangeEncoder};
+pub use records::{RecordReader, RecordWriter};
@@ -0,0 +1,202 @@
+//! Command line front end: `ae -c input output` compresses a file and `ae -d input output`
+//! decompresses one. Either path can be `-` for stdin or stdout.
+
+use ae_rs::{compress_stream, decompress_stream, order0_entropy_bits_of_counts};
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: ae -c|-d <input|-> <output|->";
+
+#[derive(Debug)]
+enum Input {
+    Stdin(std::io::Stdin),
+    File(File),
+}
+
+#[derive(Debug)]
+enum Output {
+    Stdout(std::io::Stdout),
+    File(File),
+}
+
+/// Counts the bytes that pass through to or from the inner writer or reader.
+#[derive(Debug)]
+struct Counted<W> {
+    inner: W,
+    count: u64,
+}
+
+/// Counts how often every byte value is read from the inner reader.
+#[derive(Debug)]
+struct Histogram<R> {
+    inner: R,
+    counts: [u64; 256],
+}
+
+impl Input {
+    fn open(path: &str) -> Result<Input> {
+        Ok(match path {
+            "-" => Input::Stdin(std::io::stdin()),
+            _ => Input::File(File::open(path)?),
+        })
+    }
+}
+
+impl Output {
+    fn create(path: &str) -> Result<Output> {
+        Ok(match path {
+            "-" => Output::Stdout(std::io::stdout()),
+            _ => Output::File(File::create(path)?),
+        })
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Input::Stdin(stdin) => stdin.read(buf),
+            Input::File(file) => file.read(buf),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Stdout(stdout) => stdout.write(buf),
+            Output::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::File(file) => file.flush(),
+        }
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl<R: Read> Read for Histogram<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        for &b in &buf[..n] {
+            self.counts[b as usize] += 1;
+        }
+
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Compresses `input` into `output`, returning the uncompressed and compressed sizes and the order-0
+/// entropy of the input in bits.
+fn compress(input: BufReader<Input>, output: BufWriter<Output>) -> Result<(u64, u64, Option<f64>)> {
+    let mut input = Histogram {
+        inner: input,
+        counts: [0; 256],
+    };
+    let mut output = Counted {
+        inner: output,
+        count: 0,
+    };
+
+    // Also flushes `output`.
+    compress_stream(&mut input, &mut output)?;
+
+    Ok((
+        input.counts.iter().sum(),
+        output.count,
+        Some(order0_entropy_bits_of_counts(&input.counts)),
+    ))
+}
+
+/// Decompresses `input` into `output`, returning the uncompressed and compressed sizes.
+fn decompress(input: BufReader<Input>, output: BufWriter<Output>) -> Result<(u64, u64)> {
+    let mut input = Counted {
+        inner: input,
+        count: 0,
+    };
+    let mut output = Counted {
+        inner: output,
+        count: 0,
+    };
+
+    // Also flushes `output`.
+    decompress_stream(&mut input, &mut output)?;
+
+    Ok((output.count, input.count))
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let [mode, input, output] = args else {
+        anyhow::bail!(USAGE);
+    };
+
+    // Checked before the output is created, which truncates it.
+    let compressing = match mode.as_str() {
+        "-c" => true,
+        "-d" => false,
+        _ => anyhow::bail!(USAGE),
+    };
+
+    let input = BufReader::new(Input::open(input)?);
+    let output = BufWriter::new(Output::create(output)?);
+
+    let (original, compressed, entropy) = if compressing {
+        compress(input, output)?
+    } else {
+        let (original, compressed) = decompress(input, output)?;
+        (original, compressed, None)
+    };
+
+    eprintln!(
+        "{original} bytes <-> {compressed} bytes, ratio {:.3}",
+        compressed as f64 / original.max(1) as f64
+    );
+    // What a static model of the input's own byte frequencies would need, so how close the adaptive
+    // one gets and how much is left for a better model.
+    if let Some(entropy) = entropy {
+        eprintln!(
+            "order-0 entropy {:.0} bytes, {:.3} bits per byte",
+            entropy / 8.0,
+            entropy / original.max(1) as f64
+        );
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ae: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,1290 @@
+use crate::error::AeError;
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::Result;
+use core::fmt;
+
+/// The largest symbol that carries data. Symbols `0..=MAX_DATA_SYMBOL` are the byte values.
+pub const MAX_DATA_SYMBOL: usize = 0xFF;
+
+/// The symbol that marks the end of a stream, right after the byte values. A decode loop over
+/// [`crate::Decoder::decode_next`] stops when it sees it:
+///
+/// ```
+/// use ae_rs::{Decoder, Encoder, EOF_SYMBOL};
+///
+/// let mut stream = Vec::new();
+/// {
+///     let mut encoder = Encoder::new(&mut stream);
+///     encoder.encode_slice(b"hello")?;
+///     encoder.encode_end()?;
+/// }
+///
+/// let mut reader = &stream[..];
+/// let mut decoder = Decoder::new(&mut reader)?;
+/// let mut output = Vec::new();
+/// loop {
+///     let symbol = decoder.decode_next()?;
+///     if symbol == EOF_SYMBOL {
+///         break;
+///     }
+///     output.push(symbol as u8);
+/// }
+/// assert_eq!(output, b"hello");
+/// # anyhow::Ok(())
+/// ```
+pub const EOF_SYMBOL: usize = MAX_DATA_SYMBOL + 1;
+
+/// The meta-symbol of the run-length mode, right after EOF: the byte before it repeats, a number of
+/// times coded after it. Only a [`RunModel`] has it in its alphabet.
+pub const REPEAT_SYMBOL: usize = EOF_SYMBOL + 1;
+
+/// Size of the alphabet, the byte values plus EOF.
+pub(crate) const MAX_SYMBOLS: usize = EOF_SYMBOL + 1;
+
+/// The largest total a model built with [`SymbolTable::from_counts`] may have. After renormalization
+/// the coder's range never drops below 2^30, so up to this total every non-zero count still maps to a
+/// non-empty interval.
+pub const MAX_STATIC_TOTAL: usize = 1 << 30;
+
+/// The integer type a [`SymbolTable`] keeps its cumulative counts in. A table's total can't go past
+/// [`Count::MAX_TOTAL`], and its counts are halved whenever they would.
+pub trait Count: Copy + Default + fmt::Debug + Eq {
+    /// The largest total a table with this count type holds: the type's maximum, or
+    /// [`MAX_STATIC_TOTAL`] if that's smaller.
+    const MAX_TOTAL: usize;
+
+    /// Converts a cumulative count, which is never more than [`Count::MAX_TOTAL`].
+    fn from_usize(count: usize) -> Self;
+
+    fn to_usize(self) -> usize;
+}
+
+impl Count for u16 {
+    const MAX_TOTAL: usize = u16::MAX as usize;
+
+    fn from_usize(count: usize) -> Self {
+        count as u16
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+impl Count for u32 {
+    const MAX_TOTAL: usize = MAX_STATIC_TOTAL;
+
+    fn from_usize(count: usize) -> Self {
+        count as u32
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+impl Count for usize {
+    const MAX_TOTAL: usize = MAX_STATIC_TOTAL;
+
+    fn from_usize(count: usize) -> Self {
+        count
+    }
+
+    fn to_usize(self) -> usize {
+        self
+    }
+}
+
+/// The adaptive order-0 model used by [`crate::Encoder`] and [`crate::Decoder`]: a cumulative frequency
+/// table over the 256 byte values plus the EOF symbol, starting out uniform. How it adapts is set with
+/// an [`AdaptiveModel`].
+///
+/// The counts are `usize` unless another [`Count`] type is picked, and the coder's own model always
+/// uses `usize`. A smaller type only matters when there are many tables, e.g. one per context kept
+/// for [`crate::Encoder::encode_with`]: an order-1 model of 257 tables takes about 530KB with 64 bit
+/// counts, 265KB with `u32` and 133KB with `u16`. A `u16` table halves its counts once they add up to
+/// more than 65535, so it also adapts faster.
+#[derive(Debug, Clone)]
+pub struct SymbolTable<C: Count = usize> {
+    pub(crate) symbol_count: usize,
+    table: [C; MAX_SYMBOLS + 1],
+    /// How the table adapts while coding, `None` for a static model.
+    adaptation: Option<AdaptiveModel>,
+    since_decay: usize,
+}
+
+/// Halves every count, never below one, until they add up to at most `max_total`, which has to leave
+/// room for a count of one for every symbol. Returns how many times they were halved.
+fn halve_until(frequencies: &mut [usize; MAX_SYMBOLS], max_total: usize) -> u32 {
+    let mut total = frequencies.iter().sum::<usize>();
+    let mut rescales = 0;
+    while total > max_total {
+        for frequency in frequencies.iter_mut() {
+            *frequency = (*frequency / 2).max(1);
+        }
+        total = frequencies.iter().sum();
+        rescales += 1;
+    }
+
+    rescales
+}
+
+/// How an adaptive [`SymbolTable`] learns from the symbols it codes.
+///
+/// By default every count only ever grows, so the whole history weighs the same. With a decay the
+/// counts are scaled down by `numerator / denominator` (never below one) after every `window` symbols,
+/// which lets recent symbols dominate and suits data whose statistics shift along the way. Encoder and
+/// decoder have to use the same parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveModel {
+    window: usize,
+    decay_numerator: usize,
+    decay_denominator: usize,
+    increment: usize,
+    eof_scale: usize,
+}
+
+impl Default for AdaptiveModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AdaptiveModel {
+    /// Plain cumulative counting with no decay, the model [`SymbolTable::new`] uses.
+    pub fn new() -> AdaptiveModel {
+        AdaptiveModel {
+            window: 0,
+            decay_numerator: 1,
+            decay_denominator: 1,
+            increment: 1,
+            eof_scale: 1,
+        }
+    }
+
+    /// Scales every count by `numerator / denominator` after each `window` coded symbols. The factor
+    /// has to be below one.
+    pub fn with_decay(
+        window: usize,
+        numerator: usize,
+        denominator: usize,
+    ) -> Result<AdaptiveModel> {
+        anyhow::ensure!(window > 0, "decay window must be at least 1 symbol");
+        anyhow::ensure!(
+            numerator < denominator,
+            "decay factor {numerator}/{denominator} must be below 1"
+        );
+
+        Ok(AdaptiveModel {
+            window,
+            decay_numerator: numerator,
+            decay_denominator: denominator,
+            increment: 1,
+            eof_scale: 1,
+        })
+    }
+
+    /// Puts every count back to where it started, as [`SymbolTable::reset`] does, after each `window`
+    /// coded symbols, so the model starts learning from scratch instead of dragging old statistics
+    /// along. A decay factor of zero, really: the counts can't go below their starting point.
+    pub fn with_reset(window: usize) -> Result<AdaptiveModel> {
+        Self::with_decay(window, 0, 1)
+    }
+
+    /// Adds `step` to a symbol's count every time it's coded instead of 1. A bigger step makes the
+    /// model commit to the symbols it has seen sooner, which pays off on short, skewed messages. The
+    /// counts grow `step` times faster too, so pair large steps with a decay on long inputs. The step
+    /// can be at most [`MAX_STATIC_TOTAL`].
+    pub fn with_increment(mut self, step: usize) -> Result<AdaptiveModel> {
+        anyhow::ensure!(step > 0, "increment step must be at least 1");
+        // Past this the total could top 2^32 before it's halved, which a 32 bit `usize` can't hold,
+        // and a stream has to decode the same on every target.
+        anyhow::ensure!(
+            step <= MAX_STATIC_TOTAL,
+            "increment step must be at most {MAX_STATIC_TOTAL}"
+        );
+        anyhow::ensure!(
+            step.checked_mul(self.eof_scale)
+                .is_some_and(|step| step <= MAX_STATIC_TOTAL),
+            "increment step times the EOF scale must be at most {MAX_STATIC_TOTAL}"
+        );
+        self.increment = step;
+
+        Ok(self)
+    }
+
+    /// Gives EOF `1 / scale` of the weight it otherwise has: every byte's count starts at and grows by
+    /// `scale` times as much, while EOF's stays at one and is never incremented. EOF is only coded
+    /// once, so on long inputs the bytes get back almost all of the range it would take up, at the
+    /// cost of EOF itself taking about `log2(scale)` more bits. A scale around 16 suits inputs in the
+    /// megabytes.
+    pub fn with_eof_scale(mut self, scale: usize) -> Result<AdaptiveModel> {
+        anyhow::ensure!(scale > 0, "EOF scale must be at least 1");
+        anyhow::ensure!(
+            scale
+                .checked_mul(self.increment.max(EOF_SYMBOL + 1))
+                .is_some_and(|total| total <= MAX_STATIC_TOTAL),
+            "EOF scale {scale} is too big for the model's counts"
+        );
+        self.eof_scale = scale;
+
+        Ok(self)
+    }
+
+    /// Creates a uniform table that adapts with these parameters.
+    pub fn build(self) -> SymbolTable {
+        let mut ret = SymbolTable::new();
+        ret.adaptation = Some(self);
+        ret.reset();
+
+        ret
+    }
+}
+
+/// A copy of a [`SymbolTable`]'s per-symbol frequencies, used to start several streams from the same
+/// trained model. Encoder and decoder must both be built from the same snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelSnapshot {
+    pub frequencies: [usize; MAX_SYMBOLS],
+    pub symbol_count: usize,
+}
+
+impl ModelSnapshot {
+    /// Serializes the snapshot as `symbol_count` followed by every frequency, each a little endian u64.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity((MAX_SYMBOLS + 1) * 8);
+        bytes.extend_from_slice(&(self.symbol_count as u64).to_le_bytes());
+        for &frequency in &self.frequencies {
+            bytes.extend_from_slice(&(frequency as u64).to_le_bytes());
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<ModelSnapshot> {
+        anyhow::ensure!(
+            bytes.len() == (MAX_SYMBOLS + 1) * 8,
+            "model snapshot must be {} bytes, got {}",
+            (MAX_SYMBOLS + 1) * 8,
+            bytes.len()
+        );
+
+        // On targets with a 32 bit usize a count that doesn't fit is an error, not a truncated count.
+        let mut words = bytes.chunks_exact(8).map(|chunk| {
+            let word = u64::from_le_bytes(chunk.try_into().unwrap());
+            usize::try_from(word)
+                .map_err(|_| anyhow::anyhow!("model count {word} doesn't fit usize"))
+        });
+
+        let symbol_count = words.next().unwrap()?;
+        let mut frequencies = [0; MAX_SYMBOLS];
+        for (frequency, word) in frequencies.iter_mut().zip(words) {
+            *frequency = word?;
+        }
+
+        Ok(ModelSnapshot {
+            frequencies,
+            symbol_count,
+        })
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        Self::uniform()
+    }
+
+    /// Builds an adaptive model that starts from `counts` instead of uniform, indexed by byte value
+    /// with EOF last. A prior that matches the data, e.g. one favouring ASCII letters for text, saves
+    /// most of what the model would otherwise spend learning on a short input. Every count has to be
+    /// at least one, and the total at most [`MAX_STATIC_TOTAL`].
+    pub fn with_initial_counts(counts: &[usize; 257]) -> Result<SymbolTable> {
+        anyhow::ensure!(
+            counts.iter().all(|&count| count > 0),
+            "every initial count needs to be at least 1"
+        );
+
+        let mut ret = Self {
+            symbol_count: 0,
+            table: [0; MAX_SYMBOLS + 1],
+            adaptation: Some(AdaptiveModel::new()),
+            since_decay: 0,
+        };
+
+        for (symbol, &count) in counts.iter().enumerate() {
+            ret.table[symbol + 1] = ret.table[symbol]
+                .checked_add(count)
+                .filter(|&total| total <= MAX_STATIC_TOTAL)
+                .ok_or_else(|| anyhow::anyhow!("counts sum to more than {MAX_STATIC_TOTAL}"))?;
+        }
+        ret.symbol_count = ret.table[MAX_SYMBOLS];
+
+        Ok(ret)
+    }
+
+    /// Builds a static model from per-byte counts, e.g. a histogram of the data about to be coded. The
+    /// model never adapts, and EOF always gets a count of one.
+    ///
+    /// A byte with a count of zero can't be coded at all, and encoding it fails. With
+    /// `frequency_floor` every count is raised by one first (add-one smoothing), so any byte stays
+    /// encodable at the cost of a little precision on the ones that were seen.
+    pub fn from_counts(counts: &[usize; 256], frequency_floor: bool) -> Result<SymbolTable> {
+        let floor = if frequency_floor { 1 } else { 0 };
+
+        let mut ret = Self {
+            symbol_count: 0,
+            table: [0; MAX_SYMBOLS + 1],
+            adaptation: None,
+            since_decay: 0,
+        };
+
+        for (symbol, &count) in counts.iter().enumerate() {
+            ret.table[symbol + 1] = ret.table[symbol]
+                .checked_add(count.saturating_add(floor))
+                .filter(|&total| total <= MAX_STATIC_TOTAL)
+                .ok_or_else(|| anyhow::anyhow!("counts sum to more than {MAX_STATIC_TOTAL}"))?;
+        }
+        ret.table[MAX_SYMBOLS] = ret.table[EOF_SYMBOL] + 1;
+        ret.symbol_count = ret.table[MAX_SYMBOLS];
+
+        anyhow::ensure!(
+            ret.symbol_count <= MAX_STATIC_TOTAL,
+            "counts sum to more than {MAX_STATIC_TOTAL}"
+        );
+
+        Ok(ret)
+    }
+
+    /// Rebuilds a model from a snapshot. Every symbol has to keep a non-zero frequency, otherwise it
+    /// could never be coded, and the total can be at most [`MAX_STATIC_TOTAL`].
+    pub fn import(snapshot: &ModelSnapshot) -> Result<SymbolTable> {
+        anyhow::ensure!(
+            snapshot.frequencies.iter().all(|&f| f > 0),
+            "every symbol in a model snapshot needs a non-zero frequency"
+        );
+
+        let mut ret = Self {
+            symbol_count: 0,
+            table: [0; MAX_SYMBOLS + 1],
+            adaptation: Some(AdaptiveModel::new()),
+            since_decay: 0,
+        };
+
+        for (symbol, &frequency) in snapshot.frequencies.iter().enumerate() {
+            ret.table[symbol + 1] = ret.table[symbol]
+                .checked_add(frequency)
+                .filter(|&total| total <= MAX_STATIC_TOTAL)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "model snapshot frequencies sum to more than {MAX_STATIC_TOTAL}"
+                    )
+                })?;
+        }
+        ret.symbol_count = ret.table[MAX_SYMBOLS];
+
+        anyhow::ensure!(
+            ret.symbol_count == snapshot.symbol_count,
+            "model snapshot frequencies sum to {} but symbol_count is {}",
+            ret.symbol_count,
+            snapshot.symbol_count
+        );
+
+        Ok(ret)
+    }
+}
+
+impl SymbolTable {
+    /// Number of words [`SymbolTable::to_words`] produces.
+    pub(crate) const WORDS: usize = MAX_SYMBOLS + 7;
+
+    /// The whole state of the table, how it adapts included, unlike [`SymbolTable::export`]: every
+    /// frequency, then whether it adapts, the decay window and factor, the increment, the EOF scale
+    /// and how far into the window it is.
+    pub(crate) fn to_words(&self) -> Vec<u64> {
+        let mut words = Vec::with_capacity(Self::WORDS);
+        words.extend(self.frequencies().iter().map(|&f| f as u64));
+
+        let adaptation = self.adaptation.unwrap_or_default();
+        words.extend(
+            [
+                self.adaptation.is_some() as usize,
+                adaptation.window,
+                adaptation.decay_numerator,
+                adaptation.decay_denominator,
+                adaptation.increment,
+                adaptation.eof_scale,
+                self.since_decay,
+            ]
+            .map(|word| word as u64),
+        );
+
+        words
+    }
+
+    /// Rebuilds a table from [`SymbolTable::to_words`], checking it's one a coder could have built.
+    pub(crate) fn from_words(words: &[u64]) -> Result<SymbolTable> {
+        anyhow::ensure!(
+            words.len() == Self::WORDS,
+            "model state must be {} words, got {}",
+            Self::WORDS,
+            words.len()
+        );
+        let words = words
+            .iter()
+            .map(|&word| {
+                usize::try_from(word)
+                    .map_err(|_| anyhow::anyhow!("model count {word} doesn't fit usize"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (frequencies, adaptation) = words.split_at(MAX_SYMBOLS);
+        let &[adaptive, window, numerator, denominator, increment, eof_scale, since_decay] =
+            adaptation
+        else {
+            unreachable!("the length was checked above");
+        };
+
+        let adaptation = match adaptive {
+            0 => None,
+            1 => {
+                let model = if window == 0 {
+                    anyhow::ensure!(
+                        numerator == 1 && denominator == 1,
+                        "a model without a decay window can't have a decay factor"
+                    );
+                    AdaptiveModel::new()
+                } else {
+                    AdaptiveModel::with_decay(window, numerator, denominator)?
+                };
+                anyhow::ensure!(
+                    since_decay < window.max(1),
+                    "model is {since_decay} symbols into a decay window of {window}"
+                );
+                Some(model.with_increment(increment)?.with_eof_scale(eof_scale)?)
+            }
+            _ => anyhow::bail!("model adaptation flag must be 0 or 1, got {adaptive}"),
+        };
+        anyhow::ensure!(
+            adaptation.is_none() || frequencies.iter().all(|&f| f > 0),
+            "every symbol of an adaptive model needs a non-zero frequency"
+        );
+        let total = frequencies
+            .iter()
+            .try_fold(0usize, |total, &f| total.checked_add(f))
+            .filter(|&total| total > 0 && total <= MAX_STATIC_TOTAL)
+            .ok_or_else(|| {
+                anyhow::anyhow!("model frequencies must sum to 1..={MAX_STATIC_TOTAL}")
+            })?;
+
+        let mut ret = Self {
+            symbol_count: total,
+            table: [0; MAX_SYMBOLS + 1],
+            adaptation,
+            since_decay,
+        };
+        ret.set_frequencies(frequencies.try_into().unwrap());
+
+        Ok(ret)
+    }
+}
+
+impl<C: Count> SymbolTable<C> {
+    /// The same table as [`SymbolTable::new`], with counts of type `C`, e.g.
+    /// `SymbolTable::<u16>::uniform()`.
+    pub fn uniform() -> SymbolTable<C> {
+        // Every symbol starts with a count of one, so each cumulative entry is just its index.
+        let mut table = [C::default(); MAX_SYMBOLS + 1];
+        for (i, entry) in table.iter_mut().enumerate() {
+            *entry = C::from_usize(i);
+        }
+
+        SymbolTable {
+            symbol_count: MAX_SYMBOLS,
+            table,
+            adaptation: Some(AdaptiveModel::new()),
+            since_decay: 0,
+        }
+    }
+
+    /// Puts every count back to where a new table with the same adaptation starts: one, as in
+    /// [`SymbolTable::uniform`], or the EOF scale for bytes with [`AdaptiveModel::with_eof_scale`].
+    pub fn reset(&mut self) {
+        *self = SymbolTable {
+            adaptation: self.adaptation,
+            ..Self::uniform()
+        };
+
+        let scale = self.adaptation.map_or(1, |adaptation| adaptation.eof_scale);
+        if scale > 1 {
+            let mut frequencies = [scale; MAX_SYMBOLS];
+            frequencies[EOF_SYMBOL] = 1;
+            self.set_frequencies(&frequencies);
+        }
+    }
+
+    /// Captures the current frequencies so the model can be restored with [`SymbolTable::import`].
+    pub fn export(&self) -> ModelSnapshot {
+        ModelSnapshot {
+            frequencies: self.frequencies(),
+            symbol_count: self.symbol_count,
+        }
+    }
+
+    /// The current count of every symbol, indexed by byte value with EOF last.
+    pub fn frequencies(&self) -> [usize; 257] {
+        let mut frequencies = [0; MAX_SYMBOLS];
+        for (symbol, frequency) in frequencies.iter_mut().enumerate() {
+            let (low, high) = self.get_symbol(symbol);
+            *frequency = high - low;
+        }
+
+        frequencies
+    }
+
+    /// The probability the model currently gives `symbol` (a byte value, or 256 for EOF).
+    pub fn probability(&self, symbol: usize) -> f64 {
+        let (low, high) = self.get_symbol(symbol);
+
+        (high - low) as f64 / self.symbol_count as f64
+    }
+
+    /// Adapts the model as if `symbol` had just been coded.
+    pub fn increment_symbol(&mut self, symbol: usize) {
+        self.increment_symbol_by(symbol, 1);
+    }
+
+    /// Counts `symbol` `step` more times. Once the total passes [`Count::MAX_TOTAL`] every count is
+    /// halved, never below one, so an adaptive model however long the stream stays within the range
+    /// the coder can take instead of narrowing rare symbols down to nothing. Returns how many times the
+    /// counts were halved.
+    fn increment_symbol_by(&mut self, symbol: usize, step: usize) -> u32 {
+        if self.symbol_count + step <= C::MAX_TOTAL {
+            self.symbol_count += step;
+            for i in symbol..MAX_SYMBOLS {
+                self.table[i + 1] = C::from_usize(self.table[i + 1].to_usize() + step);
+            }
+
+            return 0;
+        }
+
+        // The new total doesn't fit the table, so halve the counts before storing them.
+        let mut frequencies = self.frequencies();
+        frequencies[symbol] += step;
+        let rescales = halve_until(&mut frequencies, C::MAX_TOTAL);
+        self.set_frequencies(&frequencies);
+
+        rescales
+    }
+
+    /// What the coder calls after every symbol: an adaptive model counts it, a static one stays as is.
+    /// Returns how many times the counts were scaled down along the way.
+    pub(crate) fn update(&mut self, symbol: usize) -> u32 {
+        let Some(adaptation) = self.adaptation else {
+            return 0;
+        };
+
+        let step = match symbol {
+            EOF_SYMBOL if adaptation.eof_scale > 1 => 0,
+            EOF_SYMBOL => adaptation.increment,
+            _ => adaptation.increment * adaptation.eof_scale,
+        };
+        let mut rescales = self.increment_symbol_by(symbol, step);
+
+        if adaptation.window > 0 {
+            self.since_decay += 1;
+            if self.since_decay == adaptation.window {
+                self.since_decay = 0;
+                if adaptation.decay_numerator == 0 {
+                    // Back to where the table started, which isn't all ones with an EOF scale.
+                    self.reset();
+                } else {
+                    self.decay(adaptation.decay_numerator, adaptation.decay_denominator);
+                }
+                rescales += 1;
+            }
+        }
+
+        rescales
+    }
+
+    /// [`SymbolTable::update`] for a coder that takes totals of at most `max_total`, which can be
+    /// less than the table's own limit: an adaptive table has its counts halved, never below one, for
+    /// as long as they add up to more. Encoder and decoder have to pass the same limit.
+    pub(crate) fn update_within(&mut self, symbol: usize, max_total: usize) -> u32 {
+        let mut rescales = self.update(symbol);
+        if self.adaptation.is_some() && self.symbol_count > max_total {
+            let mut frequencies = self.frequencies();
+            rescales += halve_until(&mut frequencies, max_total);
+            self.set_frequencies(&frequencies);
+        }
+
+        rescales
+    }
+
+    fn decay(&mut self, numerator: usize, denominator: usize) {
+        let mut frequencies = self.frequencies();
+        for frequency in frequencies.iter_mut() {
+            let decayed = (*frequency as u128 * numerator as u128 / denominator as u128) as usize;
+            *frequency = decayed.max(1);
+        }
+        self.set_frequencies(&frequencies);
+    }
+
+    /// Rebuilds the cumulative table from per-symbol counts, which have to add up to at most
+    /// [`Count::MAX_TOTAL`].
+    fn set_frequencies(&mut self, frequencies: &[usize; MAX_SYMBOLS]) {
+        let mut total = 0;
+        for (symbol, &frequency) in frequencies.iter().enumerate() {
+            total += frequency;
+            self.table[symbol + 1] = C::from_usize(total);
+        }
+        self.symbol_count = total;
+    }
+
+    pub(crate) fn get_symbol(&self, symbol: usize) -> (usize, usize) {
+        (
+            self.table[symbol].to_usize(),
+            self.table[symbol + 1].to_usize(),
+        )
+    }
+
+    /// The symbol whose interval holds `cumulative_value`, with the interval. A value past the total
+    /// can only come from a corrupt stream, and is an [`AeError::CorruptStream`] rather than whichever
+    /// symbol the search happens to end on.
+    pub(crate) fn find_symbol(&self, cumulative_value: usize) -> Result<(usize, usize, usize)> {
+        if cumulative_value >= self.symbol_count {
+            return Err(AeError::CorruptStream.into());
+        }
+
+        let mut low = 0;
+        let mut high = self.table.len() - 1;
+
+        while high != low {
+            let mid = (low + high) / 2;
+            if self.table[mid].to_usize() <= cumulative_value {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        let symbol = low - 1;
+        let (symbol_low, symbol_high) = self.get_symbol(symbol);
+
+        Ok((symbol, symbol_low, symbol_high))
+    }
+}
+
+/// The order-0 entropy of `input` in bits: what coding it with a static model of its own byte
+/// frequencies would cost, the lower bound for any memoryless model. Comparing it with the compressed
+/// size shows how much is left for a better model to gain, beyond what adapting costs.
+#[cfg(feature = "std")]
+pub fn order0_entropy_bits(input: &[u8]) -> f64 {
+    let mut counts = [0; 256];
+    for &b in input {
+        counts[b as usize] += 1;
+    }
+
+    order0_entropy_bits_of_counts(&counts)
+}
+
+/// Like [`order0_entropy_bits`], from a histogram of the input's bytes, for input that's only seen a
+/// chunk at a time.
+#[cfg(feature = "std")]
+pub fn order0_entropy_bits_of_counts(counts: &[u64; 256]) -> f64 {
+    let total = counts.iter().sum::<u64>() as f64;
+
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| count as f64 * (total / count as f64).log2())
+        .sum()
+}
+
+/// The adaptive probability of a single bit, for [`crate::BinaryEncoder`] and [`crate::BinaryDecoder`].
+///
+/// It holds the probability of a 0 in units of 1/4096 and moves it 1/32 of the way towards every bit
+/// it codes, so it tracks a changing bias quickly while neither bit ever becomes impossible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitModel {
+    zero_count: u16,
+}
+
+impl Default for BitModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BitModel {
+    /// The total the probability is out of.
+    pub(crate) const TOTAL: usize = 1 << 12;
+    /// How far the probability moves per bit, as a shift: 1/32 of the distance.
+    const ADAPT_SHIFT: u32 = 5;
+
+    /// Starts at even odds.
+    pub fn new() -> BitModel {
+        BitModel {
+            zero_count: (Self::TOTAL / 2) as u16,
+        }
+    }
+
+    /// The current probability of a 1.
+    pub fn probability_of_one(&self) -> f64 {
+        1.0 - self.zero_count as f64 / Self::TOTAL as f64
+    }
+
+    pub(crate) fn zero_count(&self) -> usize {
+        self.zero_count as usize
+    }
+
+    /// Where `bit` lies out of [`BitModel::TOTAL`]: zeroes first, then ones.
+    pub(crate) fn interval(&self, bit: bool) -> (usize, usize) {
+        if bit {
+            (self.zero_count(), Self::TOTAL)
+        } else {
+            (0, self.zero_count())
+        }
+    }
+
+    /// Moves the probability towards `bit`. The shift rounds the step down, so the count never
+    /// reaches 0 or [`BitModel::TOTAL`] and both bits stay codable.
+    pub(crate) fn update(&mut self, bit: bool) {
+        if bit {
+            self.zero_count -= self.zero_count >> Self::ADAPT_SHIFT;
+        } else {
+            self.zero_count += (Self::TOTAL as u16 - self.zero_count) >> Self::ADAPT_SHIFT;
+        }
+    }
+}
+
+/// The largest alphabet an [`ExtendableModel`] may grow to. New symbols are coded with a uniform
+/// distribution over the capacity, so it has to stay well below the coder's 2^30 minimum range.
+pub const MAX_EXTENDABLE_CAPACITY: usize = 1 << 24;
+
+/// Once an [`ExtendableModel`]'s counts add up to more than this, they're halved. Like the static
+/// limit it keeps the total within the coder's range, however long the stream and however big the
+/// alphabet.
+const MAX_EXTENDABLE_TOTAL: usize = MAX_STATIC_TOTAL;
+
+/// An adaptive model over an alphabet that can grow mid-stream.
+///
+/// Besides the known symbols the model keeps a reserved "escape" slot with a fixed weight. When the
+/// encoder sees a symbol that isn't in the alphabet yet it codes the escape followed by the symbol's
+/// identity (uniformly over `0..capacity`), and both sides then add the symbol to the alphabet with a
+/// count of one. See [`crate::Encoder::encode_extendable`] and [`crate::Decoder::decode_extendable`].
+#[derive(Debug, Clone)]
+pub struct ExtendableModel {
+    symbols: Vec<usize>,
+    counts: Vec<usize>,
+    /// Sum of `counts` plus the escape weight.
+    total: usize,
+    escape_weight: usize,
+    capacity: usize,
+}
+
+impl ExtendableModel {
+    /// Creates a model whose alphabet starts as `0..initial_symbols` and may grow up to `capacity`
+    /// symbols. `escape_weight` is the count reserved for the escape slot; it is never incremented, so
+    /// the escape becomes cheaper to avoid as the known symbols accumulate counts.
+    pub fn new(initial_symbols: usize, capacity: usize, escape_weight: usize) -> Result<Self> {
+        anyhow::ensure!(
+            (1..=MAX_EXTENDABLE_CAPACITY).contains(&capacity),
+            "capacity must be between 1 and {MAX_EXTENDABLE_CAPACITY}, not {capacity}"
+        );
+        anyhow::ensure!(
+            initial_symbols <= capacity,
+            "initial alphabet of {initial_symbols} symbols exceeds capacity {capacity}"
+        );
+        anyhow::ensure!(
+            escape_weight > 0 && escape_weight <= MAX_EXTENDABLE_TOTAL / 2,
+            "escape weight must be between 1 and {}",
+            MAX_EXTENDABLE_TOTAL / 2
+        );
+
+        Ok(ExtendableModel {
+            symbols: (0..initial_symbols).collect(),
+            counts: vec![1; initial_symbols],
+            total: initial_symbols + escape_weight,
+            escape_weight,
+            capacity,
+        })
+    }
+
+    /// The symbols currently in the alphabet, in the order they joined it.
+    pub fn alphabet(&self) -> &[usize] {
+        &self.symbols
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Index of the escape slot, which sits after every known symbol.
+    pub(crate) fn escape_slot(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub(crate) fn slot_of(&self, symbol: usize) -> Option<usize> {
+        self.symbols.iter().position(|&s| s == symbol)
+    }
+
+    pub(crate) fn symbol_at(&self, slot: usize) -> usize {
+        self.symbols[slot]
+    }
+
+    pub(crate) fn get_slot(&self, slot: usize) -> (usize, usize) {
+        let low = self.counts[..slot].iter().sum::<usize>();
+        let width = if slot == self.escape_slot() {
+            self.escape_weight
+        } else {
+            self.counts[slot]
+        };
+
+        (low, low + width)
+    }
+
+    pub(crate) fn find_slot(&self, cumulative_value: usize) -> (usize, usize, usize) {
+        let mut low = 0;
+        for (slot, &count) in self.counts.iter().enumerate() {
+            if cumulative_value < low + count {
+                return (slot, low, low + count);
+            }
+            low += count;
+        }
+
+        (self.escape_slot(), low, low + self.escape_weight)
+    }
+
+    /// Counts the symbol in `slot`, keeping the total within `max_total` as well as the model's own
+    /// limit, see [`SymbolTable::update_within`].
+    pub(crate) fn increment_slot(&mut self, slot: usize, max_total: usize) {
+        self.counts[slot] += 1;
+        self.total += 1;
+        self.rescale(max_total);
+    }
+
+    /// Halves every count (keeping each at least one) when the total has outgrown
+    /// [`MAX_EXTENDABLE_TOTAL`] or the coder's `max_total`. Both sides do it after the same symbol,
+    /// so they stay in step.
+    fn rescale(&mut self, max_total: usize) {
+        if self.total <= MAX_EXTENDABLE_TOTAL.min(max_total) {
+            return;
+        }
+
+        for count in &mut self.counts {
+            *count = (*count / 2).max(1);
+        }
+        self.total = self.counts.iter().sum::<usize>() + self.escape_weight;
+    }
+
+    /// Adds `symbol` to the alphabet with a count of one, keeping the total within `max_total` like
+    /// [`ExtendableModel::increment_slot`].
+    pub(crate) fn extend(&mut self, symbol: usize, max_total: usize) -> Result<()> {
+        anyhow::ensure!(
+            symbol < self.capacity,
+            "symbol {symbol} is outside the capacity {}",
+            self.capacity
+        );
+        anyhow::ensure!(
+            self.slot_of(symbol).is_none(),
+            "symbol {symbol} is already in the alphabet"
+        );
+
+        self.symbols.push(symbol);
+        self.counts.push(1);
+        self.total += 1;
+        self.rescale(max_total);
+
+        Ok(())
+    }
+}
+
+/// The model of the run-length mode: an adaptive order-0 model over the byte values, [`EOF_SYMBOL`]
+/// and [`REPEAT_SYMBOL`]. A run of the same byte is coded as the byte, the repeat symbol and the
+/// number of repeats, so however long it is, it takes three symbols and one model update for the
+/// repeat instead of one per byte. See [`crate::Encoder::encode_runs`] and
+/// [`crate::Decoder::decode_run`].
+#[derive(Debug, Clone)]
+pub struct RunModel {
+    pub(crate) symbols: ExtendableModel,
+    /// The last byte decoded, which a repeat symbol repeats. `None` at the start and right after a
+    /// repeat, where a repeat symbol can't be.
+    pub(crate) previous: Option<u8>,
+}
+
+impl Default for RunModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunModel {
+    /// Creates a model where every symbol starts with a count of one.
+    pub fn new() -> RunModel {
+        RunModel {
+            // Every symbol is known from the start, so the escape is never coded and its weight of
+            // one is all it costs.
+            symbols: ExtendableModel::new(REPEAT_SYMBOL + 1, REPEAT_SYMBOL + 1, 1)
+                .expect("the run alphabet is far below the capacity limit"),
+            previous: None,
+        }
+    }
+}
+
+/// Counts in a [`ContextModel`] are halved once a context, or the fallback, adds up to more than this.
+/// Keeping them small lets the model follow changes in the data and keeps every total far inside the
+/// coder's range.
+const MAX_CONTEXT_TOTAL: usize = 1 << 16;
+
+/// An order-1 context model with an escape to order-0, the simplest form of PPM.
+///
+/// Every symbol is predicted from the byte before it. Each of the 256 contexts only knows the symbols
+/// it has actually seen, plus an escape whose weight is the number of them (PPM method C). A symbol the
+/// context hasn't seen yet is coded as the escape followed by the symbol in an adaptive order-0 model
+/// that excludes everything the context already knows, since those would have been coded directly.
+/// Both sides then add it to the context. See [`crate::Encoder::encode_context`] and
+/// [`crate::Decoder::decode_context`].
+#[derive(Debug, Clone)]
+pub struct ContextModel {
+    /// Counts of every symbol after each byte value, one row of `MAX_SYMBOLS` per context.
+    contexts: Vec<u32>,
+    /// The order-0 fallback, where every symbol starts with a count of one.
+    fallback: Vec<u32>,
+    previous: usize,
+}
+
+impl Default for ContextModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContextModel {
+    /// Creates a model with empty contexts and a uniform fallback. The first symbol is coded in the
+    /// context of a zero byte.
+    pub fn new() -> ContextModel {
+        ContextModel {
+            contexts: vec![0; 256 * MAX_SYMBOLS],
+            fallback: vec![1; MAX_SYMBOLS],
+            previous: 0,
+        }
+    }
+
+    fn context(&self) -> &[u32] {
+        &self.contexts[self.previous * MAX_SYMBOLS..][..MAX_SYMBOLS]
+    }
+
+    /// Weight of the escape in the current context: how many distinct symbols it has seen, at least
+    /// one so an empty context can still escape.
+    fn escape_weight(&self) -> usize {
+        self.context().iter().filter(|&&c| c > 0).count().max(1)
+    }
+
+    pub(crate) fn context_total(&self) -> usize {
+        self.context().iter().map(|&c| c as usize).sum::<usize>() + self.escape_weight()
+    }
+
+    /// The interval of `symbol` in the current context, or `None` if it has to be escaped.
+    pub(crate) fn context_interval(&self, symbol: usize) -> Option<(usize, usize)> {
+        let context = self.context();
+        if context[symbol] == 0 {
+            return None;
+        }
+
+        let low = context[..symbol].iter().map(|&c| c as usize).sum::<usize>();
+        Some((low, low + context[symbol] as usize))
+    }
+
+    /// The escape sits after every symbol of the context.
+    pub(crate) fn escape_interval(&self) -> (usize, usize) {
+        let low = self.context_total() - self.escape_weight();
+
+        (low, low + self.escape_weight())
+    }
+
+    /// The symbol of the current context whose interval holds `cumulative_value`, with its interval,
+    /// or `None` for the escape.
+    pub(crate) fn find_in_context(&self, cumulative_value: usize) -> Option<(usize, usize, usize)> {
+        let mut low = 0;
+        for (symbol, &count) in self.context().iter().enumerate() {
+            let high = low + count as usize;
+            if cumulative_value < high {
+                return Some((symbol, low, high));
+            }
+            low = high;
+        }
+
+        None
+    }
+
+    /// Fallback count of `symbol` with the symbols of the current context excluded.
+    fn fallback_count(&self, symbol: usize) -> usize {
+        if self.context()[symbol] > 0 {
+            0
+        } else {
+            self.fallback[symbol] as usize
+        }
+    }
+
+    pub(crate) fn fallback_total(&self) -> usize {
+        (0..MAX_SYMBOLS).map(|s| self.fallback_count(s)).sum()
+    }
+
+    pub(crate) fn fallback_interval(&self, symbol: usize) -> (usize, usize) {
+        let low = (0..symbol).map(|s| self.fallback_count(s)).sum::<usize>();
+
+        (low, low + self.fallback_count(symbol))
+    }
+
+    pub(crate) fn find_in_fallback(&self, cumulative_value: usize) -> (usize, usize, usize) {
+        let mut low = 0;
+        for symbol in 0..MAX_SYMBOLS {
+            let high = low + self.fallback_count(symbol);
+            if cumulative_value < high {
+                return (symbol, low, high);
+            }
+            low = high;
+        }
+
+        unreachable!("cumulative value {cumulative_value} is past the fallback total {low}")
+    }
+
+    /// Counts `symbol` in its context and the fallback, then moves on to the context it starts. Both
+    /// are kept within a coder's `max_total` as well as [`MAX_CONTEXT_TOTAL`]; a context leaves room
+    /// below it for its escape, which can weigh as much as every symbol.
+    pub(crate) fn update(&mut self, symbol: usize, max_total: usize) {
+        let limit = MAX_CONTEXT_TOTAL.min(max_total);
+
+        let row = self.previous * MAX_SYMBOLS;
+        self.contexts[row + symbol] += 1;
+        Self::rescale(
+            &mut self.contexts[row..][..MAX_SYMBOLS],
+            limit.min(max_total - MAX_SYMBOLS),
+        );
+
+        self.fallback[symbol] += 1;
+        Self::rescale(&mut self.fallback, limit);
+
+        self.previous = symbol.min(MAX_DATA_SYMBOL);
+    }
+
+    /// Halves `counts` once they add up to more than `limit`. A count that was non-zero stays at least
+    /// one, so a context doesn't forget the symbols it has seen.
+    fn rescale(counts: &mut [u32], limit: usize) {
+        if counts.iter().map(|&c| c as usize).sum::<usize>() <= limit {
+            return;
+        }
+
+        for count in counts.iter_mut().filter(|c| **c > 0) {
+            *count = (*count / 2).max(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        order0_entropy_bits, AdaptiveModel, ExtendableModel, SymbolTable, EOF_SYMBOL,
+        MAX_EXTENDABLE_TOTAL, MAX_STATIC_TOTAL, MAX_SYMBOLS,
+    };
+    use crate::error::AeError;
+    use crate::xorshift::Xorshift;
+    use crate::{Decoder, Encoder};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn new_matches_counting_every_symbol_once() {
+        let mut counted = SymbolTable {
+            symbol_count: 0,
+            table: [0; MAX_SYMBOLS + 1],
+            adaptation: Some(AdaptiveModel::new()),
+            since_decay: 0,
+        };
+        for i in 0..MAX_SYMBOLS {
+            counted.increment_symbol(i);
+        }
+
+        let built = SymbolTable::new();
+        assert_eq!(built.symbol_count, counted.symbol_count);
+        for i in 0..=MAX_SYMBOLS {
+            assert_eq!(built.table[i], counted.table[i], "entry {i}");
+        }
+    }
+
+    #[test]
+    fn initial_counts_are_validated() {
+        let mut counts = [1; MAX_SYMBOLS];
+        let uniform = SymbolTable::with_initial_counts(&counts).unwrap();
+        assert_eq!(uniform.table, SymbolTable::new().table);
+
+        counts[b'e' as usize] = 0;
+        assert!(SymbolTable::with_initial_counts(&counts).is_err());
+
+        counts[b'e' as usize] = MAX_STATIC_TOTAL;
+        assert!(SymbolTable::with_initial_counts(&counts).is_err());
+    }
+
+    fn round_trip_extendable(model: &ExtendableModel, input: &[usize]) -> ExtendableModel {
+        let mut output = Vec::new();
+        let mut encoder_model = model.clone();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            for &s in input {
+                encoder.encode_extendable(&mut encoder_model, s).unwrap();
+            }
+            encoder.encode_end().unwrap();
+        }
+
+        let mut decoder_model = model.clone();
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        for &s in input {
+            assert_eq!(decoder.decode_extendable(&mut decoder_model).unwrap(), s);
+        }
+        assert_eq!(decoder_model.counts, encoder_model.counts);
+
+        decoder_model
+    }
+
+    #[test]
+    fn large_sparse_alphabet_round_trips() {
+        // 100k possible tokens of which a few hundred show up, the common ones far more often.
+        let input = Xorshift::with_seed(0x9E3779B97F4A7C15)
+            .take(5000)
+            .map(|r| {
+                let pick = (r >> 40) as usize % 300;
+                (pick * pick / 300) * 331 % 100_000
+            })
+            .collect::<Vec<_>>();
+
+        let model = ExtendableModel::new(0, 100_000, 8).unwrap();
+        let grown = round_trip_extendable(&model, &input);
+        assert!(grown.alphabet().len() < 300);
+        assert_eq!(grown.total(), input.len() + 8);
+    }
+
+    #[test]
+    fn extendable_counts_are_halved_at_the_limit() {
+        let mut model = ExtendableModel::new(3, 1 << 20, 1).unwrap();
+        model.counts[0] = MAX_EXTENDABLE_TOTAL - 3;
+        model.total = MAX_EXTENDABLE_TOTAL;
+
+        // The first 0 brings the total over the limit, the others are already at the floor of one.
+        let grown = round_trip_extendable(&model, &[0, 1, 2, 1 << 19, 0]);
+        assert_eq!(grown.total(), grown.counts.iter().sum::<usize>() + 1);
+        assert_eq!(grown.counts[0], (MAX_EXTENDABLE_TOTAL - 2) / 2 + 1);
+        assert_eq!(grown.counts[1..], [2, 2, 1]);
+    }
+
+    #[test]
+    fn adaptive_counts_are_halved_past_the_static_total() {
+        // A big step crosses the limit after a few dozen symbols instead of a billion. Coding the
+        // symbols also checks the total against the coder's range every time.
+        let model = AdaptiveModel::new()
+            .with_increment(1 << 24)
+            .unwrap()
+            .build();
+        let input = (0..2000).map(|i| (i * i % 7) as u8).collect::<Vec<_>>();
+
+        let mut output = Vec::new();
+        let encoded = {
+            let mut encoder = Encoder::with_model(&mut output, model.clone());
+            encoder.encode_slice(&input).unwrap();
+            encoder.encode_end().unwrap();
+            encoder.model().clone()
+        };
+        assert!(encoded.symbol_count <= MAX_STATIC_TOTAL);
+        assert!(encoded.symbol_count > MAX_STATIC_TOTAL / 2);
+        // Symbols the input never had keep a count of one.
+        assert_eq!(encoded.frequencies()[200], 1);
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::with_model(&mut cursor, model).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+        assert_eq!(decoder.model().table, encoded.table);
+    }
+
+    #[test]
+    fn u16_counts_are_halved_before_they_overflow() {
+        let input = (0..100_000u64)
+            .map(|i| (i * i % 7) as u8)
+            .collect::<Vec<_>>();
+
+        let mut model = SymbolTable::<u16>::uniform();
+        let mut rescales = 0;
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            for &b in &input {
+                let before = model.symbol_count;
+                encoder.encode_with(&mut model, b as usize).unwrap();
+                rescales += (model.symbol_count < before) as u32;
+                assert!(model.symbol_count <= u16::MAX as usize);
+            }
+            encoder.encode_end().unwrap();
+        }
+        assert!(rescales > 0);
+        assert_eq!(model.frequencies()[200], 1);
+        assert_eq!(
+            model.frequencies().iter().sum::<usize>(),
+            model.symbol_count
+        );
+
+        let mut decoded_model = SymbolTable::<u16>::uniform();
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = Decoder::new(&mut cursor).unwrap();
+        let decoded = (0..input.len())
+            .map(|_| decoder.decode_with(&mut decoded_model).unwrap() as u8)
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, input);
+        assert_eq!(decoded_model.table, model.table);
+    }
+
+    #[test]
+    fn cumulative_values_past_the_total_are_corrupt() {
+        let model = SymbolTable::from_counts(&[3; 256], false).unwrap();
+        assert_eq!(model.find_symbol(0).unwrap(), (0, 0, 3));
+        assert_eq!(model.find_symbol(767).unwrap(), (255, 765, 768));
+        assert_eq!(model.find_symbol(768).unwrap(), (EOF_SYMBOL, 768, 769));
+
+        for value in [769, 1 << 20, usize::MAX] {
+            let err = model.find_symbol(value).unwrap_err();
+            assert_eq!(err.downcast_ref::<AeError>(), Some(&AeError::CorruptStream));
+        }
+    }
+
+    #[test]
+    fn entropy_of_uniform_bytes_is_about_eight_bits() {
+        let random = Xorshift::with_seed(0x9E3779B97F4A7C15)
+            .take(100_000)
+            .map(|r| (r >> 32) as u8)
+            .collect::<Vec<_>>();
+        let bits_per_byte = order0_entropy_bits(&random) / random.len() as f64;
+        assert!((7.99..=8.0).contains(&bits_per_byte), "{bits_per_byte}");
+
+        assert_eq!(order0_entropy_bits(&[]), 0.0);
+        assert_eq!(order0_entropy_bits(&[b'a'; 100]), 0.0);
+        assert_eq!(order0_entropy_bits(&b"ab".repeat(50)), 100.0);
+
+        // Nothing memoryless can beat it, adaptive or not.
+        let text = b"an adaptive model pays a little to learn the frequencies".repeat(10);
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output);
+            encoder.encode_slice(&text).unwrap();
+            encoder.encode_end().unwrap();
+        }
+        assert!(output.len() as f64 * 8.0 > order0_entropy_bits(&text));
+    }
+}
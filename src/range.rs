@@ -0,0 +1,320 @@
+//! A carryless byte-oriented range coder with 64-bit registers.
+//!
+//! This is a drop-in alternative to [`crate::Encoder`]/[`crate::Decoder`]: same API, same
+//! [`SymbolTable`] model, but it renormalizes a whole byte at a time and never has to track underflow
+//! bits, so it does far less work per symbol. The output format is different, so a stream has to be
+//! decoded by the same kind of coder that encoded it.
+//!
+//! The scheme is Subbotin's carryless range coder. Instead of propagating a carry when `low` and
+//! `low + range` straddle a byte boundary, the range is shrunk so that they no longer do, which costs
+//! a fraction of a bit in the rare case it happens.
+
+use crate::error::AeError;
+use crate::io::{Read, Write};
+use crate::model::{SymbolTable, EOF_SYMBOL};
+use anyhow::Result;
+use core::fmt;
+
+/// Once `low` and `low + range` agree on the top byte, that byte is final and gets shifted out.
+const TOP: u64 = 1 << 56;
+/// The range is never allowed to drop below this, which bounds the model total the coder can handle.
+const BOT: u64 = 1 << 48;
+const REGISTER_BYTES: usize = 8;
+
+/// Checks the model total leaves every count at least one step of the range, even once the range has
+/// dropped to [`BOT`]. Past that the carryless scheme no longer codes every symbol.
+fn check_total(total: usize) -> Result<u64> {
+    let total = total as u64;
+    anyhow::ensure!(
+        total <= BOT,
+        "model total {total} is too large for the range coder, the limit is {BOT}"
+    );
+
+    Ok(total)
+}
+
+#[derive(Debug)]
+pub struct RangeEncoder<'a, T: Write> {
+    low: u64,
+    range: u64,
+
+    symbols: SymbolTable,
+    writer: &'a mut T,
+}
+
+#[derive(Debug)]
+pub struct RangeDecoder<'a, T: Read> {
+    low: u64,
+    range: u64,
+    code: u64,
+    /// Zero bytes made up because the stream had already ended.
+    eof_bytes: usize,
+
+    symbols: SymbolTable,
+    reader: &'a mut T,
+}
+
+impl<T: Write + fmt::Debug> RangeEncoder<'_, T> {
+    pub fn new(writer: &mut T) -> RangeEncoder<'_, T> {
+        Self::with_model(writer, SymbolTable::new())
+    }
+
+    pub fn with_model(writer: &mut T, model: SymbolTable) -> RangeEncoder<'_, T> {
+        RangeEncoder {
+            low: 0,
+            range: u64::MAX,
+            symbols: model,
+            writer,
+        }
+    }
+
+    pub fn encode_next(&mut self, symbol: usize) -> Result<()> {
+        anyhow::ensure!(
+            symbol <= EOF_SYMBOL,
+            "symbol {symbol} is outside the alphabet, which ends with EOF at {EOF_SYMBOL}"
+        );
+        let (symbol_low, symbol_high) = self.symbols.get_symbol(symbol);
+        anyhow::ensure!(
+            symbol_high > symbol_low,
+            "symbol {symbol} has a zero count in the model and can't be encoded"
+        );
+        let total = check_total(self.symbols.symbol_count)?;
+
+        // One division per symbol, the bit coder needs two.
+        self.range /= total;
+        self.low = self.low.wrapping_add(symbol_low as u64 * self.range);
+        self.range *= (symbol_high - symbol_low) as u64;
+
+        loop {
+            if (self.low ^ self.low.wrapping_add(self.range)) >= TOP {
+                if self.range >= BOT {
+                    break;
+                }
+
+                // The top bytes differ but the range got small: give up the part of the range above
+                // the next BOT boundary so the top byte is settled without needing a carry.
+                self.range = self.low.wrapping_neg() & (BOT - 1);
+            }
+
+            self.write_byte((self.low >> 56) as u8)?;
+            self.low <<= 8;
+            self.range <<= 8;
+        }
+
+        self.symbols.update(symbol);
+
+        anyhow::Ok(())
+    }
+
+    pub fn encode_end(&mut self) -> Result<()> {
+        self.encode_next(EOF_SYMBOL)?;
+
+        for _ in 0..REGISTER_BYTES {
+            self.write_byte((self.low >> 56) as u8)?;
+            self.low <<= 8;
+        }
+
+        anyhow::Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<()> {
+        anyhow::ensure!(self.writer.write(&[byte])? == 1);
+
+        anyhow::Ok(())
+    }
+}
+
+impl<T: Read + fmt::Debug> RangeDecoder<'_, T> {
+    pub fn new(reader: &mut T) -> Result<RangeDecoder<'_, T>> {
+        Self::with_model(reader, SymbolTable::new())
+    }
+
+    pub fn with_model(reader: &mut T, model: SymbolTable) -> Result<RangeDecoder<'_, T>> {
+        let mut decoder = RangeDecoder {
+            low: 0,
+            range: u64::MAX,
+            code: 0,
+            eof_bytes: 0,
+            symbols: model,
+            reader,
+        };
+
+        for _ in 0..REGISTER_BYTES {
+            decoder.code = (decoder.code << 8) | decoder.read_byte()? as u64;
+        }
+
+        anyhow::Ok(decoder)
+    }
+
+    pub fn decode_next(&mut self) -> Result<usize> {
+        let total = check_total(self.symbols.symbol_count)?;
+
+        self.range /= total;
+        let cumulative_value = (self.code.wrapping_sub(self.low) / self.range).min(total - 1);
+
+        let (symbol, symbol_low, symbol_high) =
+            self.symbols.find_symbol(cumulative_value as usize)?;
+
+        self.low = self.low.wrapping_add(symbol_low as u64 * self.range);
+        self.range *= (symbol_high - symbol_low) as u64;
+
+        loop {
+            if (self.low ^ self.low.wrapping_add(self.range)) >= TOP {
+                if self.range >= BOT {
+                    break;
+                }
+
+                self.range = self.low.wrapping_neg() & (BOT - 1);
+            }
+
+            self.code = (self.code << 8) | self.read_byte()? as u64;
+            self.low <<= 8;
+            self.range <<= 8;
+        }
+
+        self.symbols.update(symbol);
+
+        anyhow::Ok(symbol)
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        let mut buff = [0];
+
+        // Past the end of the stream the encoder's flush would have written zeroes anyway. A complete
+        // stream never actually needs them, since the flush writes out the whole register the decoder
+        // reads ahead, so more than a register's worth means it was cut short. Going on would decode
+        // garbage from zeroes forever.
+        if self.reader.read(&mut buff)? == 1 {
+            anyhow::Ok(buff[0])
+        } else {
+            self.eof_bytes += 1;
+            if self.eof_bytes > REGISTER_BYTES {
+                return Err(AeError::UnexpectedEof.into());
+            }
+            anyhow::Ok(0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RangeDecoder, RangeEncoder};
+    use crate::error::AeError;
+    use crate::model::{AdaptiveModel, SymbolTable, EOF_SYMBOL};
+    use crate::xorshift::Xorshift;
+    use quickcheck_macros::quickcheck;
+
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+
+        {
+            let mut encoder = RangeEncoder::new(&mut output);
+            for s in input {
+                encoder.encode_next(*s as usize).unwrap();
+            }
+            encoder.encode_end().unwrap();
+        }
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = RangeDecoder::new(&mut cursor).unwrap();
+        let mut decoded = Vec::new();
+        loop {
+            let s = decoder.decode_next().unwrap();
+            if s == EOF_SYMBOL {
+                break;
+            }
+            decoded.push(s as u8);
+        }
+
+        decoded
+    }
+
+    #[quickcheck]
+    fn can_read_and_write_same_bytes(input: Vec<u8>) {
+        assert_eq!(round_trip(&input), input);
+    }
+
+    #[test]
+    fn large_randomized_corpus_round_trips() {
+        // Mix runs, skewed text-like bytes and uniformly random bytes so every renormalization path
+        // gets exercised.
+        let mut random = Xorshift::new();
+        let mut next = move || random.next().unwrap();
+
+        let mut input = Vec::new();
+        while input.len() < 1 << 20 {
+            let r = next();
+            match r % 3 {
+                0 => input.extend(std::iter::repeat_n(
+                    (r >> 8) as u8,
+                    (r >> 16) as usize % 300,
+                )),
+                1 => input
+                    .extend((0..(r >> 16) % 2000).map(|_| b"etaoin shrdlu"[next() as usize % 13])),
+                _ => input.extend((0..(r >> 16) % 2000).map(|_| next() as u8)),
+            }
+        }
+
+        assert_eq!(round_trip(&input), input);
+    }
+
+    #[test]
+    fn empty_input_round_trips() {
+        assert_eq!(round_trip(&[]), []);
+    }
+
+    /// Like the bit decoder's harness: arbitrary bytes end in symbols or an error, never a panic or a
+    /// decoder that keeps producing symbols from the zeroes past the end.
+    #[quickcheck]
+    fn decoder_survives_arbitrary_bytes(input: Vec<u8>, window: u8) {
+        let models = [
+            SymbolTable::new(),
+            AdaptiveModel::with_decay(window as usize % 32 + 1, 1, 2)
+                .unwrap()
+                .build(),
+        ];
+
+        for model in models {
+            let mut cursor = std::io::Cursor::new(&input);
+            let mut decoder = RangeDecoder::with_model(&mut cursor, model).unwrap();
+
+            // Neither model ever gives a symbol a probability close enough to one for this many to
+            // fit in the input plus the register's worth of made up zeroes.
+            let mut finished = false;
+            for _ in 0..(input.len() + 16) * 64 {
+                match decoder.decode_next() {
+                    Ok(EOF_SYMBOL) | Err(_) => {
+                        finished = true;
+                        break;
+                    }
+                    Ok(_) => {}
+                }
+            }
+            assert!(finished);
+        }
+    }
+
+    #[test]
+    fn truncated_stream_runs_out() {
+        let mut output = Vec::new();
+        {
+            let mut encoder = RangeEncoder::new(&mut output);
+            for &b in b"this stream will be cut off well before the end" {
+                encoder.encode_next(b as usize).unwrap();
+            }
+            encoder.encode_end().unwrap();
+        }
+        output.truncate(10);
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut decoder = RangeDecoder::new(&mut cursor).unwrap();
+        let err = loop {
+            match decoder.decode_next() {
+                Ok(EOF_SYMBOL) => panic!("decoded an EOF that was cut off"),
+                Ok(_) => {}
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(err.downcast_ref::<AeError>(), Some(&AeError::UnexpectedEof));
+    }
+}
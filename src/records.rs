@@ -0,0 +1,358 @@
+//! A stream of independently compressed records.
+//!
+//! Every record is framed as a flags byte, the compressed length as a u32 LE, two optional CRC-32s
+//! (u32 LE each, present when bit 0 of the flags is set), one of the flags and length bytes and one
+//! of the compressed bytes, and then the compressed bytes themselves. Each record gets a fresh model,
+//! so a record can be decoded, or skipped when it's corrupt, without touching the others.
+
+use crate::error::AeError;
+use crate::io::{read_exact, write_all, Read, Write};
+use crate::{Decoder, Encoder};
+use alloc::vec::Vec;
+use anyhow::Result;
+use core::fmt;
+
+const FLAG_CHECKSUM: u8 = 0x01;
+
+/// How much of a payload [`RecordReader`] reads at a time, so a damaged length field can't make it
+/// allocate more than the stream actually holds.
+const READ_CHUNK: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub struct RecordWriter<'a, T: Write> {
+    writer: &'a mut T,
+    checksum: bool,
+}
+
+#[derive(Debug)]
+pub struct RecordReader<'a, T: Read> {
+    reader: &'a mut T,
+    record: usize,
+    done: bool,
+}
+
+impl<T: Write + fmt::Debug> RecordWriter<'_, T> {
+    pub fn new(writer: &mut T) -> RecordWriter<'_, T> {
+        RecordWriter {
+            writer,
+            checksum: false,
+        }
+    }
+
+    /// Stores CRC-32s with every record written from now on, so [`RecordReader`] can tell exactly
+    /// which record was damaged, and a damaged frame header from a damaged payload.
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn write_record(&mut self, data: &[u8]) -> Result<()> {
+        let mut payload = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut payload);
+            encoder.encode_slice(data)?;
+            encoder.encode_end()?;
+        }
+
+        let length = u32::try_from(payload.len())?;
+        let flags = if self.checksum { FLAG_CHECKSUM } else { 0 };
+
+        let mut header = [flags, 0, 0, 0, 0];
+        header[1..].copy_from_slice(&length.to_le_bytes());
+        write_all(self.writer, &header)?;
+        if self.checksum {
+            write_all(self.writer, &crc32(&header).to_le_bytes())?;
+            write_all(self.writer, &crc32(&payload).to_le_bytes())?;
+        }
+        write_all(self.writer, &payload)
+    }
+}
+
+impl<T: Read + fmt::Debug> RecordReader<'_, T> {
+    pub fn new(reader: &mut T) -> RecordReader<'_, T> {
+        RecordReader {
+            reader,
+            record: 0,
+            done: false,
+        }
+    }
+
+    /// Reads the next frame and checks its payload against the checksum, leaving the reader at the
+    /// start of the following frame unless this fails with something other than a checksum mismatch.
+    fn read_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut header = [0; 5];
+        if !read_exact(self.reader, &mut header[..1])? {
+            return Ok(None);
+        }
+        self.record += 1;
+        anyhow::ensure!(
+            read_exact(self.reader, &mut header[1..])?,
+            "truncated record header"
+        );
+        let flags = header[0];
+        anyhow::ensure!(
+            flags & !FLAG_CHECKSUM == 0,
+            "record {} has unknown flags {flags:#04x}",
+            self.record
+        );
+
+        let checksum = if flags & FLAG_CHECKSUM != 0 {
+            let mut checksums = [[0; 4]; 2];
+            anyhow::ensure!(
+                read_exact(self.reader, checksums.as_flattened_mut())?,
+                "truncated record header"
+            );
+            let [header_checksum, checksum] = checksums.map(u32::from_le_bytes);
+            // With the length in doubt, so is where the next record starts.
+            anyhow::ensure!(
+                header_checksum == crc32(&header),
+                "record {} has a damaged header",
+                self.record
+            );
+            Some(checksum)
+        } else {
+            None
+        };
+
+        // Grown a chunk at a time, so a length claiming more than the stream holds runs into its end
+        // instead of allocating all of it up front.
+        let length = u32::from_le_bytes(header[1..].try_into().unwrap()) as usize;
+        let mut payload = Vec::new();
+        while payload.len() < length {
+            let start = payload.len();
+            payload.resize(length.min(start + READ_CHUNK), 0);
+            anyhow::ensure!(
+                read_exact(self.reader, &mut payload[start..])?,
+                "truncated record"
+            );
+        }
+
+        if checksum.is_some_and(|checksum| checksum != crc32(&payload)) {
+            return Err(AeError::ChecksumMismatch {
+                record: self.record,
+            }
+            .into());
+        }
+
+        Ok(Some(payload))
+    }
+}
+
+fn decode_payload(mut payload: &[u8]) -> Result<Vec<u8>> {
+    Decoder::new(&mut payload)?.decode_to_vec()
+}
+
+/// Yields every record in order. A record that fails its checksum or doesn't decode yields its error
+/// and reading carries on with the next one, since its whole frame was read. A damaged frame or a
+/// failing reader ends the iteration after reporting the error, since the following record can't be
+/// located.
+impl<T: Read + fmt::Debug> Iterator for RecordReader<'_, T> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.read_frame() {
+            Ok(Some(payload)) => Some(decode_payload(&payload)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                if !matches!(
+                    e.downcast_ref::<AeError>(),
+                    Some(AeError::ChecksumMismatch { .. })
+                ) {
+                    self.done = true;
+                }
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// CRC-32 as used by zlib and PNG (reflected, polynomial 0xEDB88320).
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc32, RecordReader, RecordWriter};
+    use crate::error::AeError;
+
+    #[test]
+    fn crc32_matches_the_reference_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn corrupt_record_is_flagged_and_the_rest_decode() {
+        let records: [&[u8]; 5] = [b"first", b"second record", b"third", b"", b"fifth and last"];
+
+        let mut output = Vec::new();
+        let mut offsets = Vec::new();
+        {
+            let mut writer = RecordWriter::new(&mut output).with_checksum(true);
+            for record in records {
+                offsets.push(writer.writer.len());
+                writer.write_record(record).unwrap();
+            }
+        }
+
+        // Flip a bit in the third record's payload, past its 13 byte header.
+        output[offsets[2] + 13] ^= 0x10;
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let results = RecordReader::new(&mut cursor).collect::<Vec<_>>();
+
+        assert_eq!(results.len(), 5);
+        for (i, result) in results.iter().enumerate() {
+            if i == 2 {
+                let err = result.as_ref().unwrap_err();
+                assert_eq!(
+                    err.downcast_ref::<AeError>(),
+                    Some(&AeError::ChecksumMismatch { record: 3 })
+                );
+            } else {
+                assert_eq!(result.as_ref().unwrap(), records[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn records_without_checksums_round_trip() {
+        let mut output = Vec::new();
+        {
+            let mut writer = RecordWriter::new(&mut output);
+            writer.write_record(b"one").unwrap();
+            writer.write_record(b"two").unwrap();
+        }
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let records = RecordReader::new(&mut cursor)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(records, [b"one".to_vec(), b"two".to_vec()]);
+    }
+
+    #[test]
+    fn truncated_frame_ends_iteration() {
+        let mut output = Vec::new();
+        RecordWriter::new(&mut output)
+            .write_record(b"cut short")
+            .unwrap();
+        output.truncate(output.len() - 2);
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let mut reader = RecordReader::new(&mut cursor);
+
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn damaged_header_ends_iteration() {
+        let mut output = Vec::new();
+        {
+            let mut writer = RecordWriter::new(&mut output).with_checksum(true);
+            writer.write_record(b"first").unwrap();
+            writer.write_record(b"second").unwrap();
+        }
+
+        // The top bit of the first record's length, which would claim 2 GiB.
+        output[4] ^= 0x80;
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let results = RecordReader::new(&mut cursor).collect::<Vec<_>>();
+
+        assert_eq!(results.len(), 1);
+        let err = results[0].as_ref().unwrap_err();
+        assert!(err.downcast_ref::<AeError>().is_none(), "{err}");
+        assert!(err.to_string().contains("damaged header"), "{err}");
+    }
+
+    #[test]
+    fn length_past_the_end_is_truncated_not_allocated() {
+        let mut output = vec![0];
+        output.extend_from_slice(&u32::MAX.to_le_bytes());
+        output.extend_from_slice(b"only a few bytes");
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let results = RecordReader::new(&mut cursor).collect::<Vec<_>>();
+
+        assert_eq!(results.len(), 1);
+        let err = results[0].as_ref().unwrap_err();
+        assert!(err.to_string().contains("end of input"), "{err}");
+    }
+
+    /// Fails every read.
+    #[derive(Debug)]
+    struct FailingReader;
+
+    impl std::io::Read for FailingReader {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("device gone"))
+        }
+    }
+
+    #[test]
+    fn failing_reader_ends_iteration() {
+        let mut reader = FailingReader;
+        let results = RecordReader::new(&mut reader).collect::<Vec<_>>();
+
+        assert_eq!(results.len(), 1);
+        let err = results[0].as_ref().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AeError>(),
+            Some(&AeError::Io {
+                kind: std::io::ErrorKind::Other
+            })
+        );
+    }
+
+    #[test]
+    fn undecodable_record_is_skipped() {
+        let mut output = Vec::new();
+        {
+            let mut writer = RecordWriter::new(&mut output);
+            writer.write_record(b"before").unwrap();
+            // A frame whose payload is cut short: it reads in full, but doesn't decode.
+            let mut payload = Vec::new();
+            let mut encoder = crate::Encoder::new(&mut payload);
+            encoder
+                .encode_slice(b"a record that loses its tail")
+                .unwrap();
+            encoder.encode_end().unwrap();
+            drop(encoder);
+            payload.truncate(4);
+            writer.writer.push(0);
+            writer.writer.extend_from_slice(&4u32.to_le_bytes());
+            writer.writer.extend_from_slice(&payload);
+            writer.write_record(b"after").unwrap();
+        }
+
+        let mut cursor = std::io::Cursor::new(&output);
+        let results = RecordReader::new(&mut cursor).collect::<Vec<_>>();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), b"before");
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap(), b"after");
+    }
+}
@@ -0,0 +1,257 @@
+//! A slow arithmetic coder that is easy to audit, to test [`crate::Encoder`] and [`crate::Decoder`]
+//! against. Test only, and not meant for anything else: it keeps the whole stream in memory as a
+//! vector of bits and does a linear search for every symbol it decodes.
+//!
+//! It codes the same intervals as the real coder, with the same integer arithmetic, but leaves out
+//! everything that makes that one fast or clever:
+//!
+//! - Narrowing divides with plain `u128` arithmetic, whatever the register width.
+//! - Renormalizing zooms into whichever half-width window the interval fits, `[0, 1/2)`, `[1/2, 1)`
+//!   or `[1/4, 3/4)`, in that order, without emitting anything.
+//! - The encoder keeps where the window starts as an exact binary number, adding each zoom's offset
+//!   into it with a carry, instead of holding back underflow bits until they're decided.
+//! - The stream ends with every bit of the bottom of the final interval, not the shortest tail that
+//!   still decodes.
+
+use crate::model::SymbolTable;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Narrows `low..=high` to the part of it `symbol_low..symbol_high` out of `total` covers.
+fn narrow(low: u64, high: u64, symbol_low: usize, symbol_high: usize, total: usize) -> (u64, u64) {
+    let range = (high - low) as u128 + 1;
+    let total = total as u128;
+
+    (
+        low + (symbol_low as u128 * range / total) as u64,
+        low + (symbol_high as u128 * range / total - 1) as u64,
+    )
+}
+
+/// Where the half-width window that `low..=high` fits in starts, if it fits one.
+fn zoom_offset<const BITS: u32>(low: u64, high: u64) -> Option<u64> {
+    let half = 1 << (BITS - 1);
+    let quarter = half / 2;
+
+    [0, half, quarter]
+        .into_iter()
+        .find(|&offset| low >= offset && high - offset < half)
+}
+
+/// The largest model total `BITS` wide registers take, a quarter of them, which adaptive models are
+/// halved to stay within.
+fn model_limit<const BITS: u32>() -> usize {
+    usize::try_from(1u64 << (BITS - 2)).unwrap_or(usize::MAX)
+}
+
+#[derive(Debug)]
+pub(crate) struct ReferenceEncoder<const BITS: u32> {
+    /// Where the window starts, as a binary number `BITS` bits longer than the zooms so far.
+    origin: Vec<bool>,
+    /// The interval, relative to `origin`.
+    low: u64,
+    high: u64,
+    model: SymbolTable,
+}
+
+impl<const BITS: u32> ReferenceEncoder<BITS> {
+    pub(crate) fn new() -> ReferenceEncoder<BITS> {
+        ReferenceEncoder {
+            origin: vec![false; BITS as usize],
+            low: 0,
+            high: u64::MAX >> (u64::BITS - BITS),
+            model: SymbolTable::new(),
+        }
+    }
+
+    pub(crate) fn encode(&mut self, symbol: usize) {
+        let (symbol_low, symbol_high) = self.model.get_symbol(symbol);
+        (self.low, self.high) = narrow(
+            self.low,
+            self.high,
+            symbol_low,
+            symbol_high,
+            self.model.symbol_count,
+        );
+        self.model.update_within(symbol, model_limit::<BITS>());
+
+        while let Some(offset) = zoom_offset::<BITS>(self.low, self.high) {
+            add(&mut self.origin, offset);
+            self.origin.push(false);
+            self.low = 2 * (self.low - offset);
+            self.high = 2 * (self.high - offset) + 1;
+        }
+    }
+
+    /// Every bit of the bottom of the interval, padded to a byte.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        add(&mut self.origin, self.low);
+
+        let mut bytes = vec![0u8; self.origin.len().div_ceil(8)];
+        for (i, &bit) in self.origin.iter().enumerate() {
+            bytes[i / 8] |= (bit as u8) << (7 - i % 8);
+        }
+
+        bytes
+    }
+}
+
+/// Adds `value` to the binary number `bits`, most significant bit first.
+fn add(bits: &mut [bool], mut value: u64) {
+    let mut carry = false;
+    for bit in bits.iter_mut().rev() {
+        if value == 0 && !carry {
+            return;
+        }
+
+        let sum = *bit as u64 + (value & 1) + carry as u64;
+        *bit = sum & 1 == 1;
+        carry = sum > 1;
+        value >>= 1;
+    }
+    assert!(
+        value == 0 && !carry,
+        "the window ran past the top of the stream"
+    );
+}
+
+#[derive(Debug)]
+pub(crate) struct ReferenceDecoder<'a, const BITS: u32> {
+    stream: &'a [u8],
+    /// The next bit of `stream` to read.
+    position: usize,
+    /// The interval, and the stream's value, relative to where the window starts.
+    low: u64,
+    high: u64,
+    code: u64,
+    model: SymbolTable,
+}
+
+impl<'a, const BITS: u32> ReferenceDecoder<'a, BITS> {
+    pub(crate) fn new(stream: &'a [u8]) -> ReferenceDecoder<'a, BITS> {
+        let mut decoder = ReferenceDecoder {
+            stream,
+            position: 0,
+            low: 0,
+            high: u64::MAX >> (u64::BITS - BITS),
+            code: 0,
+            model: SymbolTable::new(),
+        };
+        for _ in 0..BITS {
+            decoder.code = 2 * decoder.code + decoder.read_bit();
+        }
+
+        decoder
+    }
+
+    /// The next bit of the stream, as the real decoder sees it: ones once the stream has ended.
+    fn read_bit(&mut self) -> u64 {
+        let bit = match self.stream.get(self.position / 8) {
+            Some(byte) => byte >> (7 - self.position % 8) & 1,
+            None => 1,
+        };
+        self.position += 1;
+
+        bit as u64
+    }
+
+    pub(crate) fn decode(&mut self) -> usize {
+        let total = self.model.symbol_count;
+        let range = (self.high - self.low) as u128 + 1;
+        let target = (((self.code - self.low) as u128 + 1) * total as u128 - 1) / range;
+
+        let symbol = (0..=crate::model::EOF_SYMBOL)
+            .find(|&symbol| (target as usize) < self.model.get_symbol(symbol).1)
+            .expect("the target is below the total");
+        let (symbol_low, symbol_high) = self.model.get_symbol(symbol);
+        (self.low, self.high) = narrow(self.low, self.high, symbol_low, symbol_high, total);
+        self.model.update_within(symbol, model_limit::<BITS>());
+
+        while let Some(offset) = zoom_offset::<BITS>(self.low, self.high) {
+            self.low = 2 * (self.low - offset);
+            self.high = 2 * (self.high - offset) + 1;
+            self.code = 2 * (self.code - offset) + self.read_bit();
+        }
+
+        symbol
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ReferenceDecoder, ReferenceEncoder};
+    use crate::model::EOF_SYMBOL;
+    use crate::xorshift::Xorshift;
+    use crate::{Decoder, Encoder, SymbolTable};
+    use alloc::vec::Vec;
+    use quickcheck_macros::quickcheck;
+
+    fn reference_encode<const BITS: u32>(input: &[u8]) -> Vec<u8> {
+        let mut encoder = ReferenceEncoder::<BITS>::new();
+        for &b in input {
+            encoder.encode(b as usize);
+        }
+        encoder.encode(EOF_SYMBOL);
+
+        encoder.finish()
+    }
+
+    fn reference_decode<const BITS: u32>(stream: &[u8]) -> Vec<u8> {
+        let mut decoder = ReferenceDecoder::<BITS>::new(stream);
+        let mut output = Vec::new();
+        loop {
+            match decoder.decode() {
+                EOF_SYMBOL => return output,
+                b => output.push(b as u8),
+            }
+        }
+    }
+
+    fn check<const BITS: u32>(input: &[u8]) {
+        let mut fast = Vec::new();
+        let mut encoder = Encoder::<_, BITS>::with_register_bits(&mut fast, SymbolTable::new());
+        encoder.encode_slice(input).unwrap();
+        let settled = encoder.bits_written() as usize / 8;
+        encoder.encode_end().unwrap();
+        drop(encoder);
+        let reference = reference_encode::<BITS>(input);
+
+        assert_eq!(reference_decode::<BITS>(&fast), input);
+        assert_eq!(reference_decode::<BITS>(&reference), input);
+        let mut source = &reference[..];
+        let mut decoder =
+            Decoder::<_, BITS>::with_register_bits(&mut source, SymbolTable::new()).unwrap();
+        assert_eq!(decoder.decode_to_vec().unwrap(), input);
+
+        // Both settle on the same bits along the way, they only end the stream differently.
+        assert_eq!(fast[..settled], reference[..settled]);
+    }
+
+    #[quickcheck]
+    fn coders_agree_with_the_reference(input: Vec<u8>) {
+        check::<16>(&input);
+        check::<32>(&input);
+        check::<64>(&input);
+    }
+
+    #[test]
+    fn coders_agree_with_the_reference_on_skewed_input() {
+        // Long runs of a likely symbol in the middle of the alphabet narrow the interval around the
+        // midpoint, where the underflow handling does its work. Long enough for 16 bit registers to
+        // have the model's counts halved along the way.
+        let input = Xorshift::new()
+            .take(20_000)
+            .map(|r| {
+                if r.is_multiple_of(64) {
+                    (r >> 8) as u8
+                } else {
+                    0x80
+                }
+            })
+            .collect::<Vec<_>>();
+
+        check::<16>(&input);
+        check::<32>(&input);
+        check::<64>(&input);
+    }
+}
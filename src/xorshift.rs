@@ -0,0 +1,30 @@
+//! The pseudo-random numbers behind the tests' generated inputs, so every run sees the same ones.
+
+/// A xorshift64 generator, yielding each new state.
+#[derive(Debug, Clone)]
+pub(crate) struct Xorshift(u64);
+
+impl Xorshift {
+    /// The seed most tests use.
+    pub(crate) fn new() -> Xorshift {
+        Xorshift::with_seed(0x2545F4914F6CDD1D)
+    }
+
+    pub(crate) fn with_seed(seed: u64) -> Xorshift {
+        assert_ne!(seed, 0, "xorshift never leaves a zero state");
+
+        Xorshift(seed)
+    }
+}
+
+impl Iterator for Xorshift {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        Some(self.0)
+    }
+}
@@ -0,0 +1,156 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+fn ae() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_ae"))
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ae-cli-{}-{name}", std::process::id()))
+}
+
+fn sample() -> Vec<u8> {
+    b"the command line tool should round trip this text, "
+        .iter()
+        .copied()
+        .cycle()
+        .take(100_000)
+        .collect()
+}
+
+#[test]
+fn files_round_trip() {
+    let original = temp_path("files.txt");
+    let compressed = temp_path("files.ae");
+    let restored = temp_path("files.out");
+    std::fs::write(&original, sample()).unwrap();
+
+    let status = ae()
+        .arg("-c")
+        .arg(&original)
+        .arg(&compressed)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let status = ae()
+        .arg("-d")
+        .arg(&compressed)
+        .arg(&restored)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    assert!(std::fs::metadata(&compressed).unwrap().len() < sample().len() as u64);
+    assert_eq!(std::fs::read(&restored).unwrap(), sample());
+
+    for path in [original, compressed, restored] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn stdin_and_stdout_round_trip() {
+    let pipe = |mode: &str, input: &[u8]| {
+        let mut child = ae()
+            .args([mode, "-", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+
+    let compressed = pipe("-c", &sample());
+    assert_eq!(pipe("-d", &compressed), sample());
+
+    // `-c` writes whatever the library's streaming compressor does.
+    let mut streamed = Vec::new();
+    ae_rs::compress_stream(&mut &sample()[..], &mut streamed).unwrap();
+    assert_eq!(compressed, streamed);
+}
+
+#[test]
+fn bad_arguments_fail() {
+    let output = ae().arg("-x").output().unwrap();
+    assert!(!output.status.success());
+
+    let missing = temp_path("does-not-exist");
+    let output = ae().arg("-c").arg(&missing).arg("-").output().unwrap();
+    assert!(!output.status.success());
+
+    // An unknown mode leaves the output alone.
+    let existing = temp_path("existing.ae");
+    std::fs::write(&existing, b"keep me").unwrap();
+    let output = ae().arg("-x").arg("-").arg(&existing).output().unwrap();
+    assert!(!output.status.success());
+    assert_eq!(std::fs::read(&existing).unwrap(), b"keep me");
+    std::fs::remove_file(existing).unwrap();
+}
+
+#[test]
+fn reads_the_library_format() {
+    let pipe = |input: &[u8]| {
+        let mut child = ae()
+            .args(["-d", "-", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+
+    // Text gets coded, with whichever model, the alphabet in order is better off stored.
+    let text = sample();
+    let stored = (0..=255u8).collect::<Vec<_>>();
+
+    assert_eq!(pipe(&ae_rs::compress(&text).unwrap()), text);
+    assert_eq!(pipe(&ae_rs::compress(&stored).unwrap()), stored);
+
+    // Every model's stream, each under its own mode byte.
+    let mut modes = Vec::new();
+    for kind in ae_rs::ModelKind::ALL {
+        let compressed = ae_rs::compress_with(&text, kind).unwrap();
+        assert_eq!(pipe(&compressed), text, "{kind:?}");
+        modes.push(compressed[0]);
+    }
+    modes.sort();
+    modes.dedup();
+    assert_eq!(modes.len(), ae_rs::ModelKind::ALL.len());
+}
+
+#[test]
+fn compress_reports_the_entropy() {
+    let original = temp_path("entropy.txt");
+    std::fs::write(&original, b"ab".repeat(1000)).unwrap();
+
+    let output = ae()
+        .arg("-c")
+        .arg(&original)
+        .arg(temp_path("entropy.ae"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // Two equally likely bytes take one bit each.
+    let report = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        report.contains("order-0 entropy 250 bytes, 1.000 bits per byte"),
+        "{report}"
+    );
+
+    for path in [original, temp_path("entropy.ae")] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,37 @@
+//! A round trip through `&[u8]` and `Vec<u8>` alone, the sources and sinks a no_std build has. Run it
+//! against the library built without `std` with:
+//!
+//!     cargo test --no-default-features --features alloc --test no_std
+//!
+//! Without `std` the library's own `io` impls for them are used; with it, the blanket `std::io` ones.
+
+#![no_std]
+
+extern crate alloc;
+
+use ae_rs::{Decoder, Encoder};
+use alloc::vec::Vec;
+
+#[test]
+fn round_trips_through_slice_and_vec() {
+    let input = b"no_std needs nothing more than a slice and a vec";
+
+    let mut output: Vec<u8> = Vec::new();
+    {
+        let mut encoder = Encoder::new(&mut output);
+        encoder.encode_slice(input).unwrap();
+        encoder.encode_end().unwrap();
+    }
+
+    let mut source: &[u8] = &output;
+    let mut decoder = Decoder::new(&mut source).unwrap();
+    assert_eq!(decoder.decode_to_vec().unwrap(), input);
+}
+
+#[test]
+fn compress_round_trips() {
+    let input = b"compressed without std, compressed without std".repeat(4);
+
+    let compressed = ae_rs::compress(&input).unwrap();
+    assert_eq!(ae_rs::decompress(&compressed).unwrap(), input);
+}